@@ -0,0 +1,406 @@
+use std::{fs, net::SocketAddr, path::PathBuf, thread, time::Duration};
+
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use colored::Colorize;
+use lib_device::{
+    decode, encode, parse_ip_range, parse_range, DataType, DiscoveredDevice, Scan, ScanEvent,
+    ScanTarget, WordOrder,
+};
+use tokio_modbus::{client::sync::Context, prelude::*};
+
+// The most registers a single Modbus read can return.
+const MAX_READ: u16 = 125;
+
+/// Reads, writes and watches Modbus devices for field troubleshooting.
+#[derive(Parser)]
+#[command(name = "prussian-modbus", version)]
+struct Cli {
+    #[command(flatten)]
+    connection: Connection,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Args)]
+struct Connection {
+    /// Address of a TCP device, e.g. 192.168.0.10 or 192.168.0.10:502.
+    #[arg(long, conflicts_with = "rtu")]
+    tcp: Option<String>,
+    /// Serial port of an RTU device, e.g. /dev/ttyUSB0 or COM3.
+    #[arg(long)]
+    rtu: Option<String>,
+    #[arg(long, default_value_t = 9600)]
+    baudrate: u32,
+    /// The unit (slave) id.
+    #[arg(long, short, default_value_t = 1)]
+    unit: u8,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Reads values once.
+    Read(ReadArgs),
+    /// Reads values every interval and highlights what changed.
+    Watch {
+        #[command(flatten)]
+        read: ReadArgs,
+        /// Milliseconds between two reads.
+        #[arg(long, short, default_value_t = 1000)]
+        interval: u64,
+    },
+    /// Writes a coil or holding registers.
+    Write {
+        table: Table,
+        address: u16,
+        /// A number, or on/off for coils.
+        value: String,
+        #[command(flatten)]
+        format: Format,
+    },
+    /// Prints a range of registers as hex, decimal and float.
+    Dump {
+        table: Table,
+        start: u16,
+        count: u16,
+    },
+    /// Looks for units on a serial port, or for Modbus TCP devices on an IP range.
+    Discover {
+        /// IP addresses to scan with --unit, e.g. 192.168.0.0/24 or 192.168.0.10-192.168.0.20.
+        #[arg(long, conflicts_with = "serial", required_unless_present = "serial")]
+        ips: Option<String>,
+        /// TCP ports to scan, e.g. 502 or 502-510.
+        #[arg(long, default_value = "502")]
+        ports: String,
+        /// A serial port to scan the unit ids of, at --baudrate.
+        #[arg(long)]
+        serial: Option<String>,
+        /// Unit ids to scan on the serial port.
+        #[arg(long, default_value = "1-247")]
+        units: String,
+        /// Milliseconds a unit gets to answer.
+        #[arg(long, default_value_t = 200)]
+        timeout: u64,
+        /// Saves what was found, the GUI can open it to add the devices.
+        #[arg(long)]
+        save: Option<PathBuf>,
+    },
+}
+
+#[derive(Args)]
+struct ReadArgs {
+    table: Table,
+    address: u16,
+    /// The number of values to read.
+    #[arg(long, short, default_value_t = 1)]
+    count: u16,
+    #[command(flatten)]
+    format: Format,
+}
+
+#[derive(Args)]
+struct Format {
+    /// u16, i16, u32, i32, f32, u64, i64 or f64.
+    #[arg(long = "type", short = 't', default_value = "u16")]
+    data_type: DataType,
+    /// abcd, cdab, badc or dcba.
+    #[arg(long, short, default_value = "abcd")]
+    order: WordOrder,
+}
+
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+enum Table {
+    Coil,
+    Discrete,
+    Holding,
+    Input,
+}
+
+fn main() {
+    let cli = Cli::parse();
+    if let Err(e) = run(cli) {
+        eprintln!("{} {}", "error:".red().bold(), e);
+        std::process::exit(1);
+    }
+}
+
+fn run(cli: Cli) -> anyhow::Result<()> {
+    // Discovery opens its own connections.
+    if let Command::Discover {
+        ips,
+        ports,
+        serial,
+        units,
+        timeout,
+        save,
+    } = cli.command
+    {
+        let target = match (ips, serial) {
+            (Some(ips), _) => ScanTarget::Tcp {
+                addresses: parse_ip_range(&ips)?,
+                ports: parse_range(&ports)?,
+                unit: cli.connection.unit,
+            },
+            (None, Some(com_port)) => ScanTarget::Serial {
+                com_port,
+                baudrate: cli.connection.baudrate,
+                units: parse_range(&units)?,
+            },
+            (None, None) => anyhow::bail!("Either --ips or --serial is needed"),
+        };
+        return discover(target, Duration::from_millis(timeout), save);
+    }
+
+    let mut ctx = connect(&cli.connection)?;
+    match cli.command {
+        Command::Read(args) => {
+            for (address, value) in read(&mut ctx, &args)? {
+                println!("{:>6}  {}", address, value);
+            }
+        }
+        Command::Watch {
+            read: args,
+            interval,
+        } => {
+            let mut previous: Vec<(u16, String)> = Vec::new();
+            loop {
+                let values = read(&mut ctx, &args)?;
+                let time = chrono::Local::now().format("%H:%M:%S%.3f");
+                for (i, (address, value)) in values.iter().enumerate() {
+                    let changed = matches!(previous.get(i), Some((_, last)) if last != value);
+                    let value = match changed {
+                        true => value.yellow().bold(),
+                        false => value.normal(),
+                    };
+                    println!("{}  {:>6}  {}", time.to_string().dimmed(), address, value);
+                }
+                previous = values;
+                thread::sleep(Duration::from_millis(interval));
+            }
+        }
+        Command::Write {
+            table,
+            address,
+            value,
+            format,
+        } => {
+            write(&mut ctx, table, address, &value, &format)?;
+            println!("{} {} written to {}", "ok:".green().bold(), value, address);
+        }
+        Command::Dump {
+            table,
+            start,
+            count,
+        } => dump(&mut ctx, table, start, count)?,
+        Command::Discover { .. } => {}
+    }
+    Ok(())
+}
+
+fn discover(target: ScanTarget, timeout: Duration, save: Option<PathBuf>) -> anyhow::Result<()> {
+    let scan = Scan::start(target, timeout);
+    let mut found: Vec<DiscoveredDevice> = Vec::new();
+    for event in scan.receive.iter() {
+        match event {
+            ScanEvent::Progress { done, total } => eprint!("\rScanned {}/{}", done, total),
+            ScanEvent::Found(device) => {
+                let identification = match &device.identification {
+                    Some(id) => format!("{} {} {}", id.vendor, id.product_code, id.revision),
+                    None => "no identification".dimmed().to_string(),
+                };
+                eprint!("\r");
+                println!("{}  {}", device.to_string().green().bold(), identification);
+                found.push(device);
+            }
+            ScanEvent::Failed(e) => anyhow::bail!(e),
+            ScanEvent::Finished => break,
+        }
+    }
+    eprintln!();
+    println!("{} device(s) found", found.len());
+
+    if let Some(path) = save {
+        let pretty = ron::ser::PrettyConfig::new();
+        fs::write(&path, ron::ser::to_string_pretty(&found, pretty)?)?;
+        println!("Saved to {}", path.display());
+    }
+    Ok(())
+}
+
+fn connect(connection: &Connection) -> anyhow::Result<Context> {
+    let slave = Slave(connection.unit);
+    let ctx = match (&connection.tcp, &connection.rtu) {
+        (Some(address), _) => {
+            let address = match address.contains(':') {
+                true => address.to_owned(),
+                false => format!("{}:502", address),
+            };
+            let socket: SocketAddr = address
+                .parse()
+                .map_err(|e| anyhow::anyhow!("Invalid address {}: {}", address, e))?;
+            sync::tcp::connect_slave(socket, slave)?
+        }
+        (None, Some(path)) => {
+            let builder = tokio_serial::new(path, connection.baudrate);
+            sync::rtu::connect_slave(&builder, slave)?
+        }
+        (None, None) => anyhow::bail!("Either --tcp or --rtu is needed"),
+    };
+    Ok(ctx)
+}
+
+// Fails when `count` values from `address` go past the last address, 65535.
+fn check_range(address: u16, count: u32) -> anyhow::Result<()> {
+    match (address as u32).checked_add(count) {
+        Some(end) if end <= 0x1_0000 => Ok(()),
+        _ => anyhow::bail!(
+            "{} values from address {} go past address 65535",
+            count,
+            address
+        ),
+    }
+}
+
+fn read_bits(
+    ctx: &mut Context,
+    table: Table,
+    address: u16,
+    count: u16,
+) -> anyhow::Result<Vec<bool>> {
+    check_range(address, count as u32)?;
+    let bits = match table {
+        Table::Coil => ctx.read_coils(address, count)?,
+        Table::Discrete => ctx.read_discrete_inputs(address, count)?,
+        _ => anyhow::bail!("Only coils and discrete inputs are bits"),
+    };
+    Ok(bits)
+}
+
+fn read_registers(
+    ctx: &mut Context,
+    table: Table,
+    address: u16,
+    count: u32,
+) -> anyhow::Result<Vec<u16>> {
+    check_range(address, count)?;
+    // Longer ranges are read in as many requests as needed.
+    let mut registers = Vec::new();
+    let mut start = address as u32;
+    let end = start + count;
+    while start < end {
+        let quantity = (end - start).min(MAX_READ as u32) as u16;
+        let mut data = match table {
+            Table::Holding => ctx.read_holding_registers(start as u16, quantity)?,
+            Table::Input => ctx.read_input_registers(start as u16, quantity)?,
+            _ => anyhow::bail!("Only holding and input registers are words"),
+        };
+        registers.append(&mut data);
+        start += quantity as u32;
+    }
+    Ok(registers)
+}
+
+// The address and formatted value of every value read.
+fn read(ctx: &mut Context, args: &ReadArgs) -> anyhow::Result<Vec<(u16, String)>> {
+    if let Table::Coil | Table::Discrete = args.table {
+        let bits = read_bits(ctx, args.table, args.address, args.count)?;
+        let values = bits
+            .into_iter()
+            .enumerate()
+            .map(|(i, bit)| (args.address + i as u16, (bit as u8).to_string()))
+            .collect();
+        return Ok(values);
+    }
+
+    let width = args.format.data_type.registers() as u32;
+    let registers = read_registers(ctx, args.table, args.address, args.count as u32 * width)?;
+    registers
+        .chunks(width as usize)
+        .enumerate()
+        .map(|(i, chunk)| {
+            let value = decode(chunk, args.format.data_type, args.format.order)?;
+            // Within the table, `read_registers` checked the range.
+            let address = args.address as u32 + i as u32 * width;
+            Ok((address as u16, value.to_string()))
+        })
+        .collect()
+}
+
+fn write(
+    ctx: &mut Context,
+    table: Table,
+    address: u16,
+    value: &str,
+    format: &Format,
+) -> anyhow::Result<()> {
+    match table {
+        Table::Coil => {
+            let state = match value.to_lowercase().as_str() {
+                "1" | "on" | "true" => true,
+                "0" | "off" | "false" => false,
+                _ => anyhow::bail!("A coil is on or off, not {}", value),
+            };
+            ctx.write_single_coil(address, state)?;
+        }
+        Table::Holding => {
+            let value: f64 = value
+                .parse()
+                .map_err(|_| anyhow::anyhow!("{} isn't a number", value))?;
+            let registers = encode(value, format.data_type, format.order)?;
+            check_range(address, registers.len() as u32)?;
+            match registers.len() {
+                1 => ctx.write_single_register(address, registers[0])?,
+                _ => ctx.write_multiple_registers(address, &registers)?,
+            }
+        }
+        Table::Discrete | Table::Input => anyhow::bail!("Discrete and input tables are read only"),
+    }
+    Ok(())
+}
+
+fn dump(ctx: &mut Context, table: Table, start: u16, count: u16) -> anyhow::Result<()> {
+    if let Table::Coil | Table::Discrete = table {
+        let bits = read_bits(ctx, table, start, count)?;
+        // Sixteen bits a row, like a register.
+        for (row, bits) in bits.chunks(16).enumerate() {
+            let bits: String = bits
+                .iter()
+                .map(|bit| if *bit { '1' } else { '.' })
+                .collect();
+            println!("{:>6}  {}", start as usize + row * 16, bits);
+        }
+        return Ok(());
+    }
+
+    // One more register so the last row has a float too, when it exists.
+    let registers = match read_registers(ctx, table, start, count as u32 + 1) {
+        Ok(registers) => registers,
+        Err(_) => read_registers(ctx, table, start, count as u32)?,
+    };
+    println!(
+        "{}",
+        format!(
+            "{:>6}  {:>6}  {:>6}  {:>6}  {:>14}  {:>14}",
+            "Addr", "Hex", "u16", "i16", "f32 ABCD", "f32 CDAB"
+        )
+        .bold()
+    );
+    for i in 0..count as usize {
+        let register = registers[i];
+        let float = |order| match registers.get(i..i + 2) {
+            Some(pair) => decode(pair, DataType::F32, order)
+                .map(|value| format!("{:.6}", value))
+                .unwrap_or_default(),
+            None => String::new(),
+        };
+        println!(
+            "{:>6}  {}  {:>6}  {:>6}  {:>14}  {:>14}",
+            start as usize + i,
+            format!("0x{:04X}", register).cyan(),
+            register,
+            register as i16,
+            float(WordOrder::Abcd),
+            float(WordOrder::Cdab)
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,297 @@
+use std::{
+    collections::HashMap,
+    io,
+    sync::{Arc, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    exception_name,
+    transport::{
+        Transport, READ_COILS, READ_HOLDING_REGISTERS, WRITE_MULTIPLE_REGISTERS, WRITE_SINGLE_COIL,
+        WRITE_SINGLE_REGISTER,
+    },
+    ModbusClient, SerialConfig,
+};
+
+// The bus utilisation is measured over windows of this length.
+const UTILISATION_WINDOW: Duration = Duration::from_secs(60);
+
+// The silence that ends an RTU frame: 3.5 characters of 11 bits, fixed at
+// 1.75 ms above 19200 baud as the spec recommends.
+pub fn frame_delay(baudrate: u32) -> Duration {
+    match baudrate {
+        0 => Duration::ZERO,
+        baudrate if baudrate > 19200 => Duration::from_micros(1750),
+        baudrate => Duration::from_micros(38_500_000 / baudrate as u64),
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct BusStats {
+    pub com_port: String,
+    pub baudrate: u32,
+    pub transactions: u64,
+    // Requests that timed out or got an invalid answer.
+    pub errors: u64,
+    // The share of time the line carried a transaction, from 0 to 1.
+    pub utilisation: f32,
+}
+
+struct BusState {
+    com_port: String,
+    baudrate: u32,
+    transport: Option<Transport>,
+    // When the last frame ended, the next one waits for the frame delay.
+    last_frame: Instant,
+    busy: Duration,
+    window_start: Instant,
+    // The utilisation of the last full window, if there was one.
+    utilisation: Option<f32>,
+    transactions: u64,
+    errors: u64,
+}
+
+impl BusState {
+    fn open(&mut self, timeout: Duration) -> anyhow::Result<&mut Transport> {
+        if self.transport.is_none() {
+            self.transport = Some(Transport::rtu(&self.com_port, self.baudrate, timeout)?);
+        }
+        self.transport
+            .as_mut()
+            .ok_or_else(|| anyhow::anyhow!("{} is closed", self.com_port))
+    }
+
+    fn transaction(&mut self, slave: u8, pdu: &[u8], timeout: Duration) -> anyhow::Result<Vec<u8>> {
+        let transport = self.open(timeout)?;
+        transport.set_timeout(timeout)?;
+        let response = transport.request(slave, pdu);
+        // A slave that doesn't answer leaves the port open, an adapter
+        // that was unplugged gets it reopened on the next request.
+        if let Err(e) = &response {
+            if matches!(e.downcast_ref::<io::Error>(), Some(e) if e.kind() != io::ErrorKind::TimedOut)
+            {
+                self.transport = None;
+            }
+        }
+        response
+    }
+
+    fn record(&mut self, busy: Duration, success: bool) {
+        self.transactions += 1;
+        if !success {
+            self.errors += 1;
+        }
+        self.busy += busy;
+        let elapsed = self.window_start.elapsed();
+        if elapsed >= UTILISATION_WINDOW {
+            self.utilisation = Some(self.busy.as_secs_f32() / elapsed.as_secs_f32());
+            self.busy = Duration::ZERO;
+            self.window_start = Instant::now();
+        }
+    }
+
+    fn stats(&self) -> BusStats {
+        let utilisation = self.utilisation.unwrap_or_else(|| {
+            self.busy.as_secs_f32() / self.window_start.elapsed().as_secs_f32().max(f32::EPSILON)
+        });
+        BusStats {
+            com_port: self.com_port.clone(),
+            baudrate: self.baudrate,
+            transactions: self.transactions,
+            errors: self.errors,
+            utilisation: utilisation.min(1.0),
+        }
+    }
+}
+
+// One serial port shared by every device on the line, requests from their
+// workers are sent one at a time.
+#[derive(Clone)]
+pub struct SerialBus {
+    state: Arc<Mutex<BusState>>,
+}
+
+impl SerialBus {
+    fn new(com_port: &str, baudrate: u32) -> Self {
+        let state = BusState {
+            com_port: com_port.to_owned(),
+            baudrate,
+            transport: None,
+            last_frame: Instant::now(),
+            busy: Duration::ZERO,
+            window_start: Instant::now(),
+            utilisation: None,
+            transactions: 0,
+            errors: 0,
+        };
+        Self {
+            state: Arc::new(Mutex::new(state)),
+        }
+    }
+
+    // Sends a request PDU to a slave and returns the response PDU.
+    fn request(&self, slave: u8, pdu: &[u8], timeout: Duration) -> anyhow::Result<Vec<u8>> {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        let silence = state.last_frame.elapsed();
+        let delay = frame_delay(state.baudrate);
+        if silence < delay {
+            thread::sleep(delay - silence);
+        }
+        let start = Instant::now();
+        let response = state.transaction(slave, pdu, timeout);
+        state.record(start.elapsed(), response.is_ok());
+        state.last_frame = Instant::now();
+        response
+    }
+
+    pub fn stats(&self) -> BusStats {
+        self.state.lock().unwrap_or_else(|e| e.into_inner()).stats()
+    }
+}
+
+fn exception_message(code: u8) -> String {
+    format!("Exception {}: {}", code, exception_name(code))
+}
+
+// A slave on a shared bus, what the worker of a serial device polls through.
+pub struct BusClient {
+    bus: SerialBus,
+    slave: u8,
+    timeout: Duration,
+}
+
+impl BusClient {
+    fn call(&mut self, pdu: &[u8]) -> io::Result<Vec<u8>> {
+        let response = self
+            .bus
+            .request(self.slave, pdu, self.timeout)
+            .map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))?;
+        match response.first() {
+            Some(code) if *code == pdu[0] => Ok(response),
+            Some(code) if *code == pdu[0] | 0x80 => Err(io::Error::new(
+                io::ErrorKind::Other,
+                exception_message(response.get(1).copied().unwrap_or_default()),
+            )),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Unexpected response",
+            )),
+        }
+    }
+
+    // The data bytes of a read response, after the function code and byte count.
+    fn read(&mut self, function: u8, address: u16, count: u16) -> io::Result<Vec<u8>> {
+        let mut pdu = vec![function];
+        pdu.extend(address.to_be_bytes());
+        pdu.extend(count.to_be_bytes());
+        let response = self.call(&pdu)?;
+        Ok(response.get(2..).unwrap_or_default().to_vec())
+    }
+
+    fn write(&mut self, function: u8, address: u16, data: &[u8]) -> io::Result<()> {
+        let mut pdu = vec![function];
+        pdu.extend(address.to_be_bytes());
+        pdu.extend(data);
+        self.call(&pdu).map(|_| ())
+    }
+}
+
+impl ModbusClient for BusClient {
+    fn read_holding_registers(&mut self, address: u16, count: u16) -> io::Result<Vec<u16>> {
+        let data = self.read(READ_HOLDING_REGISTERS, address, count)?;
+        if data.len() < count as usize * 2 {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "Short response"));
+        }
+        Ok(data
+            .chunks_exact(2)
+            .take(count as usize)
+            .map(|bytes| u16::from_be_bytes([bytes[0], bytes[1]]))
+            .collect())
+    }
+
+    fn read_coils(&mut self, address: u16, count: u16) -> io::Result<Vec<bool>> {
+        let data = self.read(READ_COILS, address, count)?;
+        if data.len() * 8 < count as usize {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "Short response"));
+        }
+        Ok((0..count as usize)
+            .map(|i| (data[i / 8] >> (i % 8)) & 1 == 1)
+            .collect())
+    }
+
+    fn write_single_register(&mut self, address: u16, value: u16) -> io::Result<()> {
+        self.write(WRITE_SINGLE_REGISTER, address, &value.to_be_bytes())
+    }
+
+    fn write_multiple_registers(&mut self, address: u16, values: &[u16]) -> io::Result<()> {
+        let mut data = (values.len() as u16).to_be_bytes().to_vec();
+        data.push(values.len() as u8 * 2);
+        for value in values {
+            data.extend(value.to_be_bytes());
+        }
+        self.write(WRITE_MULTIPLE_REGISTERS, address, &data)
+    }
+
+    fn write_single_coil(&mut self, address: u16, state: bool) -> io::Result<()> {
+        let value: u16 = match state {
+            true => 0xFF00,
+            false => 0x0000,
+        };
+        self.write(WRITE_SINGLE_COIL, address, &value.to_be_bytes())
+    }
+}
+
+// Owns every serial port the workers poll through, so slaves on the same
+// line share it instead of each opening the port.
+#[derive(Clone, Default)]
+pub struct BusManager {
+    buses: Arc<Mutex<HashMap<String, SerialBus>>>,
+}
+
+impl BusManager {
+    // Opens the port of the device unless another device already did.
+    pub fn client(&self, config: &SerialConfig) -> anyhow::Result<BusClient> {
+        let timeout = Duration::from_millis(config.timeout);
+        let mut buses = self.buses.lock().unwrap_or_else(|e| e.into_inner());
+        let bus = buses
+            .entry(config.com_port.clone())
+            .or_insert_with(|| SerialBus::new(&config.com_port, config.baudrate));
+        {
+            let mut state = bus.state.lock().unwrap_or_else(|e| e.into_inner());
+            // Slaves on one line share its baudrate, the last one configured wins.
+            if state.baudrate != config.baudrate {
+                state.baudrate = config.baudrate;
+                state.transport = None;
+            }
+            state.open(timeout)?;
+        }
+        Ok(BusClient {
+            bus: bus.clone(),
+            slave: config.slave,
+            timeout,
+        })
+    }
+
+    pub fn stats(&self) -> Vec<BusStats> {
+        let buses = self.buses.lock().unwrap_or_else(|e| e.into_inner());
+        let mut stats: Vec<BusStats> = buses.values().map(|bus| bus.stats()).collect();
+        stats.sort_by(|a, b| a.com_port.cmp(&b.com_port));
+        stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::frame_delay;
+
+    #[test]
+    fn frame_delay_test() {
+        // 38.5 bit times at 9600 baud.
+        assert_eq!(frame_delay(9600), Duration::from_micros(4010));
+        assert_eq!(frame_delay(115200), Duration::from_micros(1750));
+    }
+}
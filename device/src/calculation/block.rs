@@ -0,0 +1,286 @@
+use chrono::{Datelike, Local, TimeZone, Timelike};
+use serde::{Deserialize, Serialize};
+use std::{cmp::Ordering, collections::VecDeque, fmt::Display};
+
+// A stateful block applied to the result of a calculation expression, for
+// what needs memory across poll cycles. The state is serialized with the
+// calculation so totals survive a restart.
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq)]
+pub struct FunctionBlock {
+    pub block_type: BlockType,
+    // The window in samples for averages and medians, in seconds for min/max,
+    // the time constant of lag filters, the time base of totalizers
+    // (3600 for a flow per hour) and the rollover value of counters.
+    pub parameter: f32,
+    #[serde(default)]
+    pub reset: ResetSchedule,
+    #[serde(default)]
+    pub state: BlockState,
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq)]
+pub enum BlockType {
+    None,
+    Totalizer,
+    MovingAverage,
+    MovingMedian,
+    WindowMin,
+    WindowMax,
+    Integral,
+    Counter,
+    Lag,
+}
+
+// When a totalizer goes back to zero, in local time.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq)]
+pub enum ResetSchedule {
+    Never,
+    Hourly,
+    Daily,
+    Monthly,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq)]
+pub struct BlockState {
+    pub total: f64,
+    pub last_input: Option<f32>,
+    // Unix timestamps in milliseconds. The time of the last input isn't
+    // saved, so the first cycle after a restart doesn't integrate the outage.
+    #[serde(skip)]
+    pub last_time: Option<i64>,
+    pub last_reset: Option<i64>,
+    pub samples: VecDeque<(i64, f32)>,
+}
+
+impl FunctionBlock {
+    pub fn process(&mut self, input: f32) -> f32 {
+        self.process_at(input, Local::now().timestamp_millis())
+    }
+
+    // Feeds the block a new input at `now` and returns its output.
+    pub fn process_at(&mut self, input: f32, now: i64) -> f32 {
+        let state = &mut self.state;
+        let elapsed = state
+            .last_time
+            .map_or(0.0, |last| (now - last).max(0) as f64 / 1000.0);
+        let output = match self.block_type {
+            BlockType::None => input,
+            BlockType::Totalizer => {
+                if self.reset.is_due(state.last_reset, now) {
+                    state.total = 0.0;
+                    state.last_reset = Some(now);
+                }
+                state.last_reset.get_or_insert(now);
+                let time_base = match self.parameter > 0.0 {
+                    true => self.parameter as f64,
+                    false => 1.0,
+                };
+                state.total += input as f64 * elapsed / time_base;
+                state.total as f32
+            }
+            BlockType::Integral => {
+                // Trapezoidal, so irregular scan times are weighted properly.
+                let last = state.last_input.unwrap_or(input);
+                state.total += (last as f64 + input as f64) / 2.0 * elapsed;
+                state.total as f32
+            }
+            BlockType::MovingAverage | BlockType::MovingMedian => {
+                state.samples.push_back((now, input));
+                while state.samples.len() > (self.parameter.max(1.0) as usize) {
+                    state.samples.pop_front();
+                }
+                let mut values: Vec<f32> = state.samples.iter().map(|(_, value)| *value).collect();
+                match self.block_type {
+                    BlockType::MovingAverage => values.iter().sum::<f32>() / values.len() as f32,
+                    _ => {
+                        values.sort_by(|a, b| a.partial_cmp(b).unwrap_or(Ordering::Equal));
+                        let middle = values.len() / 2;
+                        match values.len() % 2 {
+                            0 => (values[middle - 1] + values[middle]) / 2.0,
+                            _ => values[middle],
+                        }
+                    }
+                }
+            }
+            BlockType::WindowMin | BlockType::WindowMax => {
+                let window = (self.parameter.max(0.0) * 1000.0) as i64;
+                state.samples.push_back((now, input));
+                while let Some((time, _)) = state.samples.front() {
+                    match now - time > window {
+                        true => state.samples.pop_front(),
+                        false => break,
+                    };
+                }
+                let values = state.samples.iter().map(|(_, value)| *value);
+                match self.block_type {
+                    BlockType::WindowMin => values.fold(f32::MAX, f32::min),
+                    _ => values.fold(f32::MIN, f32::max),
+                }
+            }
+            BlockType::Counter => {
+                if let Some(last) = state.last_input {
+                    let mut delta = input - last;
+                    if delta < 0.0 {
+                        // Without a rollover value we assume the counter was reset.
+                        delta = match self.parameter > 0.0 {
+                            true => delta + self.parameter,
+                            false => input,
+                        };
+                    }
+                    state.total += delta as f64;
+                }
+                state.total as f32
+            }
+            BlockType::Lag => {
+                let output = state.total as f32;
+                let output = match (state.last_time, self.parameter > 0.0) {
+                    (Some(_), true) => {
+                        let factor = 1.0 - (-elapsed / self.parameter as f64).exp();
+                        output + (input - output) * factor as f32
+                    }
+                    _ => input,
+                };
+                state.total = output as f64;
+                output
+            }
+        };
+        state.last_input = Some(input);
+        state.last_time = Some(now);
+        output
+    }
+
+    // Clears the state, to be used when the block configuration changes.
+    pub fn reset(&mut self) {
+        self.state = BlockState::default();
+    }
+}
+
+impl ResetSchedule {
+    // Whether the last reset belongs to an earlier hour, day or month than `now`.
+    fn is_due(&self, last_reset: Option<i64>, now: i64) -> bool {
+        let (last, now) = match (
+            last_reset.and_then(|last| Local.timestamp_millis_opt(last).single()),
+            Local.timestamp_millis_opt(now).single(),
+        ) {
+            (Some(last), Some(now)) => (last, now),
+            _ => return false,
+        };
+        match self {
+            ResetSchedule::Never => false,
+            ResetSchedule::Hourly => {
+                (last.year(), last.ordinal(), last.hour())
+                    != (now.year(), now.ordinal(), now.hour())
+            }
+            ResetSchedule::Daily => (last.year(), last.ordinal()) != (now.year(), now.ordinal()),
+            ResetSchedule::Monthly => last.year() != now.year() || last.month() != now.month(),
+        }
+    }
+}
+
+impl Default for BlockType {
+    fn default() -> Self {
+        BlockType::None
+    }
+}
+
+impl Default for ResetSchedule {
+    fn default() -> Self {
+        ResetSchedule::Never
+    }
+}
+
+impl Display for BlockType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let block_type = match self {
+            BlockType::None => "None",
+            BlockType::Totalizer => "Totalizer",
+            BlockType::MovingAverage => "Moving average",
+            BlockType::MovingMedian => "Moving median",
+            BlockType::WindowMin => "Window min",
+            BlockType::WindowMax => "Window max",
+            BlockType::Integral => "Integral",
+            BlockType::Counter => "Counter",
+            BlockType::Lag => "Lag filter",
+        };
+        write!(f, "{}", block_type)
+    }
+}
+
+impl Display for ResetSchedule {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let reset = match self {
+            ResetSchedule::Never => "Never",
+            ResetSchedule::Hourly => "Hourly",
+            ResetSchedule::Daily => "Daily",
+            ResetSchedule::Monthly => "Monthly",
+        };
+        write!(f, "{}", reset)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BlockType, FunctionBlock};
+
+    fn block(block_type: BlockType, parameter: f32) -> FunctionBlock {
+        FunctionBlock {
+            block_type,
+            parameter,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn totalizer_test() {
+        // 360 units per hour for 10 seconds.
+        let mut totalizer = block(BlockType::Totalizer, 3600.0);
+        totalizer.process_at(360.0, 0);
+        assert_eq!(totalizer.process_at(360.0, 10_000), 1.0);
+    }
+
+    #[test]
+    fn moving_median_test() {
+        let mut median = block(BlockType::MovingMedian, 3.0);
+        for (i, value) in [5.0, 1.0, 100.0, 2.0].into_iter().enumerate() {
+            median.process_at(value, i as i64);
+        }
+        assert_eq!(median.process_at(3.0, 4), 3.0);
+    }
+
+    #[test]
+    fn window_max_test() {
+        let mut max = block(BlockType::WindowMax, 1.0);
+        max.process_at(10.0, 0);
+        assert_eq!(max.process_at(2.0, 500), 10.0);
+        assert_eq!(max.process_at(1.0, 1_500), 2.0);
+    }
+
+    #[test]
+    fn counter_rollover_test() {
+        // A 16 bit counter rolling over from 65530 to 4 counted 10.
+        let mut counter = block(BlockType::Counter, 65536.0);
+        counter.process_at(65530.0, 0);
+        assert_eq!(counter.process_at(4.0, 1_000), 10.0);
+    }
+
+    #[test]
+    fn lag_test() {
+        let mut lag = block(BlockType::Lag, 1.0);
+        lag.process_at(0.0, 0);
+        let output = lag.process_at(1.0, 1_000);
+        assert!((output - 0.632).abs() < 0.001);
+    }
+
+    #[test]
+    fn restored_state_test() {
+        let mut totalizer = block(BlockType::Totalizer, 1.0);
+        totalizer.process_at(1.0, 0);
+        totalizer.process_at(1.0, 10_000);
+        let saved = ron::to_string(&totalizer).unwrap();
+
+        // An hour later, the outage isn't added to the total.
+        let mut restored: FunctionBlock = ron::from_str(&saved).unwrap();
+        assert_eq!(restored.process_at(1.0, 3_610_000), 10.0);
+        assert_eq!(restored.process_at(1.0, 3_611_000), 11.0);
+    }
+}
@@ -0,0 +1,196 @@
+use rhai::{Dynamic, Engine, Scope};
+use std::{
+    collections::{HashMap, HashSet},
+    time::Duration,
+};
+
+use crate::{Calculation, Device};
+
+// Calculations are evaluated on every report, and at least this often while
+// polling, so time based blocks keep running when nothing is reported.
+pub const CALCULATION_RATE: Duration = Duration::from_secs(1);
+
+// Evaluates every enabled calculation against the latest device values.
+// Calculations referencing other calculations are evaluated after them,
+// errors are reported in the status of the calculation that failed.
+pub fn evaluate_calculations(
+    engine: &Engine,
+    calculations: &mut Vec<Calculation>,
+    devices: &[Device],
+) {
+    let mut scope = Scope::new();
+    for device in devices {
+        for channel in &device.channels {
+            scope.push(format!("D{}_CH{}", device.id, channel.id), channel.value);
+        }
+    }
+    // Tags are only usable when they are valid identifiers, first one wins.
+    let mut names: HashSet<String> = HashSet::new();
+    for channel in devices.iter().flat_map(|device| device.channels.iter()) {
+        if is_identifier(&channel.tag) && names.insert(channel.tag.clone()) {
+            scope.push(channel.tag.clone(), channel.value);
+        }
+    }
+    for calculation in calculations.iter() {
+        scope.push(format!("EVAL{}", calculation.id), calculation.value);
+        if is_identifier(&calculation.tag) && names.insert(calculation.tag.clone()) {
+            scope.push(calculation.tag.clone(), calculation.value);
+        }
+    }
+
+    let (order, circular) = evaluation_order(calculations);
+    for i in circular {
+        calculations[i].status = "ERROR: Circular reference".to_owned();
+    }
+    for i in order {
+        let calculation = &mut calculations[i];
+        let script = rewrite_references(&calculation.expression);
+        let result = engine
+            .eval_with_scope::<Dynamic>(&mut scope, &script)
+            .map_err(|e| e.to_string())
+            .and_then(|value| to_f32(value));
+        match result {
+            Ok(value) => {
+                let value = calculation.block.process(value);
+                calculation.set_value(value);
+                calculation.status = "OK".to_owned();
+                // So the calculations evaluated next see the new value.
+                scope.set_value(format!("EVAL{}", calculation.id), value);
+                if is_identifier(&calculation.tag) {
+                    scope.set_value(calculation.tag.clone(), value);
+                }
+            }
+            Err(e) => calculation.status = format!("ERROR: {}", e),
+        }
+    }
+}
+
+// The indexes of the enabled calculations, dependencies first,
+// followed by the ones that are part of a dependency cycle.
+fn evaluation_order(calculations: &[Calculation]) -> (Vec<usize>, Vec<usize>) {
+    let enabled: Vec<usize> = (0..calculations.len())
+        .filter(|i| calculations[*i].enabled && !calculations[*i].expression.trim().is_empty())
+        .collect();
+    let mut names: HashMap<String, usize> = HashMap::new();
+    for i in &enabled {
+        let calculation = &calculations[*i];
+        names.insert(format!("EVAL{}", calculation.id), *i);
+        if is_identifier(&calculation.tag) {
+            names.entry(calculation.tag.clone()).or_insert(*i);
+        }
+    }
+    let mut dependencies: HashMap<usize, HashSet<usize>> = HashMap::new();
+    for i in &enabled {
+        let script = rewrite_references(&calculations[*i].expression);
+        let depends_on = identifiers(&script)
+            .filter_map(|identifier| names.get(identifier).copied())
+            .filter(|dependency| dependency != i)
+            .collect();
+        dependencies.insert(*i, depends_on);
+    }
+
+    let mut order = Vec::new();
+    let mut remaining = enabled;
+    loop {
+        let (ready, waiting): (Vec<usize>, Vec<usize>) = remaining.iter().partition(|i| {
+            dependencies[*i]
+                .iter()
+                .all(|dependency| order.contains(dependency))
+        });
+        if ready.is_empty() {
+            return (order, waiting);
+        }
+        order.extend(ready);
+        remaining = waiting;
+    }
+}
+
+// Rhai can't parse D0:CH3, so the references become D0_CH3.
+fn rewrite_references(expression: &str) -> String {
+    let mut script = String::with_capacity(expression.len());
+    let mut rest = expression;
+    while let Some(position) = rest.find(":CH") {
+        let (before, after) = rest.split_at(position);
+        let is_reference = before
+            .rsplit(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+            .next()
+            .map_or(false, |word| {
+                word.len() > 1
+                    && word.starts_with('D')
+                    && word[1..].chars().all(|c| c.is_ascii_digit())
+            })
+            && after[3..].starts_with(|c: char| c.is_ascii_digit());
+        script.push_str(before);
+        script.push_str(match is_reference {
+            true => "_CH",
+            false => ":CH",
+        });
+        rest = &after[3..];
+    }
+    script.push_str(rest);
+    script
+}
+
+fn identifiers(script: &str) -> impl Iterator<Item = &str> {
+    script
+        .split(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+        .filter(|word| is_identifier(word))
+}
+
+fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(first) if first.is_ascii_alphabetic() || first == '_' => {
+            chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+        }
+        _ => false,
+    }
+}
+
+fn to_f32(value: Dynamic) -> Result<f32, String> {
+    if let Ok(value) = value.as_float() {
+        Ok(value)
+    } else if let Ok(value) = value.as_int() {
+        Ok(value as f32)
+    } else if let Ok(value) = value.as_bool() {
+        Ok(value as u8 as f32)
+    } else {
+        Err(format!("Expected a number, got {}", value.type_name()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::evaluate_calculations;
+    use crate::{Calculation, Device};
+    use rhai::Engine;
+
+    #[test]
+    fn calculation_order_test() {
+        let mut device = Device::default();
+        device.channels[3].value = 2.0;
+        device.channels[4].tag = "Flow".to_owned();
+        device.channels[4].value = 10.0;
+        let calculation = |id: usize, tag: &str, expression: &str| Calculation {
+            id,
+            tag: tag.to_owned(),
+            expression: expression.to_owned(),
+            enabled: true,
+            ..Default::default()
+        };
+        // The first calculation depends on the second one.
+        let mut calculations = vec![
+            calculation(0, "Total", "Double + 1"),
+            calculation(1, "Double", "D0:CH3 * Flow"),
+            calculation(2, "", "EVAL3 + 1"),
+            calculation(3, "", "EVAL2 + 1"),
+            calculation(4, "", "CH3 +"),
+        ];
+
+        evaluate_calculations(&Engine::new(), &mut calculations, &[device]);
+        assert_eq!(calculations[1].value, 20.0);
+        assert_eq!(calculations[0].value, 21.0);
+        assert_eq!(calculations[2].status, "ERROR: Circular reference");
+        assert!(calculations[4].status.starts_with("ERROR"));
+    }
+}
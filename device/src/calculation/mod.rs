@@ -1,8 +1,37 @@
 use serde::{Deserialize, Serialize};
 
+mod block;
+mod engine;
+pub use block::*;
+pub use engine::*;
+
+use crate::ChannelAlarm;
+
+// A virtual channel computed from a Rhai expression, referenced as EVAL{id}.
+// Expressions can use channels as D0_CH3 (or D0:CH3), tags and other calculations.
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Default)]
 pub struct Calculation {
     pub id: usize,
     pub value: f32,
     pub tag: String,
+    #[serde(default)]
+    pub expression: String,
+    #[serde(default)]
+    pub enabled: bool,
+    // The result of the last evaluation, either OK or the error.
+    #[serde(default)]
+    pub status: String,
+    // Deviation alarms only make sense for device channels and are ignored here.
+    #[serde(default)]
+    pub alarm: ChannelAlarm,
+    // Applied to the result of the expression, see `FunctionBlock`.
+    #[serde(default)]
+    pub block: FunctionBlock,
+}
+
+impl Calculation {
+    pub fn set_value(&mut self, value: f32) {
+        self.value = value;
+        self.alarm.process(value);
+    }
 }
@@ -1,39 +1,240 @@
 use serde::{Deserialize, Serialize};
-use std::fmt::Display;
+use std::{
+    fmt::Display,
+    time::{Duration, Instant},
+};
+
+use crate::unix_timestamp;
 
 #[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq)]
 pub enum AlarmType {
+    HHigh,
     High,
-    // HHigh,
     Low,
-    // LLow,
+    LLow,
+    RateOfChange,
+    Deviation,
+    Discrete,
+}
+
+// What the deviation alarm of a channel compares its value against.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub enum DeviationSource {
+    None,
+    // A tag, or a channel or calculation in D0:CH3 or EVAL1 notation. A
+    // channel of the same device can also be written CH3.
+    Reference(String),
+    // A fixed value.
+    Setpoint(f32),
 }
 
+// What raises the discrete alarm of a Bool or enumerated channel.
 #[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq)]
+pub enum DiscreteTrigger {
+    OnTrue,
+    OnFalse,
+    OnChange,
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq)]
+pub enum AlarmPriority {
+    Low,
+    Medium,
+    High,
+    Critical,
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq)]
+pub enum AlarmState {
+    Normal,
+    ActiveUnacked,
+    ActiveAcked,
+    ReturnedUnacked,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
 pub struct Alarm {
     pub alarm_type: AlarmType,
     pub active: bool,
     pub enabled: bool,
     pub setpoint: f32,
+    // The value has to come back past the setpoint by this much to clear the alarm.
+    #[serde(default)]
+    pub hysteresis: f32,
+    // Seconds the condition has to hold before the alarm is raised or cleared.
+    #[serde(default)]
+    pub on_delay: u64,
+    #[serde(default)]
+    pub off_delay: u64,
+    #[serde(default)]
+    pub priority: AlarmPriority,
+    #[serde(default)]
+    pub message: String,
+    #[serde(default)]
+    pub state: AlarmState,
+    // A latching alarm needs an acknowledgement after it returns to normal,
+    // even if it was already acknowledged while active.
+    #[serde(default)]
+    pub latching: bool,
+    #[serde(default)]
+    pub acked_by: String,
+    // Unix timestamp of the last acknowledgement.
+    #[serde(default)]
+    pub acked_at: Option<i64>,
+    // Unix timestamp until which the alarm is shelved by an operator.
+    #[serde(default)]
+    pub shelved_until: Option<i64>,
+    #[serde(default)]
+    pub shelved_by: String,
+    // Set while the suppression condition of the channel holds.
+    #[serde(default)]
+    pub suppressed: bool,
+    #[serde(skip)]
+    pending_since: Option<Instant>,
 }
 
 impl Alarm {
+    pub fn new(alarm_type: AlarmType) -> Self {
+        Self {
+            alarm_type,
+            ..Default::default()
+        }
+    }
+
     pub fn process_alarm(&mut self, value: f32) {
+        self.process_alarm_at(value, Instant::now());
+    }
+
+    pub fn process_alarm_at(&mut self, value: f32, now: Instant) {
+        if self
+            .shelved_until
+            .map_or(false, |until| unix_timestamp() >= until)
+        {
+            self.shelved_until = None;
+        }
+        if self.is_inhibited() {
+            self.inhibit();
+            return;
+        }
+        let condition = self.condition(value);
+        if condition == self.active {
+            self.pending_since = None;
+            return;
+        }
+        let delay = match condition {
+            true => self.on_delay,
+            false => self.off_delay,
+        };
+        let since = *self.pending_since.get_or_insert(now);
+        if now.duration_since(since) >= Duration::from_secs(delay) {
+            self.set_active(condition);
+            self.pending_since = None;
+        }
+    }
+
+    fn set_active(&mut self, active: bool) {
+        self.active = active;
+        self.state = match (active, self.state) {
+            (true, AlarmState::ActiveAcked) => AlarmState::ActiveAcked,
+            (true, _) => AlarmState::ActiveUnacked,
+            (false, AlarmState::ActiveAcked) if !self.latching => AlarmState::Normal,
+            (false, AlarmState::Normal) => AlarmState::Normal,
+            (false, _) => AlarmState::ReturnedUnacked,
+        };
+    }
+
+    // Returns false if there was nothing to acknowledge.
+    pub fn acknowledge(&mut self, user: &str) -> bool {
+        let state = match self.state {
+            AlarmState::ActiveUnacked => AlarmState::ActiveAcked,
+            AlarmState::ReturnedUnacked => AlarmState::Normal,
+            _ => return false,
+        };
+        self.state = state;
+        self.acked_by = user.to_owned();
+        self.acked_at = Some(unix_timestamp());
+        true
+    }
+
+    // Shelves the alarm for a number of seconds, or unshelves it with 0.
+    pub fn shelve(&mut self, duration: u64, user: &str) {
+        self.shelved_until = match duration {
+            0 => None,
+            _ => Some(unix_timestamp() + duration as i64),
+        };
+        self.shelved_by = user.to_owned();
+        if self.is_inhibited() {
+            self.inhibit();
+        }
+    }
+
+    pub fn is_shelved(&self) -> bool {
+        self.shelved_until.is_some()
+    }
+
+    // A shelved or suppressed alarm is neither raised nor shown.
+    pub fn is_inhibited(&self) -> bool {
+        self.is_shelved() || self.suppressed
+    }
+
+    // Takes the alarm out of annunciation without asking for an acknowledgement.
+    pub fn inhibit(&mut self) {
+        self.active = false;
+        self.state = AlarmState::Normal;
+        self.pending_since = None;
+    }
+
+    pub fn is_unacked(&self) -> bool {
+        matches!(
+            self.state,
+            AlarmState::ActiveUnacked | AlarmState::ReturnedUnacked
+        )
+    }
+
+    // Whether the value is in alarm, taking the hysteresis band into account
+    // once the alarm is already active.
+    fn condition(&self, value: f32) -> bool {
+        let hysteresis = match self.active {
+            true => self.hysteresis.abs(),
+            false => 0.0,
+        };
         match self.alarm_type {
-            AlarmType::Low => {
-                if value < self.setpoint {
-                    self.active = true;
-                } else {
-                    self.active = false;
-                }
-            }
-            AlarmType::High => {
-                if value > self.setpoint {
-                    self.active = true;
-                } else {
-                    self.active = false;
-                }
+            AlarmType::HHigh | AlarmType::High | AlarmType::RateOfChange | AlarmType::Deviation => {
+                value > self.setpoint - hysteresis
             }
+            AlarmType::Low | AlarmType::LLow => value < self.setpoint + hysteresis,
+            // The trigger is evaluated by the channel, see `ChannelAlarm::discrete_condition`.
+            AlarmType::Discrete => value != 0.0,
+        }
+    }
+
+    // The text shown to operators when the alarm is active.
+    pub fn text(&self) -> String {
+        if self.message.is_empty() {
+            format!("{} ALARM", self.alarm_type).to_uppercase()
+        } else {
+            self.message.clone()
+        }
+    }
+}
+
+impl AlarmPriority {
+    // A numeric level used to order and store priorities, Low being 0.
+    pub fn level(&self) -> i64 {
+        match self {
+            AlarmPriority::Low => 0,
+            AlarmPriority::Medium => 1,
+            AlarmPriority::High => 2,
+            AlarmPriority::Critical => 3,
+        }
+    }
+    pub fn from_level(level: i64) -> Option<Self> {
+        match level {
+            0 => Some(AlarmPriority::Low),
+            1 => Some(AlarmPriority::Medium),
+            2 => Some(AlarmPriority::High),
+            3 => Some(AlarmPriority::Critical),
+            _ => None,
         }
     }
 }
@@ -43,13 +244,85 @@ impl Display for AlarmType {
         let alarm_type = match self {
             AlarmType::Low => "Low",
             AlarmType::High => "High",
-            // AlarmType::LLow => "LLow",
-            // AlarmType::HHigh => "HHigh",
+            AlarmType::LLow => "LLow",
+            AlarmType::HHigh => "HHigh",
+            AlarmType::RateOfChange => "ROC",
+            AlarmType::Deviation => "Deviation",
+            AlarmType::Discrete => "Discrete",
         };
         write!(f, "{}", alarm_type)
     }
 }
 
+impl Display for DiscreteTrigger {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let trigger = match self {
+            DiscreteTrigger::OnTrue => "On true",
+            DiscreteTrigger::OnFalse => "On false",
+            DiscreteTrigger::OnChange => "On change",
+        };
+        write!(f, "{}", trigger)
+    }
+}
+
+impl Display for AlarmPriority {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let priority = match self {
+            AlarmPriority::Low => "Low",
+            AlarmPriority::Medium => "Medium",
+            AlarmPriority::High => "High",
+            AlarmPriority::Critical => "Critical",
+        };
+        write!(f, "{}", priority)
+    }
+}
+
+impl Display for AlarmState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let state = match self {
+            AlarmState::Normal => "Normal",
+            AlarmState::ActiveUnacked => "Active/Unacked",
+            AlarmState::ActiveAcked => "Active/Acked",
+            AlarmState::ReturnedUnacked => "Returned/Unacked",
+        };
+        write!(f, "{}", state)
+    }
+}
+
+impl Default for AlarmState {
+    fn default() -> Self {
+        AlarmState::Normal
+    }
+}
+
+impl Default for DeviationSource {
+    fn default() -> Self {
+        DeviationSource::None
+    }
+}
+
+impl Display for DeviationSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DeviationSource::None => write!(f, "None"),
+            DeviationSource::Reference(reference) => write!(f, "{}", reference),
+            DeviationSource::Setpoint(setpoint) => write!(f, "{}", setpoint),
+        }
+    }
+}
+
+impl Default for DiscreteTrigger {
+    fn default() -> Self {
+        DiscreteTrigger::OnTrue
+    }
+}
+
+impl Default for AlarmPriority {
+    fn default() -> Self {
+        AlarmPriority::Medium
+    }
+}
+
 impl Default for Alarm {
     fn default() -> Self {
         Self {
@@ -57,6 +330,73 @@ impl Default for Alarm {
             active: false,
             enabled: false,
             setpoint: 0.0,
+            hysteresis: 0.0,
+            on_delay: 0,
+            off_delay: 0,
+            priority: AlarmPriority::default(),
+            message: String::new(),
+            state: AlarmState::default(),
+            latching: false,
+            acked_by: String::new(),
+            acked_at: None,
+            shelved_until: None,
+            shelved_by: String::new(),
+            suppressed: false,
+            pending_since: None,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{Alarm, AlarmState, AlarmType};
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn hysteresis_and_delays_test() {
+        let mut alarm = Alarm {
+            enabled: true,
+            setpoint: 100.0,
+            hysteresis: 5.0,
+            on_delay: 2,
+            ..Alarm::new(AlarmType::High)
+        };
+        let start = Instant::now();
+
+        // The alarm waits for the on delay before being raised.
+        alarm.process_alarm_at(101.0, start);
+        assert!(!alarm.active);
+        alarm.process_alarm_at(101.0, start + Duration::from_secs(2));
+        assert!(alarm.active);
+
+        // It stays active inside the hysteresis band.
+        alarm.process_alarm_at(97.0, start + Duration::from_secs(3));
+        assert!(alarm.active);
+        alarm.process_alarm_at(94.0, start + Duration::from_secs(4));
+        assert!(!alarm.active);
+    }
+
+    #[test]
+    fn acknowledgement_test() {
+        let mut alarm = Alarm {
+            enabled: true,
+            setpoint: 10.0,
+            latching: true,
+            ..Alarm::new(AlarmType::Low)
+        };
+        let now = Instant::now();
+
+        alarm.process_alarm_at(5.0, now);
+        assert_eq!(alarm.state, AlarmState::ActiveUnacked);
+        assert!(alarm.acknowledge("operator"));
+        assert_eq!(alarm.state, AlarmState::ActiveAcked);
+        assert_eq!(alarm.acked_by, "operator");
+
+        // A latched alarm has to be acknowledged again once it returns.
+        alarm.process_alarm_at(15.0, now);
+        assert_eq!(alarm.state, AlarmState::ReturnedUnacked);
+        assert!(alarm.acknowledge("operator"));
+        assert_eq!(alarm.state, AlarmState::Normal);
+        assert!(!alarm.acknowledge("operator"));
+    }
+}
@@ -0,0 +1,80 @@
+use serde::{Deserialize, Serialize};
+use std::fmt::Display;
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq)]
+pub enum DeadbandType {
+    Disabled,
+    Absolute,
+    Percent,
+}
+
+// A deadband decides whether a new value is different enough from the last
+// published one to be reported. A disabled deadband reports any change.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq)]
+pub struct Deadband {
+    pub deadband_type: DeadbandType,
+    pub value: f32,
+}
+
+impl Deadband {
+    pub fn exceeded(&self, last: f32, value: f32) -> bool {
+        let delta = (value - last).abs();
+        match self.deadband_type {
+            DeadbandType::Disabled => value != last,
+            DeadbandType::Absolute => delta > self.value,
+            // A percentage of zero is zero, so any move away from it is reported.
+            DeadbandType::Percent if last == 0.0 => value != 0.0,
+            // The percentage is taken from the last published value.
+            DeadbandType::Percent => delta > last.abs() * self.value / 100.0,
+        }
+    }
+}
+
+impl Display for DeadbandType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let deadband_type = match self {
+            DeadbandType::Disabled => "Disabled",
+            DeadbandType::Absolute => "Absolute",
+            DeadbandType::Percent => "Percent",
+        };
+        write!(f, "{}", deadband_type)
+    }
+}
+
+impl Default for Deadband {
+    fn default() -> Self {
+        Self {
+            deadband_type: DeadbandType::Disabled,
+            value: 0.0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Deadband, DeadbandType};
+
+    #[test]
+    fn absolute_deadband_test() {
+        let deadband = Deadband {
+            deadband_type: DeadbandType::Absolute,
+            value: 1.0,
+        };
+        assert!(!deadband.exceeded(10.0, 10.5));
+        assert!(deadband.exceeded(10.0, 11.5));
+        assert!(deadband.exceeded(10.0, 8.5));
+    }
+
+    #[test]
+    fn percent_deadband_test() {
+        let deadband = Deadband {
+            deadband_type: DeadbandType::Percent,
+            value: 10.0,
+        };
+        assert!(!deadband.exceeded(100.0, 109.0));
+        assert!(deadband.exceeded(-100.0, -111.0));
+        // There's no band around zero, only leaving it counts.
+        assert!(!deadband.exceeded(0.0, 0.0));
+        assert!(deadband.exceeded(0.0, 0.001));
+    }
+}
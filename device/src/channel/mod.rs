@@ -1,11 +1,26 @@
 // use std::error::Error;
 
-use std::fmt::Display;
+use std::{
+    collections::VecDeque,
+    fmt::Display,
+    time::{Duration, Instant},
+};
 
 mod alarm;
-use alarm::*;
+mod deadband;
+mod scaling;
+mod table;
+mod write;
+mod write_limits;
+pub use alarm::*;
+pub use deadband::*;
+pub use scaling::*;
 use serde::{Deserialize, Serialize};
-use tokio_modbus::prelude::{sync::Context, *};
+pub use table::*;
+pub use write::*;
+pub use write_limits::*;
+
+use crate::{decode, DataType, ModbusClient, WordOrder, DEFAULT_HISTORY_DEPTH};
 
 //use crate::LoggerChannel;
 
@@ -21,10 +36,213 @@ pub enum AccessType {
     Write,
 }
 
-#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq)]
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
 pub struct ChannelAlarm {
+    #[serde(default = "default_hhigh")]
+    pub hhigh: Alarm,
     pub high: Alarm,
     pub low: Alarm,
+    #[serde(default = "default_llow")]
+    pub llow: Alarm,
+    // Its setpoint is the maximum rate in units per second.
+    #[serde(default = "default_rate_of_change")]
+    pub rate_of_change: Alarm,
+    // Seconds over which the rate of change is measured.
+    #[serde(default)]
+    pub rate_window: u64,
+    // Its setpoint is the maximum absolute deviation from the reference.
+    #[serde(default = "default_deviation")]
+    pub deviation: Alarm,
+    #[serde(default)]
+    pub deviation_source: DeviationSource,
+    // Up to project version 1, the id of the reference channel on the same
+    // device. It's moved to `deviation_source` when the project is loaded.
+    #[serde(default, skip_serializing)]
+    pub deviation_reference: Option<usize>,
+    // For Bool and enumerated channels.
+    #[serde(default = "default_discrete")]
+    pub discrete: Alarm,
+    #[serde(default)]
+    pub discrete_trigger: DiscreteTrigger,
+    // The alarms of the channel are suppressed while this condition holds,
+    // e.g. `Pump1 == 0`. Empty means never, see `Condition`.
+    #[serde(default)]
+    pub suppress_when: String,
+    #[serde(skip)]
+    samples: VecDeque<(Instant, f32)>,
+    #[serde(skip)]
+    last_discrete: Option<f32>,
+}
+
+fn default_hhigh() -> Alarm {
+    Alarm::new(AlarmType::HHigh)
+}
+
+fn default_llow() -> Alarm {
+    Alarm::new(AlarmType::LLow)
+}
+
+fn default_rate_of_change() -> Alarm {
+    Alarm::new(AlarmType::RateOfChange)
+}
+
+fn default_deviation() -> Alarm {
+    Alarm::new(AlarmType::Deviation)
+}
+
+fn default_discrete() -> Alarm {
+    Alarm::new(AlarmType::Discrete)
+}
+
+impl ChannelAlarm {
+    pub fn alarms(&self) -> [&Alarm; 7] {
+        [
+            &self.hhigh,
+            &self.high,
+            &self.low,
+            &self.llow,
+            &self.rate_of_change,
+            &self.deviation,
+            &self.discrete,
+        ]
+    }
+    pub fn alarms_mut(&mut self) -> [&mut Alarm; 7] {
+        [
+            &mut self.hhigh,
+            &mut self.high,
+            &mut self.low,
+            &mut self.llow,
+            &mut self.rate_of_change,
+            &mut self.deviation,
+            &mut self.discrete,
+        ]
+    }
+    // The texts of all the alarms that are not back to normal, most severe limit first.
+    pub fn active_text(&self) -> String {
+        self.alarms()
+            .iter()
+            .filter(|alarm| alarm.enabled && alarm.state != AlarmState::Normal)
+            .map(|alarm| alarm.text())
+            .collect::<Vec<String>>()
+            .join("/")
+    }
+    pub fn states(&self) -> Vec<AlarmState> {
+        self.alarms().iter().map(|alarm| alarm.state).collect()
+    }
+    // "Shelved" or "Suppressed" when any enabled alarm is inhibited.
+    pub fn inhibited_text(&self) -> String {
+        let enabled = self.alarms().into_iter().filter(|alarm| alarm.enabled);
+        let mut texts = Vec::new();
+        if enabled.clone().any(|alarm| alarm.is_shelved()) {
+            texts.push("Shelved");
+        }
+        if enabled.clone().any(|alarm| alarm.suppressed) {
+            texts.push("Suppressed");
+        }
+        texts.join("/")
+    }
+    pub fn shelve(&mut self, alarm_type: Option<AlarmType>, duration: u64, user: &str) {
+        for alarm in self.alarms_mut() {
+            if alarm_type.map_or(true, |alarm_type| alarm.alarm_type == alarm_type) {
+                alarm.shelve(duration, user);
+            }
+        }
+    }
+    pub fn is_unacked(&self) -> bool {
+        self.alarms()
+            .iter()
+            .any(|alarm| alarm.enabled && alarm.is_unacked())
+    }
+    // Acknowledges one alarm type, or all of them when none is given.
+    pub fn acknowledge(&mut self, alarm_type: Option<AlarmType>, user: &str) -> bool {
+        let mut acknowledged = false;
+        for alarm in self.alarms_mut() {
+            if alarm_type.map_or(true, |alarm_type| alarm.alarm_type == alarm_type) {
+                acknowledged |= alarm.acknowledge(user);
+            }
+        }
+        acknowledged
+    }
+
+    // Whether the discrete alarm condition holds, any non zero value being true.
+    pub fn discrete_condition(&mut self, value: f32) -> bool {
+        let condition = match self.discrete_trigger {
+            DiscreteTrigger::OnTrue => value != 0.0,
+            DiscreteTrigger::OnFalse => value == 0.0,
+            DiscreteTrigger::OnChange => self.last_discrete.map_or(false, |last| last != value),
+        };
+        self.last_discrete = Some(value);
+        condition
+    }
+
+    // Processes every alarm but the deviation one against a new value.
+    pub fn process(&mut self, value: f32) {
+        for limit in [
+            &mut self.hhigh,
+            &mut self.high,
+            &mut self.low,
+            &mut self.llow,
+        ] {
+            if limit.enabled {
+                limit.process_alarm(value);
+            }
+        }
+        if self.rate_of_change.enabled {
+            let rate = self.rate_of_change(value, Instant::now());
+            self.rate_of_change.process_alarm(rate);
+        }
+        if self.discrete.enabled {
+            let condition = self.discrete_condition(value);
+            self.discrete.process_alarm(condition as u8 as f32);
+        }
+    }
+
+    // Records a new sample and returns the rate of change in units per
+    // second against the oldest sample still inside the window.
+    pub fn rate_of_change(&mut self, value: f32, now: Instant) -> f32 {
+        let window = Duration::from_secs(self.rate_window);
+        while self.samples.len() > 1 {
+            match self.samples.get(1) {
+                Some((time, _)) if now.duration_since(*time) >= window => {
+                    self.samples.pop_front();
+                }
+                _ => break,
+            }
+        }
+        let rate = match self.samples.front() {
+            Some((time, oldest)) => {
+                let elapsed = now.duration_since(*time).as_secs_f32();
+                match elapsed > 0.0 {
+                    true => (value - oldest).abs() / elapsed,
+                    false => 0.0,
+                }
+            }
+            None => 0.0,
+        };
+        self.samples.push_back((now, value));
+        rate
+    }
+}
+
+impl Default for ChannelAlarm {
+    fn default() -> Self {
+        Self {
+            hhigh: Alarm::new(AlarmType::HHigh),
+            high: Alarm::new(AlarmType::High),
+            low: Alarm::new(AlarmType::Low),
+            llow: Alarm::new(AlarmType::LLow),
+            rate_of_change: Alarm::new(AlarmType::RateOfChange),
+            rate_window: 0,
+            deviation: Alarm::new(AlarmType::Deviation),
+            deviation_source: DeviationSource::None,
+            deviation_reference: None,
+            discrete: Alarm::new(AlarmType::Discrete),
+            discrete_trigger: DiscreteTrigger::default(),
+            suppress_when: String::new(),
+            samples: VecDeque::new(),
+            last_discrete: None,
+        }
+    }
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
@@ -40,6 +258,36 @@ pub struct Channel {
     pub status: String,
     pub alarm: ChannelAlarm,
     pub enabled: bool,
+    #[serde(default)]
+    pub deadband: Deadband,
+    // Labels for Bool and enumerated values, e.g. 1 => "Running".
+    #[serde(default)]
+    pub state_labels: Vec<StateLabel>,
+    // The label of the current value, empty when there is none.
+    #[serde(default)]
+    pub state_text: String,
+    #[serde(default)]
+    pub description: String,
+    #[serde(default)]
+    pub scaling: Scaling,
+    // Read the value back after each write and report a mismatch.
+    #[serde(default)]
+    pub verify_writes: bool,
+    #[serde(default)]
+    pub write_limits: WriteLimits,
+    // Samples kept in memory for trends, 0 keeps none.
+    #[serde(default = "default_history_depth")]
+    pub history_depth: usize,
+}
+
+fn default_history_depth() -> usize {
+    DEFAULT_HISTORY_DEPTH
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq)]
+pub struct StateLabel {
+    pub value: i32,
+    pub label: String,
 }
 
 impl Channel {
@@ -68,92 +316,69 @@ impl Channel {
             status,
             alarm,
             enabled,
+            deadband: Deadband::default(),
+            state_labels: Vec::new(),
+            state_text: String::new(),
+            description: String::new(),
+            scaling: Scaling::default(),
+            verify_writes: false,
+            write_limits: WriteLimits::default(),
+            history_depth: DEFAULT_HISTORY_DEPTH,
         }
     }
-    pub fn read_value(&mut self, ctx: &mut Context) {
+    pub fn read_value(&mut self, ctx: &mut dyn ModbusClient) {
         match self.value_type {
             ValueType::Int16 => {
                 if let Ok(value) = ctx.read_holding_registers(self.index, 1) {
-                    self.value = value[0] as f32;
-
-                    self.process_alarms(self.value);
+                    self.set_raw_value(value[0] as f32);
                 }
             }
             ValueType::Real32 => {
                 if let Ok(data) = ctx.read_holding_registers(self.index, 2) {
-                    let data_32bit_rep = ((data[0] as u32) << 16) | data[1] as u32;
-                    let data_32_array = data_32bit_rep.to_ne_bytes();
-                    self.value = f32::from_ne_bytes(data_32_array);
-
-                    self.process_alarms(self.value);
+                    if let Ok(value) = decode(&data, DataType::F32, WordOrder::Abcd) {
+                        self.set_raw_value(value as f32);
+                    }
                 }
             }
+            // A coil that is on reads 1.0, as it is written. In version 0
+            // projects it read 0.0, alarms and calculations written against
+            // the inverted value have to be updated, see the README.
             ValueType::BoolType => {
                 if let Ok(states) = ctx.read_coils(self.index, 1) {
-                    self.value = match states[0] {
-                        true => 0.0,
-                        false => 1.0,
+                    let value = match states[0] {
+                        true => 1.0,
+                        false => 0.0,
                     };
+                    self.set_value(value);
                 }
             }
         }
     }
-    pub fn write_value(&mut self, ctx: &mut Context) {
-        match self.value_type {
-            ValueType::Int16 => {
-                let value = self.value as u16;
 
-                match ctx.write_single_register(self.index, value) {
-                    Ok(_) => {
-                        self.status = "Value written successfully!".to_owned();
-                    }
-                    Err(e) => {
-                        self.status = format!("ERROR!: {}", e);
-                    }
-                }
-            }
-            ValueType::Real32 => {
-                let value = self.value as u16;
+    // Deviation alarms need the value of their reference,
+    // they are processed in `Device::process_deviation_alarms`.
+    pub fn process_alarms(&mut self, value: f32) {
+        self.alarm.process(value);
+    }
 
-                match ctx.write_single_register(self.index, value) {
-                    Ok(_) => {
-                        self.status = "Value written successfully!".to_owned();
-                    }
-                    Err(e) => {
-                        self.status = format!("ERROR!: {}", e);
-                    }
-                }
-            }
-            ValueType::BoolType => {
-                let value = self.value as u16;
-                match value {
-                    1 => {
-                        if let Ok(_) = ctx.write_single_coil(self.index, true) {
-                            self.status = "Value written successfully!".to_owned();
-                        } else {
-                            self.status = "Couldn't write value".to_owned();
-                        }
-                    }
-                    0 => {
-                        if let Ok(_) = ctx.write_single_coil(self.index, false) {
-                            self.status = "Value written successfully!".to_owned();
-                        } else {
-                            self.status = "Couldn't write value".to_owned();
-                        }
-                    }
-                    _ => self.status = "Only bit values are allowed!.".to_owned(),
-                }
-            }
-        }
+    // Every value read from the device goes through here
+    // so the state text and the alarms follow it.
+    pub fn set_value(&mut self, value: f32) {
+        self.value = value;
+        self.state_text = self.state_label(value).unwrap_or_default();
+        self.process_alarms(value);
     }
 
-    pub fn process_alarms(&mut self, value: f32) {
-        if self.alarm.low.enabled {
-            self.alarm.low.process_alarm(value);
-        }
-        if self.alarm.high.enabled {
-            self.alarm.high.process_alarm(value);
-        }
+    // For register values, which are scaled to engineering units first.
+    pub fn set_raw_value(&mut self, raw: f32) {
+        self.set_value(self.scaling.apply(raw));
+    }
+
+    pub fn state_label(&self, value: f32) -> Option<String> {
+        self.state_labels
+            .iter()
+            .find(|state| state.value == value.round() as i32)
+            .map(|state| state.label.clone())
     }
 }
 
@@ -170,20 +395,15 @@ impl Default for Channel {
             index: 0,
             status: "Initialized".to_owned(),
             enabled: false,
-            alarm: ChannelAlarm {
-                high: Alarm {
-                    alarm_type: AlarmType::High,
-                    active: false,
-                    enabled: false,
-                    setpoint: 0.0,
-                },
-                low: Alarm {
-                    alarm_type: AlarmType::Low,
-                    active: false,
-                    enabled: false,
-                    setpoint: 0.0,
-                },
-            },
+            deadband: Deadband::default(),
+            state_labels: Vec::new(),
+            state_text: String::new(),
+            description: String::new(),
+            scaling: Scaling::default(),
+            verify_writes: false,
+            write_limits: WriteLimits::default(),
+            history_depth: DEFAULT_HISTORY_DEPTH,
+            alarm: ChannelAlarm::default(),
         }
     }
 }
@@ -0,0 +1,31 @@
+use serde::{Deserialize, Serialize};
+
+// Linear scaling from the raw register value to engineering units.
+// Bool channels are never scaled.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq)]
+pub struct Scaling {
+    pub gain: f32,
+    pub offset: f32,
+}
+
+impl Scaling {
+    pub fn apply(&self, raw: f32) -> f32 {
+        raw * self.gain + self.offset
+    }
+    // The raw value to write for a value in engineering units.
+    pub fn raw(&self, value: f32) -> f32 {
+        match self.gain != 0.0 {
+            true => (value - self.offset) / self.gain,
+            false => value,
+        }
+    }
+}
+
+impl Default for Scaling {
+    fn default() -> Self {
+        Self {
+            gain: 1.0,
+            offset: 0.0,
+        }
+    }
+}
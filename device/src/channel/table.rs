@@ -0,0 +1,465 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Display,
+    io::{Read, Write},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{AccessType, Alarm, Channel, TagAddress, TagDatabase, ValueType};
+
+// One row of a channel table. Alarm columns hold the setpoint of the alarm,
+// an empty cell means the alarm is disabled.
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq)]
+struct ChannelRow {
+    #[serde(default)]
+    id: String,
+    tag: String,
+    #[serde(default)]
+    description: String,
+    #[serde(default)]
+    enabled: String,
+    address: String,
+    value_type: String,
+    access_type: String,
+    #[serde(default)]
+    gain: String,
+    #[serde(default)]
+    offset: String,
+    #[serde(default)]
+    hhigh: String,
+    #[serde(default)]
+    high: String,
+    #[serde(default)]
+    low: String,
+    #[serde(default)]
+    llow: String,
+}
+
+const REQUIRED_COLUMNS: [&str; 4] = ["tag", "address", "value_type", "access_type"];
+
+// The outcome of reading a channel table, to be previewed before it's applied.
+#[derive(Clone, Debug, Default)]
+pub struct ChannelImport {
+    // In the order of the file. Channels that already existed keep their id,
+    // new ones get ids past every existing one, see `Device::replace_channels`.
+    pub channels: Vec<Channel>,
+    pub changes: Vec<ChannelChange>,
+    pub errors: Vec<RowError>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ChannelChange {
+    Added(String),
+    // The channel and what changed, e.g. "address: 4 -> 5".
+    Modified(String, Vec<String>),
+    Removed(String),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct RowError {
+    // The line in the file, the header being line 1.
+    pub line: u64,
+    pub message: String,
+}
+
+impl ChannelRow {
+    fn from_channel(channel: &Channel) -> Self {
+        let setpoint = |alarm: &Alarm| match alarm.enabled {
+            true => alarm.setpoint.to_string(),
+            false => String::new(),
+        };
+        Self {
+            id: channel.id.to_string(),
+            tag: channel.tag.clone(),
+            description: channel.description.clone(),
+            enabled: channel.enabled.to_string(),
+            address: channel.index.to_string(),
+            value_type: channel.value_type.to_string(),
+            access_type: channel.access_type.to_string(),
+            gain: channel.scaling.gain.to_string(),
+            offset: channel.scaling.offset.to_string(),
+            hhigh: setpoint(&channel.alarm.hhigh),
+            high: setpoint(&channel.alarm.high),
+            low: setpoint(&channel.alarm.low),
+            llow: setpoint(&channel.alarm.llow),
+        }
+    }
+
+    fn cells(&self) -> [(&'static str, &str); 12] {
+        [
+            ("tag", &self.tag),
+            ("description", &self.description),
+            ("enabled", &self.enabled),
+            ("address", &self.address),
+            ("value_type", &self.value_type),
+            ("access_type", &self.access_type),
+            ("gain", &self.gain),
+            ("offset", &self.offset),
+            ("hhigh", &self.hhigh),
+            ("high", &self.high),
+            ("low", &self.low),
+            ("llow", &self.llow),
+        ]
+    }
+
+    // Applies the row on top of `channel`. Optional columns missing from the
+    // file keep what's there, as do empty enabled, gain and offset cells.
+    fn apply(&self, channel: &mut Channel, columns: &HashSet<String>) -> Result<(), Vec<String>> {
+        let mut errors = Vec::new();
+        let present = |column: &str| columns.contains(column);
+        channel.tag = self.tag.trim().to_owned();
+        if present("description") {
+            channel.description = self.description.trim().to_owned();
+        }
+        if !self.enabled.trim().is_empty() {
+            match parse_bool(&self.enabled) {
+                Some(enabled) => channel.enabled = enabled,
+                None => errors.push(format!(
+                    "enabled: expected true or false, got {}",
+                    self.enabled
+                )),
+            }
+        }
+        match self.address.trim().parse::<u16>() {
+            Ok(address) => channel.index = address,
+            Err(_) => errors.push(format!(
+                "address: expected 0 to 65535, got {}",
+                self.address
+            )),
+        }
+        match parse_value_type(&self.value_type) {
+            Some(value_type) => channel.value_type = value_type,
+            None => errors.push(format!(
+                "value_type: expected Int, Real or Bool, got {}",
+                self.value_type
+            )),
+        }
+        match parse_access_type(&self.access_type) {
+            Some(access_type) => channel.access_type = access_type,
+            None => errors.push(format!(
+                "access_type: expected Read or Write, got {}",
+                self.access_type
+            )),
+        }
+        for (name, cell, value) in [
+            ("gain", &self.gain, &mut channel.scaling.gain),
+            ("offset", &self.offset, &mut channel.scaling.offset),
+        ] {
+            match parse_number(cell) {
+                Ok(Some(number)) => *value = number,
+                Ok(None) => {}
+                Err(e) => errors.push(format!("{}: {}", name, e)),
+            }
+        }
+        if channel.scaling.gain == 0.0 {
+            errors.push("gain: can't be 0".to_owned());
+        }
+        let alarm = &mut channel.alarm;
+        for (name, cell, alarm) in [
+            ("hhigh", &self.hhigh, &mut alarm.hhigh),
+            ("high", &self.high, &mut alarm.high),
+            ("low", &self.low, &mut alarm.low),
+            ("llow", &self.llow, &mut alarm.llow),
+        ] {
+            if !present(name) {
+                continue;
+            }
+            match parse_number(cell) {
+                Ok(Some(setpoint)) => {
+                    alarm.enabled = true;
+                    alarm.setpoint = setpoint;
+                }
+                Ok(None) => alarm.enabled = false,
+                Err(e) => errors.push(format!("{}: {}", name, e)),
+            }
+        }
+        match errors.is_empty() {
+            true => Ok(()),
+            false => Err(errors),
+        }
+    }
+}
+
+// Writes the channels of a device as CSV, with a header row.
+pub fn export_channels<W: Write>(writer: W, channels: &[Channel]) -> anyhow::Result<()> {
+    let mut writer = csv::Writer::from_writer(writer);
+    for channel in channels {
+        writer.serialize(ChannelRow::from_channel(channel))?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+// Reads a channel table and compares it with the current channels of the
+// device. Only a file that can't be read as CSV at all is an error, invalid
+// rows are reported in `ChannelImport::errors`. The tags of the other devices
+// and of the calculations can't be reused, the ones of this device are replaced.
+pub fn import_channels<R: Read>(
+    reader: R,
+    current: &[Channel],
+    used_tags: &TagDatabase,
+) -> anyhow::Result<ChannelImport> {
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_reader(reader);
+    let headers = reader.headers()?.clone();
+    let columns: HashSet<String> = headers.iter().map(|header| header.to_owned()).collect();
+    for column in REQUIRED_COLUMNS {
+        if !columns.contains(column) {
+            anyhow::bail!("Missing column: {}", column);
+        }
+    }
+
+    let device_id = current.first().map_or(0, |channel| channel.device_id);
+    let existing: HashMap<usize, &Channel> = current
+        .iter()
+        .map(|channel| (channel.id, channel))
+        .collect();
+    let mut next_id = current
+        .iter()
+        .map(|channel| channel.id + 1)
+        .max()
+        .unwrap_or(0);
+    let mut import = ChannelImport::default();
+    let mut ids = HashSet::new();
+    let mut tags = HashSet::new();
+    for record in reader.records() {
+        let record = record?;
+        let line = record.position().map_or(0, |position| position.line());
+        let mut row_errors = Vec::new();
+        let row: ChannelRow = match record.deserialize(Some(&headers)) {
+            Ok(row) => row,
+            Err(e) => {
+                import.errors.push(RowError {
+                    line,
+                    message: e.to_string(),
+                });
+                continue;
+            }
+        };
+
+        let previous = match row.id.is_empty() {
+            true => None,
+            false => match row.id.parse::<usize>() {
+                Ok(id) if !ids.insert(id) => {
+                    row_errors.push(format!("id: CH{} is listed twice", id));
+                    None
+                }
+                Ok(id) => existing.get(&id).copied(),
+                Err(_) => {
+                    row_errors.push(format!("id: expected a channel number, got {}", row.id));
+                    None
+                }
+            },
+        };
+        if !row.tag.is_empty() && !tags.insert(row.tag.clone()) {
+            row_errors.push(format!("tag: {} is used twice", row.tag));
+        }
+        match used_tags.lookup(&row.tag) {
+            Some(TagAddress::Channel { device_id: id, .. }) if id == device_id => (),
+            Some(address) => {
+                row_errors.push(format!("tag: {} is already used by {}", row.tag, address))
+            }
+            None => (),
+        }
+
+        let mut channel = match previous {
+            Some(channel) => channel.clone(),
+            None => {
+                let channel = Channel {
+                    id: next_id,
+                    device_id,
+                    enabled: true,
+                    ..Default::default()
+                };
+                next_id += 1;
+                channel
+            }
+        };
+        if let Err(mut errors) = row.apply(&mut channel, &columns) {
+            row_errors.append(&mut errors);
+        }
+        if !row_errors.is_empty() {
+            import.errors.push(RowError {
+                line,
+                message: row_errors.join(", "),
+            });
+            continue;
+        }
+
+        match previous {
+            Some(previous) => {
+                let (old, new) = (
+                    ChannelRow::from_channel(previous),
+                    ChannelRow::from_channel(&channel),
+                );
+                let fields: Vec<String> = old
+                    .cells()
+                    .iter()
+                    .zip(new.cells())
+                    .filter(|((_, old), (_, new))| old != new)
+                    .map(|((name, old), (_, new))| format!("{}: {} -> {}", name, old, new))
+                    .collect();
+                if !fields.is_empty() {
+                    import
+                        .changes
+                        .push(ChannelChange::Modified(previous.to_string(), fields));
+                }
+            }
+            None => import
+                .changes
+                .push(ChannelChange::Added(channel_name(&channel))),
+        }
+        import.channels.push(channel);
+    }
+
+    for channel in current.iter().filter(|channel| !ids.contains(&channel.id)) {
+        import
+            .changes
+            .push(ChannelChange::Removed(channel_name(channel)));
+    }
+    Ok(import)
+}
+
+fn channel_name(channel: &Channel) -> String {
+    match channel.tag.is_empty() {
+        true => channel.to_string(),
+        false => format!("{} {}", channel, channel.tag),
+    }
+}
+
+fn parse_bool(cell: &str) -> Option<bool> {
+    match cell.trim().to_lowercase().as_str() {
+        "true" | "yes" | "1" => Some(true),
+        "false" | "no" | "0" => Some(false),
+        _ => None,
+    }
+}
+
+fn parse_value_type(cell: &str) -> Option<ValueType> {
+    match cell.trim().to_lowercase().as_str() {
+        "int" | "int16" => Some(ValueType::Int16),
+        "real" | "real32" => Some(ValueType::Real32),
+        "bool" | "booltype" => Some(ValueType::BoolType),
+        _ => None,
+    }
+}
+
+fn parse_access_type(cell: &str) -> Option<AccessType> {
+    match cell.trim().to_lowercase().as_str() {
+        "read" => Some(AccessType::Read),
+        "write" => Some(AccessType::Write),
+        _ => None,
+    }
+}
+
+fn parse_number(cell: &str) -> Result<Option<f32>, String> {
+    match cell.trim() {
+        "" => Ok(None),
+        cell => cell
+            .parse::<f32>()
+            .map(Some)
+            .map_err(|_| format!("expected a number, got {}", cell)),
+    }
+}
+
+impl Display for ChannelChange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ChannelChange::Added(channel) => write!(f, "+ {}", channel),
+            ChannelChange::Modified(channel, fields) => {
+                write!(f, "~ {}: {}", channel, fields.join(", "))
+            }
+            ChannelChange::Removed(channel) => write!(f, "- {}", channel),
+        }
+    }
+}
+
+impl Display for RowError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Line {}: {}", self.line, self.message)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{export_channels, import_channels, ChannelChange};
+    use crate::{Calculation, Device, TagDatabase, ValueType};
+
+    #[test]
+    fn channel_table_test() {
+        let mut device = Device::default();
+        device.channels.truncate(3);
+        device.channels[1].tag = "Flow".to_owned();
+        device.channels[1].alarm.high.enabled = true;
+        device.channels[1].alarm.high.setpoint = 80.0;
+        let tags = TagDatabase::new(std::slice::from_ref(&device), &[]);
+
+        let mut exported = Vec::new();
+        export_channels(&mut exported, &device.channels, &tags).unwrap();
+        let exported = String::from_utf8(exported).unwrap();
+        let import = import_channels(exported.as_bytes(), &device.channels, &tags).unwrap();
+        assert!(import.changes.is_empty() && import.errors.is_empty());
+        assert_eq!(import.channels, device.channels);
+
+        // CH2 is dropped, CH1 changes and a channel is added.
+        let table = "id,tag,address,value_type,access_type,high\n\
+                     0,,0,Int,Read,\n\
+                     1,Flow,4,Real,Read,90\n\
+                     ,Level,7,Int,Write,\n";
+        let import = import_channels(table.as_bytes(), &device.channels, &tags).unwrap();
+        assert!(import.errors.is_empty());
+        assert_eq!(import.channels.len(), 3);
+        assert_eq!(import.channels[1].value_type, ValueType::Real32);
+        assert_eq!(import.channels[2].id, 3);
+        assert!(
+            matches!(&import.changes[0], ChannelChange::Modified(_, fields) if fields.len() == 3)
+        );
+        assert_eq!(
+            import.changes[1],
+            ChannelChange::Added("D0:CH3 Level".to_owned())
+        );
+        assert_eq!(
+            import.changes[2],
+            ChannelChange::Removed("D0:CH2".to_owned())
+        );
+
+        let table = "tag,address,value_type,access_type\n\
+                     Flow,99999,Int,Read\n\
+                     Flow,1,Text,Read\n";
+        let import = import_channels(table.as_bytes(), &device.channels, &tags).unwrap();
+        assert_eq!(import.errors.len(), 2);
+        assert_eq!(import.errors[0].line, 2);
+        assert!(import.errors[1].message.contains("used twice"));
+        assert!(import_channels("tag,address\n".as_bytes(), &device.channels, &tags).is_err());
+    }
+
+    #[test]
+    fn used_tags_test() {
+        let mut device = Device::default();
+        device.channels[3].tag = "Level".to_owned();
+        let mut other = Device::default();
+        for channel in other.channels.iter_mut() {
+            channel.device_id = 1;
+        }
+        other.channels[0].tag = "Flow".to_owned();
+
+        let calculations = [Calculation {
+            id: 0,
+            tag: "Total".to_owned(),
+            ..Default::default()
+        }];
+        let tags = TagDatabase::new(&[device.clone(), other], &calculations);
+        // Level is a tag of the device being replaced, it can be kept.
+        let table = "tag,address,value_type,access_type\n\
+                     Flow,1,Int,Read\n\
+                     Total,2,Int,Read\n\
+                     Level,3,Int,Read\n";
+        let import = import_channels(table.as_bytes(), &device.channels, &tags).unwrap();
+        assert_eq!(import.errors.len(), 2);
+        assert_eq!(import.channels.len(), 1);
+        assert!(import.errors[0].message.contains("already used by D1:CH0"));
+        assert!(import.errors[1].message.contains("already used by EVAL0"));
+    }
+}
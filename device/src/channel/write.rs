@@ -0,0 +1,228 @@
+use std::{
+    collections::HashMap,
+    fmt::Display,
+    time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    decode, encode, Channel, DataType, Device, JsonConfirmWrite, JsonWriteChannel, ModbusClient,
+    ValueType, WordOrder,
+};
+
+// How long a write waits for its confirmation.
+const CONFIRM_TIMEOUT: Duration = Duration::from_secs(30);
+
+// Who asked for a write, so the result goes back to them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum WriteOrigin {
+    Hmi,
+    Gui,
+}
+
+impl Default for WriteOrigin {
+    fn default() -> Self {
+        WriteOrigin::Hmi
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum WriteOutcome {
+    Success,
+    Failed(String),
+    // The write went through but the device holds something else.
+    Mismatch { read_back: f32 },
+    // The write broke a limit or an interlock and never reached the device.
+    Rejected(String),
+    // The channel wants a `JsonConfirmWrite` before writing.
+    AwaitingConfirmation,
+}
+
+// Sent back for every write request, whether it reached the device or not.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct WriteResult {
+    // The id the client gave its request, if any.
+    pub request_id: Option<String>,
+    pub origin: WriteOrigin,
+    pub device_id: usize,
+    pub channel: usize,
+    pub value: f32,
+    pub outcome: WriteOutcome,
+}
+
+// Enforces the write limits of the channels of one device and holds the
+// writes waiting for confirmation. Each device worker has its own.
+#[derive(Default)]
+pub struct WriteGuard {
+    last_writes: HashMap<usize, Instant>,
+    pending: HashMap<usize, (JsonWriteChannel, Instant)>,
+}
+
+impl WriteGuard {
+    // The write to carry out now, or the result to report instead.
+    pub fn request(
+        &mut self,
+        device: &Device,
+        request: JsonWriteChannel,
+    ) -> Result<JsonWriteChannel, WriteResult> {
+        let confirm = match self.check(device, &request) {
+            Ok(confirm) => confirm,
+            Err(e) => return Err(WriteResult::new(request, WriteOutcome::Rejected(e))),
+        };
+        if !confirm {
+            return Ok(request);
+        }
+        // A new request replaces the one waiting on the channel.
+        self.pending
+            .insert(request.channel, (request.clone(), Instant::now()));
+        Err(WriteResult::new(
+            request,
+            WriteOutcome::AwaitingConfirmation,
+        ))
+    }
+
+    // None when there is no write waiting on the channel. The limits are
+    // checked again as the device may have changed in the meantime.
+    pub fn confirm(
+        &mut self,
+        device: &Device,
+        confirm: &JsonConfirmWrite,
+    ) -> Option<Result<JsonWriteChannel, WriteResult>> {
+        let (request, requested) = self.pending.remove(&confirm.channel)?;
+        let rejected = if !confirm.confirm {
+            Some("Cancelled".to_owned())
+        } else if requested.elapsed() > CONFIRM_TIMEOUT {
+            Some("Confirmation timed out".to_owned())
+        } else {
+            self.check(device, &request).err()
+        };
+        match rejected {
+            Some(e) => Some(Err(WriteResult::new(request, WriteOutcome::Rejected(e)))),
+            None => Some(Ok(request)),
+        }
+    }
+
+    // Called once a write was sent to the device, for the interval limit.
+    pub fn written(&mut self, channel: usize) {
+        self.last_writes.insert(channel, Instant::now());
+    }
+
+    // Whether the write needs a confirmation, if it's allowed at all.
+    fn check(&self, device: &Device, request: &JsonWriteChannel) -> Result<bool, String> {
+        let channel = match device.channels.get(request.channel) {
+            Some(channel) => channel,
+            None => return Err("Channel no longer exists".to_owned()),
+        };
+        channel.check_write(device, request.value)?;
+        let min_interval = Duration::from_secs(channel.write_limits.min_interval);
+        if let Some(last) = self.last_writes.get(&request.channel) {
+            if last.elapsed() < min_interval {
+                return Err(format!(
+                    "Only one write every {} s is allowed",
+                    min_interval.as_secs()
+                ));
+            }
+        }
+        Ok(channel.write_limits.confirm)
+    }
+}
+
+impl Channel {
+    // Writes a value in engineering units, then reads it back when the
+    // channel verifies its writes.
+    pub fn write(&self, ctx: &mut dyn ModbusClient, value: f32) -> WriteOutcome {
+        let raw = self.scaling.raw(value);
+        let written = match self.value_type {
+            ValueType::Int16 => ctx.write_single_register(self.index, raw as u16),
+            ValueType::Real32 => match encode(raw.into(), DataType::F32, WordOrder::Abcd) {
+                Ok(registers) => ctx.write_multiple_registers(self.index, &registers),
+                Err(e) => return WriteOutcome::Failed(e.to_string()),
+            },
+            ValueType::BoolType => ctx.write_single_coil(self.index, value as u16 == 1),
+        };
+        if let Err(e) = written {
+            return WriteOutcome::Failed(e.to_string());
+        }
+        if !self.verify_writes {
+            return WriteOutcome::Success;
+        }
+
+        // Compared as raw values, so rounding to the register isn't a mismatch.
+        let expected = match self.value_type {
+            ValueType::Int16 => (raw as u16) as f32,
+            ValueType::Real32 => raw,
+            ValueType::BoolType => (value as u16 == 1) as u8 as f32,
+        };
+        let read_back = match self.read_back(ctx) {
+            Ok(read_back) => read_back,
+            Err(e) => return WriteOutcome::Failed(format!("Read back failed: {}", e)),
+        };
+        if (expected - read_back).abs() <= f32::EPSILON * expected.abs().max(1.0) {
+            WriteOutcome::Success
+        } else {
+            let read_back = match self.value_type {
+                ValueType::BoolType => read_back,
+                _ => self.scaling.apply(read_back),
+            };
+            WriteOutcome::Mismatch { read_back }
+        }
+    }
+
+    // The raw value the device holds now.
+    fn read_back(&self, ctx: &mut dyn ModbusClient) -> anyhow::Result<f32> {
+        let value = match self.value_type {
+            ValueType::Int16 => ctx.read_holding_registers(self.index, 1)?[0] as f32,
+            ValueType::Real32 => {
+                let registers = ctx.read_holding_registers(self.index, 2)?;
+                decode(&registers, DataType::F32, WordOrder::Abcd)? as f32
+            }
+            ValueType::BoolType => ctx.read_coils(self.index, 1)?[0] as u8 as f32,
+        };
+        Ok(value)
+    }
+}
+
+impl WriteResult {
+    pub fn new(request: JsonWriteChannel, outcome: WriteOutcome) -> Self {
+        Self {
+            request_id: request.request_id,
+            origin: request.origin,
+            device_id: request.device_id,
+            channel: request.channel,
+            value: request.value,
+            outcome,
+        }
+    }
+
+    pub fn is_success(&self) -> bool {
+        self.outcome == WriteOutcome::Success
+    }
+}
+
+impl Display for WriteResult {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let target = format!("D{}:CH{}", self.device_id, self.channel);
+        match &self.outcome {
+            WriteOutcome::Success => write!(f, "Wrote {} to {}", self.value, target),
+            WriteOutcome::Failed(e) => {
+                write!(f, "Writing {} to {} failed: {}", self.value, target, e)
+            }
+            WriteOutcome::Mismatch { read_back } => write!(
+                f,
+                "Wrote {} to {} but read back {}",
+                self.value, target, read_back
+            ),
+            WriteOutcome::Rejected(e) => {
+                write!(f, "Writing {} to {} rejected: {}", self.value, target, e)
+            }
+            WriteOutcome::AwaitingConfirmation => {
+                write!(
+                    f,
+                    "Writing {} to {} awaits confirmation",
+                    self.value, target
+                )
+            }
+        }
+    }
+}
@@ -0,0 +1,103 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{AccessType, Channel, Condition, Device, ValueType};
+
+// What a write to a channel has to satisfy before it reaches the device.
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq)]
+pub struct WriteLimits {
+    pub min: Option<f32>,
+    pub max: Option<f32>,
+    // The largest change from the current value in one write, together with
+    // the interval this bounds how fast a setpoint can be moved.
+    pub max_step: Option<f32>,
+    // Seconds between two writes to the channel.
+    #[serde(default)]
+    pub min_interval: u64,
+    // A `Condition` that must hold, e.g. `Pump1 == 0`. Empty means none.
+    #[serde(default)]
+    pub interlock: String,
+    // Writes wait for a second message confirming them.
+    #[serde(default)]
+    pub confirm: bool,
+}
+
+impl Channel {
+    // Checks everything but the interval, which depends on the previous
+    // writes. `device` is the one the channel belongs to.
+    pub fn check_write(&self, device: &Device, value: f32) -> Result<(), String> {
+        let limits = &self.write_limits;
+        if self.access_type != AccessType::Write {
+            return Err(format!("{} is read only", self));
+        }
+        if !value.is_finite() {
+            return Err("Not a number".to_owned());
+        }
+        if self.value_type == ValueType::BoolType && value != 0.0 && value != 1.0 {
+            return Err("Only 0 or 1 can be written to a bool".to_owned());
+        }
+        if let Some(min) = limits.min {
+            if value < min {
+                return Err(format!("{} is below the minimum of {}", value, min));
+            }
+        }
+        if let Some(max) = limits.max {
+            if value > max {
+                return Err(format!("{} is above the maximum of {}", value, max));
+            }
+        }
+        if let Some(max_step) = limits.max_step {
+            if (value - self.value).abs() > max_step {
+                return Err(format!(
+                    "{} is more than {} away from {}",
+                    value, max_step, self.value
+                ));
+            }
+        }
+        let interlock = limits.interlock.trim();
+        if !interlock.is_empty() {
+            let holds = interlock
+                .parse::<Condition>()
+                .and_then(|condition| condition.evaluate(device))
+                .map_err(|e| format!("Interlock error: {}", e))?;
+            if !holds {
+                return Err(format!("Interlock {} doesn't hold", interlock));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{AccessType, Device, WriteLimits};
+
+    #[test]
+    fn check_write_test() {
+        let mut device = Device::default();
+        device.channels[0].tag = "Pump1".to_owned();
+        device.channels[0].value = 1.0;
+        let channel = &mut device.channels[2];
+        channel.value = 50.0;
+        channel.write_limits = WriteLimits {
+            min: Some(0.0),
+            max: Some(100.0),
+            max_step: Some(10.0),
+            interlock: "Pump1 == 0".to_owned(),
+            ..Default::default()
+        };
+        let channel = channel.clone();
+
+        // Read only by default.
+        assert!(channel.check_write(&device, 55.0).is_err());
+        let mut channel = channel;
+        channel.access_type = AccessType::Write;
+        // The pump is running.
+        assert!(channel.check_write(&device, 55.0).is_err());
+        device.channels[0].value = 0.0;
+        assert!(channel.check_write(&device, 55.0).is_ok());
+        assert!(channel.check_write(&device, 61.0).is_err());
+        channel.value = 95.0;
+        assert!(channel.check_write(&device, 101.0).is_err());
+        assert!(channel.check_write(&device, f32::NAN).is_err());
+    }
+}
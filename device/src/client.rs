@@ -0,0 +1,35 @@
+use std::io;
+
+use tokio_modbus::prelude::{sync::Context, SyncReader, SyncWriter};
+
+// What the device workers need from a connection, so devices sharing a
+// serial bus can be polled through it like any other.
+pub trait ModbusClient {
+    fn read_holding_registers(&mut self, address: u16, count: u16) -> io::Result<Vec<u16>>;
+    fn read_coils(&mut self, address: u16, count: u16) -> io::Result<Vec<bool>>;
+    fn write_single_register(&mut self, address: u16, value: u16) -> io::Result<()>;
+    fn write_multiple_registers(&mut self, address: u16, values: &[u16]) -> io::Result<()>;
+    fn write_single_coil(&mut self, address: u16, state: bool) -> io::Result<()>;
+}
+
+impl ModbusClient for Context {
+    fn read_holding_registers(&mut self, address: u16, count: u16) -> io::Result<Vec<u16>> {
+        SyncReader::read_holding_registers(self, address, count)
+    }
+
+    fn read_coils(&mut self, address: u16, count: u16) -> io::Result<Vec<bool>> {
+        SyncReader::read_coils(self, address, count)
+    }
+
+    fn write_single_register(&mut self, address: u16, value: u16) -> io::Result<()> {
+        SyncWriter::write_single_register(self, address, value)
+    }
+
+    fn write_multiple_registers(&mut self, address: u16, values: &[u16]) -> io::Result<()> {
+        SyncWriter::write_multiple_registers(self, address, values)
+    }
+
+    fn write_single_coil(&mut self, address: u16, state: bool) -> io::Result<()> {
+        SyncWriter::write_single_coil(self, address, state)
+    }
+}
@@ -0,0 +1,185 @@
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Datelike, Local, TimeZone, Timelike, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{encode, DataType, Device, Event, EventKind, ModbusClient, WordOrder};
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq)]
+pub enum ClockLayout {
+    // Year, month, day, hour, minute and second in consecutive registers.
+    Fields,
+    // Seconds since 1970 as a 32 bit value over two registers.
+    UnixTimestamp,
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq)]
+pub enum ClockZone {
+    Local,
+    Utc,
+}
+
+// How the time of the host is written into a device without NTP.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct ClockSyncConfig {
+    pub enabled: bool,
+    pub layout: ClockLayout,
+    pub zone: ClockZone,
+    // The first register written.
+    pub address: u16,
+    // Only used by the Unix timestamp.
+    pub word_order: WordOrder,
+    // Minutes between two syncs.
+    pub interval: u64,
+}
+
+impl Default for ClockLayout {
+    fn default() -> Self {
+        ClockLayout::Fields
+    }
+}
+
+impl Default for ClockZone {
+    fn default() -> Self {
+        ClockZone::Local
+    }
+}
+
+impl Default for ClockSyncConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            layout: ClockLayout::Fields,
+            zone: ClockZone::Local,
+            address: 0,
+            word_order: WordOrder::Abcd,
+            interval: 60,
+        }
+    }
+}
+
+// The registers to write for `now`, in the layout and zone of the config.
+// A Unix timestamp in local time is shifted by the offset of the host.
+pub fn clock_registers(config: &ClockSyncConfig, now: DateTime<Utc>) -> anyhow::Result<Vec<u16>> {
+    match config.zone {
+        ClockZone::Utc => registers_at(config, now, 0),
+        ClockZone::Local => {
+            let local = now.with_timezone(&Local);
+            let offset = local.offset().local_minus_utc();
+            registers_at(config, local, offset)
+        }
+    }
+}
+
+fn registers_at<Tz: TimeZone>(
+    config: &ClockSyncConfig,
+    time: DateTime<Tz>,
+    offset: i32,
+) -> anyhow::Result<Vec<u16>> {
+    match config.layout {
+        ClockLayout::Fields => Ok(vec![
+            time.year() as u16,
+            time.month() as u16,
+            time.day() as u16,
+            time.hour() as u16,
+            time.minute() as u16,
+            time.second() as u16,
+        ]),
+        ClockLayout::UnixTimestamp => encode(
+            (time.timestamp() + offset as i64) as f64,
+            DataType::U32,
+            config.word_order,
+        ),
+    }
+}
+
+// Kept by the worker of a device, the first sync happens once it connects.
+#[derive(Default)]
+pub struct ClockSync {
+    last_sync: Option<Instant>,
+    requested: bool,
+}
+
+impl ClockSync {
+    // Syncs on the next cycle, whether the schedule is enabled or not.
+    pub fn request(&mut self) {
+        self.requested = true;
+    }
+
+    // Writes the clock when due, every attempt ends up in an event.
+    pub fn process(&mut self, device: &Device, ctx: &mut dyn ModbusClient) -> Option<Event> {
+        let config = &device.clock_sync;
+        let due = config.enabled
+            && self.last_sync.map_or(true, |last_sync| {
+                last_sync.elapsed() >= Duration::from_secs(config.interval.max(1) * 60)
+            });
+        if !due && !self.requested {
+            return None;
+        }
+        self.requested = false;
+        self.last_sync = Some(Instant::now());
+        let now = Utc::now();
+        let result = clock_registers(config, now).and_then(|registers| {
+            ctx.write_multiple_registers(config.address, &registers)
+                .map_err(anyhow::Error::from)
+        });
+        let event = match result {
+            Ok(_) => Event::new(
+                EventKind::ClockSynced,
+                device.id,
+                format!(
+                    "{} clock set to {}",
+                    device,
+                    match config.zone {
+                        ClockZone::Local => now.with_timezone(&Local).format("%Y-%m-%d %H:%M:%S"),
+                        ClockZone::Utc => now.format("%Y-%m-%d %H:%M:%S UTC"),
+                    }
+                ),
+            ),
+            Err(e) => Event::new(
+                EventKind::WriteFailed,
+                device.id,
+                format!("{} clock sync failed: {}", device, e),
+            ),
+        };
+        Some(event)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{TimeZone, Utc};
+
+    use super::{clock_registers, ClockLayout, ClockSyncConfig, ClockZone};
+    use crate::WordOrder;
+
+    // 2022-11-05 14:30:15 UTC.
+    const NOW: i64 = 1667658615;
+
+    #[test]
+    fn fields_registers_test() {
+        let now = Utc.timestamp_opt(NOW, 0).unwrap();
+        let config = ClockSyncConfig {
+            zone: ClockZone::Utc,
+            ..Default::default()
+        };
+        assert_eq!(
+            clock_registers(&config, now).unwrap(),
+            [2022, 11, 5, 14, 30, 15]
+        );
+    }
+
+    #[test]
+    fn unix_timestamp_registers_test() {
+        let now = Utc.timestamp_opt(NOW, 0).unwrap();
+        let mut config = ClockSyncConfig {
+            zone: ClockZone::Utc,
+            layout: ClockLayout::UnixTimestamp,
+            ..Default::default()
+        };
+        assert_eq!(clock_registers(&config, now).unwrap(), [0x6366, 0x7377]);
+        config.word_order = WordOrder::Cdab;
+        assert_eq!(clock_registers(&config, now).unwrap(), [0x7377, 0x6366]);
+    }
+}
@@ -0,0 +1,181 @@
+use std::str::FromStr;
+
+use crate::{Device, TagDatabase};
+
+// A small boolean expression over channels, such as `Pump1 == 0 || CH3 < 10`.
+// Channels are referenced by tag, CH3 or D0:CH3, calculations by tag or
+// EVAL1. A bare reference is true when the value isn't zero and `!` negates it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Condition {
+    // Any of the groups has to hold, with every comparison inside the group.
+    groups: Vec<Vec<Comparison>>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct Comparison {
+    reference: String,
+    operator: Operator,
+    value: f32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Operator {
+    Equal,
+    NotEqual,
+    LessEqual,
+    GreaterEqual,
+    Less,
+    Greater,
+}
+
+// Two character operators first, so `<=` isn't read as `<`.
+const OPERATORS: [(&str, Operator); 6] = [
+    ("==", Operator::Equal),
+    ("!=", Operator::NotEqual),
+    ("<=", Operator::LessEqual),
+    (">=", Operator::GreaterEqual),
+    ("<", Operator::Less),
+    (">", Operator::Greater),
+];
+
+impl Condition {
+    // Only against the channels of `device`.
+    pub fn evaluate(&self, device: &Device) -> anyhow::Result<bool> {
+        self.evaluate_by(|reference| device.find_channel(reference).map(|channel| channel.value))
+    }
+
+    // Against `device` first, then every device and calculation of the tags.
+    pub fn evaluate_with_tags(&self, device: &Device, tags: &TagDatabase) -> anyhow::Result<bool> {
+        self.evaluate_by(|reference| device.reference_value(reference, tags))
+    }
+
+    fn evaluate_by(&self, value: impl Fn(&str) -> Option<f32>) -> anyhow::Result<bool> {
+        for group in &self.groups {
+            let mut holds = true;
+            for comparison in group {
+                holds &= comparison.evaluate(&value)?;
+            }
+            if holds {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+}
+
+impl Comparison {
+    fn evaluate(&self, value: impl Fn(&str) -> Option<f32>) -> anyhow::Result<bool> {
+        let value = match value(&self.reference) {
+            Some(value) => value,
+            None => anyhow::bail!("Unknown reference: {}", self.reference),
+        };
+        let result = match self.operator {
+            Operator::Equal => value == self.value,
+            Operator::NotEqual => value != self.value,
+            Operator::LessEqual => value <= self.value,
+            Operator::GreaterEqual => value >= self.value,
+            Operator::Less => value < self.value,
+            Operator::Greater => value > self.value,
+        };
+        Ok(result)
+    }
+}
+
+impl FromStr for Comparison {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        for (symbol, operator) in OPERATORS {
+            if let Some((reference, value)) = s.split_once(symbol) {
+                let value = value
+                    .trim()
+                    .parse::<f32>()
+                    .map_err(|_| anyhow::anyhow!("Expected a number after {}", symbol))?;
+                return Ok(Comparison {
+                    reference: parse_reference(reference)?,
+                    operator,
+                    value,
+                });
+            }
+        }
+        let (reference, operator) = match s.strip_prefix('!') {
+            Some(reference) => (reference, Operator::Equal),
+            None => (s, Operator::NotEqual),
+        };
+        Ok(Comparison {
+            reference: parse_reference(reference)?,
+            operator,
+            value: 0.0,
+        })
+    }
+}
+
+fn parse_reference(reference: &str) -> anyhow::Result<String> {
+    let reference = reference.trim();
+    if reference.is_empty() {
+        anyhow::bail!("Missing channel reference");
+    }
+    Ok(reference.to_owned())
+}
+
+impl FromStr for Condition {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut groups = Vec::new();
+        for group in s.split("||") {
+            let comparisons = group
+                .split("&&")
+                .map(Comparison::from_str)
+                .collect::<anyhow::Result<Vec<Comparison>>>()?;
+            groups.push(comparisons);
+        }
+        Ok(Condition { groups })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Condition;
+    use crate::{Calculation, Device, TagDatabase};
+
+    #[test]
+    fn condition_test() {
+        let mut device = Device::default();
+        device.channels[1].tag = "Pump1".to_owned();
+        device.channels[1].value = 0.0;
+        device.channels[3].value = 12.0;
+
+        let condition: Condition = "!Pump1 && CH3 >= 10".parse().unwrap();
+        assert!(condition.evaluate(&device).unwrap());
+        let condition: Condition = "Pump1 || D0:CH3 < 10".parse().unwrap();
+        assert!(!condition.evaluate(&device).unwrap());
+        let condition: Condition = "CH42 == 1".parse().unwrap();
+        assert!(condition.evaluate(&device).is_err());
+        assert!("CH3 == on".parse::<Condition>().is_err());
+    }
+
+    #[test]
+    fn condition_with_tags_test() {
+        let device = Device::default();
+        let mut other = Device::initialize(1, "Other".to_owned());
+        other.channels[2].tag = "Pump2".to_owned();
+        other.channels[2].value = 1.0;
+        let calculations = [Calculation {
+            id: 0,
+            value: 5.0,
+            ..Default::default()
+        }];
+        let tags = TagDatabase::new(&[device.clone(), other], &calculations);
+
+        let condition: Condition = "Pump2 && EVAL0 > 4".parse().unwrap();
+        assert!(condition.evaluate_with_tags(&device, &tags).unwrap());
+        // The device alone doesn't know about them.
+        assert!(condition.evaluate(&device).is_err());
+        let condition: Condition = "D1:CH2 == 0 || CH0 == 1".parse().unwrap();
+        assert!(!condition.evaluate_with_tags(&device, &tags).unwrap());
+        let condition: Condition = "D2:CH0 == 0".parse().unwrap();
+        assert!(condition.evaluate_with_tags(&device, &tags).is_err());
+    }
+}
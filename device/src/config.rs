@@ -1,3 +1,5 @@
+use std::fmt::Display;
+
 use serde::{Deserialize, Serialize};
 
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
@@ -12,10 +14,66 @@ pub enum Parity {
     NoneParity,
 }
 
-#[derive(Default, Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
 pub struct TcpConfig {
     pub address: String,
     pub port: usize,
+    // Gateways forward to the serial unit with this id, 255 reaches the device itself.
+    #[serde(default = "default_tcp_unit")]
+    pub unit: u8,
+    // The second network card of a redundant device, used when the primary fails.
+    #[serde(default)]
+    pub secondary: Option<TcpEndpoint>,
+    // Seconds between two checks of the path we're not using, the worker
+    // goes back to the primary as soon as it answers again.
+    #[serde(default = "default_health_check_rate")]
+    pub health_check_rate: u64,
+}
+
+fn default_tcp_unit() -> u8 {
+    255
+}
+
+fn default_health_check_rate() -> u64 {
+    30
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq)]
+pub struct TcpEndpoint {
+    pub address: String,
+    pub port: usize,
+}
+
+// The endpoint a redundant device is polled through.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq)]
+pub enum ConnectionPath {
+    Primary,
+    Secondary,
+}
+
+impl TcpConfig {
+    // The socket address of a path, None for the secondary of a device without one.
+    pub fn endpoint(&self, path: ConnectionPath) -> Option<String> {
+        match (path, &self.secondary) {
+            (ConnectionPath::Primary, _) => Some(format!("{}:{}", self.address, self.port)),
+            (ConnectionPath::Secondary, Some(secondary)) => {
+                Some(format!("{}:{}", secondary.address, secondary.port))
+            }
+            (ConnectionPath::Secondary, None) => None,
+        }
+    }
+}
+
+impl Default for TcpConfig {
+    fn default() -> Self {
+        Self {
+            address: String::new(),
+            port: 502,
+            unit: default_tcp_unit(),
+            secondary: None,
+            health_check_rate: default_health_check_rate(),
+        }
+    }
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
@@ -24,14 +82,42 @@ pub struct SerialConfig {
     pub baudrate: u32,
     pub slave: u8,
     pub parity: Parity,
+    // Milliseconds this slave gets to answer before the bus moves on.
+    #[serde(default = "default_serial_timeout")]
+    pub timeout: u64,
+}
+
+fn default_serial_timeout() -> u64 {
+    1000
+}
+
+impl DeviceConfig {
+    pub fn is_redundant(&self) -> bool {
+        matches!(self, DeviceConfig::Tcp(config) if config.secondary.is_some())
+    }
 }
 
 impl Default for DeviceConfig {
     fn default() -> Self {
         let config = TcpConfig {
             address: "127.0.0.1".to_owned(),
-            port: 502,
+            ..Default::default()
         };
         DeviceConfig::Tcp(config)
     }
 }
+
+impl Default for ConnectionPath {
+    fn default() -> Self {
+        ConnectionPath::Primary
+    }
+}
+
+impl Display for ConnectionPath {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConnectionPath::Primary => write!(f, "primary"),
+            ConnectionPath::Secondary => write!(f, "secondary"),
+        }
+    }
+}
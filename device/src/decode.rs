@@ -0,0 +1,232 @@
+use std::{fmt::Display, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+
+// How a value is laid out over one or more 16 bit registers.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DataType {
+    U16,
+    I16,
+    U32,
+    I32,
+    F32,
+    U64,
+    I64,
+    F64,
+}
+
+// The order of the bytes of a value, A being the most significant one.
+// ABCD is plain big endian, CDAB swaps the 16 bit words, BADC swaps the bytes
+// inside each word and DCBA is plain little endian.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum WordOrder {
+    Abcd,
+    Cdab,
+    Badc,
+    Dcba,
+}
+
+impl DataType {
+    pub fn registers(&self) -> usize {
+        match self {
+            DataType::U16 | DataType::I16 => 1,
+            DataType::U32 | DataType::I32 | DataType::F32 => 2,
+            DataType::U64 | DataType::I64 | DataType::F64 => 4,
+        }
+    }
+}
+
+// Big endian bytes of the registers, put back in ABCD order.
+fn ordered_bytes(registers: &[u16], order: WordOrder) -> Vec<u8> {
+    let mut bytes: Vec<u8> = match order {
+        WordOrder::Cdab => registers
+            .iter()
+            .rev()
+            .flat_map(|r| r.to_be_bytes())
+            .collect(),
+        _ => registers.iter().flat_map(|r| r.to_be_bytes()).collect(),
+    };
+    match order {
+        WordOrder::Badc => bytes.chunks_mut(2).for_each(|word| word.reverse()),
+        WordOrder::Dcba => bytes.reverse(),
+        _ => {}
+    }
+    bytes
+}
+
+// The reverse of `ordered_bytes`, the order is its own inverse.
+fn registers_from_bytes(bytes: &[u8], order: WordOrder) -> Vec<u16> {
+    let registers: Vec<u16> = bytes
+        .chunks(2)
+        .map(|word| u16::from_be_bytes([word[0], word[1]]))
+        .collect();
+    let bytes = ordered_bytes(&registers, order);
+    bytes
+        .chunks(2)
+        .map(|word| u16::from_be_bytes([word[0], word[1]]))
+        .collect()
+}
+
+// Decodes the first value out of `registers`. 64 bit integers beyond 2^53
+// lose precision as they're returned as f64.
+pub fn decode(registers: &[u16], data_type: DataType, order: WordOrder) -> anyhow::Result<f64> {
+    let count = data_type.registers();
+    if registers.len() < count {
+        anyhow::bail!(
+            "{} needs {} registers, got {}",
+            data_type,
+            count,
+            registers.len()
+        );
+    }
+    let bytes = ordered_bytes(&registers[..count], order);
+    let value = match data_type {
+        DataType::U16 => u16::from_be_bytes([bytes[0], bytes[1]]) as f64,
+        DataType::I16 => i16::from_be_bytes([bytes[0], bytes[1]]) as f64,
+        DataType::U32 => u32::from_be_bytes(bytes[..4].try_into()?) as f64,
+        DataType::I32 => i32::from_be_bytes(bytes[..4].try_into()?) as f64,
+        DataType::F32 => f32::from_be_bytes(bytes[..4].try_into()?) as f64,
+        DataType::U64 => u64::from_be_bytes(bytes[..8].try_into()?) as f64,
+        DataType::I64 => i64::from_be_bytes(bytes[..8].try_into()?) as f64,
+        DataType::F64 => f64::from_be_bytes(bytes[..8].try_into()?),
+    };
+    Ok(value)
+}
+
+// The registers to write for `value`, integers have to fit the type.
+pub fn encode(value: f64, data_type: DataType, order: WordOrder) -> anyhow::Result<Vec<u16>> {
+    let integer = |min: f64, max: f64| {
+        if value.fract() != 0.0 || value < min || value > max {
+            anyhow::bail!("{} doesn't fit in {}", value, data_type);
+        }
+        Ok(value)
+    };
+    let bytes = match data_type {
+        DataType::U16 => (integer(0.0, u16::MAX as f64)? as u16)
+            .to_be_bytes()
+            .to_vec(),
+        DataType::I16 => (integer(i16::MIN as f64, i16::MAX as f64)? as i16)
+            .to_be_bytes()
+            .to_vec(),
+        DataType::U32 => (integer(0.0, u32::MAX as f64)? as u32)
+            .to_be_bytes()
+            .to_vec(),
+        DataType::I32 => (integer(i32::MIN as f64, i32::MAX as f64)? as i32)
+            .to_be_bytes()
+            .to_vec(),
+        DataType::F32 => (value as f32).to_be_bytes().to_vec(),
+        DataType::U64 => (integer(0.0, u64::MAX as f64)? as u64)
+            .to_be_bytes()
+            .to_vec(),
+        DataType::I64 => (integer(i64::MIN as f64, i64::MAX as f64)? as i64)
+            .to_be_bytes()
+            .to_vec(),
+        DataType::F64 => value.to_be_bytes().to_vec(),
+    };
+    Ok(registers_from_bytes(&bytes, order))
+}
+
+impl FromStr for DataType {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let data_type = match s.to_lowercase().as_str() {
+            "u16" | "uint16" => DataType::U16,
+            "i16" | "int16" => DataType::I16,
+            "u32" | "uint32" => DataType::U32,
+            "i32" | "int32" => DataType::I32,
+            "f32" | "float" | "real" => DataType::F32,
+            "u64" | "uint64" => DataType::U64,
+            "i64" | "int64" => DataType::I64,
+            "f64" | "double" => DataType::F64,
+            _ => anyhow::bail!(
+                "Unknown data type {}, expected u16, i16, u32, i32, f32, u64, i64 or f64",
+                s
+            ),
+        };
+        Ok(data_type)
+    }
+}
+
+impl FromStr for WordOrder {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let order = match s.to_lowercase().as_str() {
+            "abcd" | "be" => WordOrder::Abcd,
+            "cdab" => WordOrder::Cdab,
+            "badc" => WordOrder::Badc,
+            "dcba" | "le" => WordOrder::Dcba,
+            _ => anyhow::bail!(
+                "Unknown word order {}, expected abcd, cdab, badc or dcba",
+                s
+            ),
+        };
+        Ok(order)
+    }
+}
+
+impl Display for DataType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let data_type = match self {
+            DataType::U16 => "u16",
+            DataType::I16 => "i16",
+            DataType::U32 => "u32",
+            DataType::I32 => "i32",
+            DataType::F32 => "f32",
+            DataType::U64 => "u64",
+            DataType::I64 => "i64",
+            DataType::F64 => "f64",
+        };
+        write!(f, "{}", data_type)
+    }
+}
+
+impl Display for WordOrder {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let order = match self {
+            WordOrder::Abcd => "ABCD",
+            WordOrder::Cdab => "CDAB",
+            WordOrder::Badc => "BADC",
+            WordOrder::Dcba => "DCBA",
+        };
+        write!(f, "{}", order)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decode, encode, DataType, WordOrder};
+
+    #[test]
+    fn decode_test() {
+        // 123.456 as f32 is 0x42F6E979.
+        let abcd = [0x42F6, 0xE979];
+        let orders = [
+            (WordOrder::Abcd, [0x42F6, 0xE979]),
+            (WordOrder::Cdab, [0xE979, 0x42F6]),
+            (WordOrder::Badc, [0xF642, 0x79E9]),
+            (WordOrder::Dcba, [0x79E9, 0xF642]),
+        ];
+        for (order, registers) in orders {
+            let value = decode(&registers, DataType::F32, order).unwrap();
+            assert!((value - 123.456).abs() < 0.001);
+            assert_eq!(encode(value, DataType::F32, order).unwrap(), registers);
+        }
+        assert_eq!(
+            decode(&abcd, DataType::U16, WordOrder::Abcd).unwrap(),
+            0x42F6 as f64
+        );
+        assert_eq!(
+            decode(&[0xFFFF], DataType::I16, WordOrder::Abcd).unwrap(),
+            -1.0
+        );
+        assert_eq!(
+            encode(-2.0, DataType::I32, WordOrder::Cdab).unwrap(),
+            [0xFFFE, 0xFFFF]
+        );
+        assert!(decode(&abcd, DataType::F64, WordOrder::Abcd).is_err());
+        assert!(encode(70000.0, DataType::U16, WordOrder::Abcd).is_err());
+        assert!(encode(1.5, DataType::I32, WordOrder::Abcd).is_err());
+    }
+}
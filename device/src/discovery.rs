@@ -0,0 +1,353 @@
+use std::{
+    fmt::Display,
+    net::{Ipv4Addr, SocketAddr},
+    str::FromStr,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        mpsc::{channel, Receiver, Sender},
+        Arc,
+    },
+    thread,
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    transport::{Transport, ENCAPSULATED_INTERFACE, READ_HOLDING_REGISTERS},
+    Device, DeviceConfig, Parity, SerialConfig, TcpConfig,
+};
+
+// Hosts probed at the same time during a TCP scan.
+const PARALLEL_CONNECTIONS: usize = 32;
+// The largest TCP scan we accept, a /16 on one port.
+const MAX_TCP_TARGETS: usize = 65536;
+const READ_DEVICE_IDENTIFICATION: u8 = 0x0E;
+
+// What to scan.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ScanTarget {
+    // Every unit id of the range on one serial port.
+    Serial {
+        com_port: String,
+        baudrate: u32,
+        units: (u8, u8),
+    },
+    // Every address and port of the ranges, with the same unit id.
+    Tcp {
+        addresses: (Ipv4Addr, Ipv4Addr),
+        ports: (u16, u16),
+        unit: u8,
+    },
+}
+
+// The basic objects of a device identification (function 43/14).
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct DeviceIdentification {
+    pub vendor: String,
+    pub product_code: String,
+    pub revision: String,
+}
+
+// A unit that answered, even with an exception.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct DiscoveredDevice {
+    pub config: DeviceConfig,
+    pub identification: Option<DeviceIdentification>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ScanEvent {
+    Progress { done: usize, total: usize },
+    Found(DiscoveredDevice),
+    // The scan couldn't go on, e.g. the serial port couldn't be opened.
+    Failed(String),
+    Finished,
+}
+
+// A scan running in its own thread. Dropping it cancels the scan.
+pub struct Scan {
+    pub receive: Receiver<ScanEvent>,
+    cancel: Arc<AtomicBool>,
+}
+
+impl Scan {
+    // `timeout` is how long a unit gets to answer each request.
+    pub fn start(target: ScanTarget, timeout: Duration) -> Self {
+        let (send, receive) = channel();
+        let cancel = Arc::new(AtomicBool::new(false));
+        let thread_cancel = Arc::clone(&cancel);
+        thread::spawn(move || {
+            let result = match target {
+                ScanTarget::Serial {
+                    com_port,
+                    baudrate,
+                    units,
+                } => scan_serial(&com_port, baudrate, units, timeout, &send, &thread_cancel),
+                ScanTarget::Tcp {
+                    addresses,
+                    ports,
+                    unit,
+                } => scan_tcp(addresses, ports, unit, timeout, &send, &thread_cancel),
+            };
+            let event = match result {
+                Ok(_) => ScanEvent::Finished,
+                Err(e) => ScanEvent::Failed(e.to_string()),
+            };
+            let _ = send.send(event);
+        });
+        Self { receive, cancel }
+    }
+
+    pub fn cancel(&self) {
+        self.cancel.store(true, Ordering::Relaxed);
+    }
+}
+
+impl Drop for Scan {
+    fn drop(&mut self) {
+        self.cancel();
+    }
+}
+
+fn scan_serial(
+    com_port: &str,
+    baudrate: u32,
+    units: (u8, u8),
+    timeout: Duration,
+    send: &Sender<ScanEvent>,
+    cancel: &AtomicBool,
+) -> anyhow::Result<()> {
+    let mut transport = Transport::rtu(com_port, baudrate, timeout)?;
+    let total = (units.1 as usize + 1).saturating_sub(units.0 as usize);
+    for (done, unit) in (units.0..=units.1).enumerate() {
+        if cancel.load(Ordering::Relaxed) {
+            break;
+        }
+        if let Some(identification) = probe(&mut transport, unit) {
+            let config = DeviceConfig::Serial(SerialConfig {
+                com_port: com_port.to_owned(),
+                baudrate,
+                slave: unit,
+                parity: Parity::NoneParity,
+                timeout: timeout.as_millis() as u64,
+            });
+            let _ = send.send(ScanEvent::Found(DiscoveredDevice {
+                config,
+                identification,
+            }));
+        }
+        let _ = send.send(ScanEvent::Progress {
+            done: done + 1,
+            total,
+        });
+    }
+    Ok(())
+}
+
+fn scan_tcp(
+    addresses: (Ipv4Addr, Ipv4Addr),
+    ports: (u16, u16),
+    unit: u8,
+    timeout: Duration,
+    send: &Sender<ScanEvent>,
+    cancel: &AtomicBool,
+) -> anyhow::Result<()> {
+    let (first, last) = (u32::from(addresses.0), u32::from(addresses.1));
+    let hosts = (last as usize + 1).saturating_sub(first as usize);
+    let port_count = (ports.1 as usize + 1).saturating_sub(ports.0 as usize);
+    let total = hosts * port_count;
+    if total > MAX_TCP_TARGETS {
+        anyhow::bail!(
+            "{} targets to scan, at most {} are allowed",
+            total,
+            MAX_TCP_TARGETS
+        );
+    }
+    let targets: Vec<SocketAddr> = (first..=last)
+        .flat_map(|ip| (ports.0..=ports.1).map(move |port| (Ipv4Addr::from(ip), port).into()))
+        .collect();
+
+    let (next, done) = (&AtomicUsize::new(0), &AtomicUsize::new(0));
+    let targets = &targets;
+    thread::scope(|scope| {
+        for _ in 0..PARALLEL_CONNECTIONS.min(total) {
+            let send = send.clone();
+            scope.spawn(move || loop {
+                let i = next.fetch_add(1, Ordering::Relaxed);
+                if i >= targets.len() || cancel.load(Ordering::Relaxed) {
+                    break;
+                }
+                if let Some(found) = probe_tcp(targets[i], unit, timeout) {
+                    let _ = send.send(ScanEvent::Found(found));
+                }
+                let done = done.fetch_add(1, Ordering::Relaxed) + 1;
+                let _ = send.send(ScanEvent::Progress { done, total });
+            });
+        }
+    });
+    Ok(())
+}
+
+fn probe_tcp(address: SocketAddr, unit: u8, timeout: Duration) -> Option<DiscoveredDevice> {
+    let mut transport = Transport::tcp(address, timeout).ok()?;
+    let identification = probe(&mut transport, unit)?;
+    let config = DeviceConfig::Tcp(TcpConfig {
+        address: address.ip().to_string(),
+        port: address.port() as usize,
+        unit,
+        ..Default::default()
+    });
+    Some(DiscoveredDevice {
+        config,
+        identification,
+    })
+}
+
+// None when the unit doesn't answer. Any answer, exceptions included, means
+// it's there, the identification is then asked for.
+fn probe(transport: &mut Transport, unit: u8) -> Option<Option<DeviceIdentification>> {
+    let read = [READ_HOLDING_REGISTERS, 0, 0, 0, 1];
+    transport.request(unit, &read).ok()?;
+    let identify = [
+        ENCAPSULATED_INTERFACE,
+        READ_DEVICE_IDENTIFICATION,
+        0x01,
+        0x00,
+    ];
+    let identification = transport
+        .request(unit, &identify)
+        .ok()
+        .and_then(|response| parse_identification(&response));
+    Some(identification)
+}
+
+// The objects of a basic identification response, 0 to 2.
+fn parse_identification(pdu: &[u8]) -> Option<DeviceIdentification> {
+    if pdu.len() < 7 || pdu[0] != ENCAPSULATED_INTERFACE || pdu[1] != READ_DEVICE_IDENTIFICATION {
+        return None;
+    }
+    let mut identification = DeviceIdentification::default();
+    let mut rest = &pdu[7..];
+    for _ in 0..pdu[6] {
+        let (id, length) = (*rest.first()?, *rest.get(1)? as usize);
+        let value = String::from_utf8_lossy(rest.get(2..2 + length)?)
+            .trim()
+            .to_owned();
+        match id {
+            0 => identification.vendor = value,
+            1 => identification.product_code = value,
+            2 => identification.revision = value,
+            _ => {}
+        }
+        rest = &rest[2 + length..];
+    }
+    Some(identification)
+}
+
+// Reads "first-last", or a single value, as in 1-247 or 502.
+pub fn parse_range<T: FromStr + PartialOrd + Copy>(s: &str) -> anyhow::Result<(T, T)> {
+    let parse = |value: &str| {
+        value
+            .trim()
+            .parse::<T>()
+            .map_err(|_| anyhow::anyhow!("Invalid range: {}", s))
+    };
+    let range = match s.split_once('-') {
+        Some((first, last)) => (parse(first)?, parse(last)?),
+        None => (parse(s)?, parse(s)?),
+    };
+    if range.0 > range.1 {
+        anyhow::bail!("Invalid range: {}", s);
+    }
+    Ok(range)
+}
+
+// Reads an IP range as 192.168.0.1-192.168.0.254, 192.168.0.0/24 or a single
+// address. Network and broadcast addresses of a CIDR range are left out.
+pub fn parse_ip_range(s: &str) -> anyhow::Result<(Ipv4Addr, Ipv4Addr)> {
+    match s.split_once('/') {
+        Some((network, prefix)) => {
+            let network: Ipv4Addr = network.trim().parse()?;
+            let prefix: u32 = prefix.trim().parse()?;
+            if !(16..=32).contains(&prefix) {
+                anyhow::bail!("Only /16 to /32 networks can be scanned");
+            }
+            let mask = u32::MAX.checked_shl(32 - prefix).unwrap_or(0);
+            let first = u32::from(network) & mask;
+            let last = first | !mask;
+            match prefix {
+                31 | 32 => Ok((first.into(), last.into())),
+                _ => Ok(((first + 1).into(), (last - 1).into())),
+            }
+        }
+        None => parse_range(s),
+    }
+}
+
+impl DiscoveredDevice {
+    pub fn name(&self) -> String {
+        match &self.identification {
+            Some(identification) if !identification.product_code.is_empty() => {
+                format!("{} {}", identification.vendor, identification.product_code)
+                    .trim()
+                    .to_owned()
+            }
+            _ => self.to_string(),
+        }
+    }
+
+    // A new device with the default channels, connected to what was found.
+    pub fn to_device(&self, id: usize) -> Device {
+        let mut device = Device::initialize(id, self.name());
+        device.config = self.config.clone();
+        device
+    }
+}
+
+impl Display for DiscoveredDevice {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.config {
+            DeviceConfig::Tcp(config) => {
+                write!(f, "{}:{} unit {}", config.address, config.port, config.unit)
+            }
+            DeviceConfig::Serial(config) => write!(f, "{} unit {}", config.com_port, config.slave),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use super::{parse_identification, parse_ip_range, parse_range};
+
+    #[test]
+    fn discovery_test() {
+        let response = [
+            0x2B, 0x0E, 0x01, 0x01, 0x00, 0x00, 0x03, 0x00, 0x04, b'A', b'c', b'm', b'e', 0x01,
+            0x03, b'P', b'L', b'C', 0x02, 0x04, b'V', b'1', b'.', b'2',
+        ];
+        let identification = parse_identification(&response).unwrap();
+        assert_eq!(identification.vendor, "Acme");
+        assert_eq!(identification.product_code, "PLC");
+        assert_eq!(identification.revision, "V1.2");
+        assert!(parse_identification(&response[..12]).is_none());
+
+        assert_eq!(parse_range::<u8>("1-247").unwrap(), (1, 247));
+        assert_eq!(parse_range::<u16>("502").unwrap(), (502, 502));
+        assert!(parse_range::<u8>("10-2").is_err());
+        assert_eq!(
+            parse_ip_range("192.168.0.7/24").unwrap(),
+            (
+                Ipv4Addr::new(192, 168, 0, 1),
+                Ipv4Addr::new(192, 168, 0, 254)
+            )
+        );
+        assert_eq!(
+            parse_ip_range("10.0.0.5-10.0.0.9").unwrap(),
+            (Ipv4Addr::new(10, 0, 0, 5), Ipv4Addr::new(10, 0, 0, 9))
+        );
+        assert!(parse_ip_range("10.0.0.0/8").is_err());
+    }
+}
@@ -0,0 +1,440 @@
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt::Display,
+    str::FromStr,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::{AlarmPriority, Calculation, ChannelAlarm, Device};
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq)]
+pub enum EventKind {
+    AlarmRaised,
+    AlarmCleared,
+    AlarmAcked,
+    AlarmShelved,
+    AlarmUnshelved,
+    AlarmSuppressed,
+    AlarmUnsuppressed,
+    DeviceConnected,
+    DeviceDisconnected,
+    ChannelWritten,
+    // The device refused the write, or holds another value afterwards.
+    WriteFailed,
+    // A redundant device is now polled through its other endpoint.
+    PathSwitched,
+    // The clock of the device was set to the time of the host.
+    ClockSynced,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct Event {
+    // Unix timestamp in seconds.
+    pub timestamp: i64,
+    pub kind: EventKind,
+    pub device_id: usize,
+    pub channel: Option<usize>,
+    // Set instead of the channel for calculation events.
+    #[serde(default)]
+    pub calculation: Option<usize>,
+    pub value: Option<f32>,
+    pub priority: Option<AlarmPriority>,
+    pub message: String,
+}
+
+impl Event {
+    pub fn new(kind: EventKind, device_id: usize, message: String) -> Self {
+        Self {
+            timestamp: unix_timestamp(),
+            kind,
+            device_id,
+            channel: None,
+            calculation: None,
+            value: None,
+            priority: None,
+            message,
+        }
+    }
+    // The source of the event in the D0:CH3 notation, D0 for device events
+    // and EVAL3 for calculations.
+    pub fn source(&self) -> String {
+        match (self.calculation, self.channel) {
+            (Some(calculation), _) => format!("EVAL{}", calculation),
+            (None, Some(channel)) => format!("D{}:CH{}", self.device_id, channel),
+            (None, None) => format!("D{}", self.device_id),
+        }
+    }
+}
+
+// Used both by the GUI event viewer and the server query API.
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq)]
+pub struct EventFilter {
+    pub from: Option<i64>,
+    pub to: Option<i64>,
+    pub device_id: Option<usize>,
+    pub min_priority: Option<AlarmPriority>,
+}
+
+impl EventFilter {
+    pub fn matches(&self, event: &Event) -> bool {
+        self.from.map_or(true, |from| event.timestamp >= from)
+            && self.to.map_or(true, |to| event.timestamp <= to)
+            && self.device_id.map_or(true, |device_id| {
+                event.calculation.is_none() && event.device_id == device_id
+            })
+            && self.min_priority.map_or(true, |min_priority| {
+                event
+                    .priority
+                    .map_or(false, |priority| priority.level() >= min_priority.level())
+            })
+    }
+}
+
+// Compares two snapshots of the same device and returns an event for every
+// alarm that was raised, cleared, acknowledged, shelved or suppressed in between.
+pub fn alarm_events(previous: &Device, current: &Device) -> Vec<Event> {
+    let mut events = Vec::new();
+    for channel in current.channels.iter() {
+        let old_channel = match previous.channels.iter().find(|old| old.id == channel.id) {
+            Some(old) => old,
+            None => continue,
+        };
+        let event = Event {
+            channel: Some(channel.id),
+            value: Some(channel.value),
+            ..Event::new(EventKind::AlarmRaised, current.id, String::new())
+        };
+        events.append(&mut channel_alarm_events(
+            &old_channel.alarm,
+            &channel.alarm,
+            event,
+        ));
+    }
+    events
+}
+
+// The same as `alarm_events` for the calculations.
+pub fn calculation_events(previous: &[Calculation], current: &[Calculation]) -> Vec<Event> {
+    let mut events = Vec::new();
+    for calculation in current {
+        let old_alarm = match previous.iter().find(|old| old.id == calculation.id) {
+            Some(old) => &old.alarm,
+            None => continue,
+        };
+        let event = Event {
+            calculation: Some(calculation.id),
+            value: Some(calculation.value),
+            ..Event::new(EventKind::AlarmRaised, 0, String::new())
+        };
+        events.append(&mut channel_alarm_events(
+            old_alarm,
+            &calculation.alarm,
+            event,
+        ));
+    }
+    events
+}
+
+// Every alarm change between two snapshots, as copies of `template`.
+fn channel_alarm_events(
+    old_alarm: &ChannelAlarm,
+    alarm: &ChannelAlarm,
+    template: Event,
+) -> Vec<Event> {
+    let mut events = Vec::new();
+    for (old, new) in old_alarm.alarms().iter().zip(alarm.alarms().iter()) {
+        if !old.enabled && !new.enabled {
+            continue;
+        }
+        let mut changes = Vec::new();
+        // An acknowledgement in the same cycle as a clear or a raise is
+        // reported first, the state alone doesn't show it anymore.
+        if old.is_unacked() && new.acked_at != old.acked_at {
+            changes.push((
+                EventKind::AlarmAcked,
+                format!("{} acknowledged by {}", new.text(), new.acked_by),
+            ));
+        }
+        match (old.shelved_until, new.shelved_until) {
+            (None, Some(until)) => changes.push((
+                EventKind::AlarmShelved,
+                format!(
+                    "{} shelved by {} for {} s",
+                    new.text(),
+                    new.shelved_by,
+                    until - unix_timestamp()
+                ),
+            )),
+            (Some(_), None) => changes.push((
+                EventKind::AlarmUnshelved,
+                format!("{} unshelved", new.text()),
+            )),
+            _ => {}
+        }
+        match (old.suppressed, new.suppressed) {
+            (false, true) => changes.push((
+                EventKind::AlarmSuppressed,
+                format!("{} suppressed by {}", new.text(), alarm.suppress_when),
+            )),
+            (true, false) => changes.push((
+                EventKind::AlarmUnsuppressed,
+                format!("{} no longer suppressed", new.text()),
+            )),
+            _ => {}
+        }
+        match (old.active, new.active) {
+            (false, true) => changes.push((EventKind::AlarmRaised, new.text())),
+            // Inhibiting an alarm clears it, the shelve or suppress event says why.
+            (true, false) if !new.is_inhibited() => {
+                changes.push((EventKind::AlarmCleared, format!("{} cleared", new.text())))
+            }
+            _ => {}
+        }
+        for (kind, message) in changes {
+            events.push(Event {
+                kind,
+                priority: Some(new.priority),
+                message,
+                ..template.clone()
+            });
+        }
+    }
+    events
+}
+
+pub fn unix_timestamp() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|time| time.as_secs() as i64)
+        .unwrap_or_default()
+}
+
+impl Display for EventKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let kind = match self {
+            EventKind::AlarmRaised => "AlarmRaised",
+            EventKind::AlarmCleared => "AlarmCleared",
+            EventKind::AlarmAcked => "AlarmAcked",
+            EventKind::AlarmShelved => "AlarmShelved",
+            EventKind::AlarmUnshelved => "AlarmUnshelved",
+            EventKind::AlarmSuppressed => "AlarmSuppressed",
+            EventKind::AlarmUnsuppressed => "AlarmUnsuppressed",
+            EventKind::DeviceConnected => "DeviceConnected",
+            EventKind::DeviceDisconnected => "DeviceDisconnected",
+            EventKind::ChannelWritten => "ChannelWritten",
+            EventKind::WriteFailed => "WriteFailed",
+            EventKind::PathSwitched => "PathSwitched",
+            EventKind::ClockSynced => "ClockSynced",
+        };
+        write!(f, "{}", kind)
+    }
+}
+
+impl FromStr for EventKind {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let kind = match s {
+            "AlarmRaised" => EventKind::AlarmRaised,
+            "AlarmCleared" => EventKind::AlarmCleared,
+            "AlarmAcked" => EventKind::AlarmAcked,
+            "AlarmShelved" => EventKind::AlarmShelved,
+            "AlarmUnshelved" => EventKind::AlarmUnshelved,
+            "AlarmSuppressed" => EventKind::AlarmSuppressed,
+            "AlarmUnsuppressed" => EventKind::AlarmUnsuppressed,
+            "DeviceConnected" => EventKind::DeviceConnected,
+            "DeviceDisconnected" => EventKind::DeviceDisconnected,
+            "ChannelWritten" => EventKind::ChannelWritten,
+            "WriteFailed" => EventKind::WriteFailed,
+            "PathSwitched" => EventKind::PathSwitched,
+            "ClockSynced" => EventKind::ClockSynced,
+            _ => anyhow::bail!("Unknown event kind: {}", s),
+        };
+        Ok(kind)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{alarm_events, Event, EventFilter, EventKind};
+    use crate::{AlarmPriority, AlarmType, Device};
+
+    fn kinds(events: &[Event]) -> Vec<EventKind> {
+        events.iter().map(|event| event.kind).collect()
+    }
+
+    #[test]
+    fn alarm_raised_test() {
+        let mut previous = Device::default();
+        let alarm = &mut previous.channels[1].alarm.high;
+        alarm.enabled = true;
+        alarm.setpoint = 10.0;
+        alarm.priority = AlarmPriority::High;
+        let mut current = previous.clone();
+        current.channels[1].set_value(12.0);
+
+        let events = alarm_events(&previous, &current);
+        assert_eq!(kinds(&events), [EventKind::AlarmRaised]);
+        assert_eq!(events[0].source(), "D0:CH1");
+        assert_eq!(events[0].value, Some(12.0));
+        assert_eq!(events[0].priority, Some(AlarmPriority::High));
+        // Nothing changed, nothing is reported.
+        assert!(alarm_events(&current, &current).is_empty());
+    }
+
+    #[test]
+    fn alarm_cleared_test() {
+        let mut previous = Device::default();
+        previous.channels[1].alarm.high.enabled = true;
+        previous.channels[1].alarm.high.setpoint = 10.0;
+        previous.channels[1].set_value(12.0);
+        let mut current = previous.clone();
+        current.channels[1].set_value(5.0);
+
+        let events = alarm_events(&previous, &current);
+        assert_eq!(kinds(&events), [EventKind::AlarmCleared]);
+        assert_eq!(events[0].message, "HIGH ALARM cleared");
+    }
+
+    #[test]
+    fn alarm_acked_test() {
+        let mut previous = Device::default();
+        previous.channels[1].alarm.high.enabled = true;
+        previous.channels[1].alarm.high.setpoint = 10.0;
+        previous.channels[1].set_value(12.0);
+        let mut current = previous.clone();
+        current.channels[1]
+            .alarm
+            .acknowledge(Some(AlarmType::High), "operator");
+
+        let events = alarm_events(&previous, &current);
+        assert_eq!(kinds(&events), [EventKind::AlarmAcked]);
+        assert!(events[0].message.ends_with("acknowledged by operator"));
+    }
+
+    #[test]
+    fn acked_and_cleared_test() {
+        let mut previous = Device::default();
+        previous.channels[1].alarm.high.enabled = true;
+        previous.channels[1].alarm.high.setpoint = 10.0;
+        previous.channels[1].set_value(12.0);
+        let mut current = previous.clone();
+        current.channels[1]
+            .alarm
+            .acknowledge(Some(AlarmType::High), "operator");
+        current.channels[1].set_value(5.0);
+
+        let events = alarm_events(&previous, &current);
+        assert_eq!(
+            kinds(&events),
+            [EventKind::AlarmAcked, EventKind::AlarmCleared]
+        );
+    }
+
+    #[test]
+    fn reordered_channels_test() {
+        let mut previous = Device::default();
+        previous.channels[1].alarm.high.enabled = true;
+        previous.channels[1].alarm.high.setpoint = 10.0;
+        previous.channels[1].set_value(12.0);
+        let mut current = previous.clone();
+        current.channels.swap(1, 2);
+
+        // Channels are compared by id, not by position.
+        assert!(alarm_events(&previous, &current).is_empty());
+        current.channels[2].set_value(5.0);
+        let events = alarm_events(&previous, &current);
+        assert_eq!(kinds(&events), [EventKind::AlarmCleared]);
+        assert_eq!(events[0].source(), "D0:CH1");
+    }
+
+    #[test]
+    fn alarm_shelved_test() {
+        let mut previous = Device::default();
+        previous.channels[1].alarm.high.enabled = true;
+        previous.channels[1].alarm.high.setpoint = 10.0;
+        previous.channels[1].set_value(12.0);
+        let mut current = previous.clone();
+        current.channels[1]
+            .alarm
+            .shelve(Some(AlarmType::High), 60, "operator");
+        current.channels[1].set_value(12.0);
+
+        // The shelve event says why the alarm went away, it isn't cleared.
+        let events = alarm_events(&previous, &current);
+        assert_eq!(kinds(&events), [EventKind::AlarmShelved]);
+        assert!(!current.channels[1].alarm.high.active);
+    }
+
+    #[test]
+    fn disabled_alarm_test() {
+        let previous = Device::default();
+        let mut current = previous.clone();
+        current.channels[1].alarm.high.active = true;
+        assert!(alarm_events(&previous, &current).is_empty());
+    }
+
+    #[test]
+    fn event_filter_time_test() {
+        let event = Event {
+            timestamp: 100,
+            ..Event::new(EventKind::AlarmRaised, 1, String::new())
+        };
+        assert!(EventFilter::default().matches(&event));
+        let filter = EventFilter {
+            from: Some(100),
+            to: Some(100),
+            ..Default::default()
+        };
+        assert!(filter.matches(&event));
+        let filter = EventFilter {
+            from: Some(101),
+            ..Default::default()
+        };
+        assert!(!filter.matches(&event));
+        let filter = EventFilter {
+            to: Some(99),
+            ..Default::default()
+        };
+        assert!(!filter.matches(&event));
+    }
+
+    #[test]
+    fn event_filter_device_test() {
+        let event = Event::new(EventKind::AlarmRaised, 1, String::new());
+        let filter = EventFilter {
+            device_id: Some(1),
+            ..Default::default()
+        };
+        assert!(filter.matches(&event));
+        // Calculation events belong to no device.
+        let calculation = Event {
+            calculation: Some(1),
+            ..event.clone()
+        };
+        assert!(!filter.matches(&calculation));
+        let filter = EventFilter {
+            device_id: Some(0),
+            ..Default::default()
+        };
+        assert!(!filter.matches(&event));
+    }
+
+    #[test]
+    fn event_filter_priority_test() {
+        let event = Event {
+            priority: Some(AlarmPriority::Medium),
+            ..Event::new(EventKind::AlarmRaised, 1, String::new())
+        };
+        let filter = |min_priority| EventFilter {
+            min_priority: Some(min_priority),
+            ..Default::default()
+        };
+        assert!(filter(AlarmPriority::Low).matches(&event));
+        assert!(filter(AlarmPriority::Medium).matches(&event));
+        assert!(!filter(AlarmPriority::High).matches(&event));
+        // Events without a priority are below every one.
+        let connected = Event::new(EventKind::DeviceConnected, 1, String::new());
+        assert!(!filter(AlarmPriority::Low).matches(&connected));
+    }
+}
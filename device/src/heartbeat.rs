@@ -0,0 +1,266 @@
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{AlarmPriority, Device, Event, EventKind, ModbusClient};
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq)]
+pub enum HeartbeatMode {
+    // Writes a coil on and off.
+    Toggle,
+    // Increments a holding register, wrapping at 65535.
+    Counter,
+}
+
+// What we write to the device so its logic knows the SCADA is alive.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct HeartbeatConfig {
+    pub enabled: bool,
+    pub mode: HeartbeatMode,
+    // The coil toggled or the register incremented.
+    pub address: u16,
+    // Seconds between two writes.
+    pub interval: u64,
+}
+
+impl Default for HeartbeatMode {
+    fn default() -> Self {
+        HeartbeatMode::Toggle
+    }
+}
+
+impl Default for HeartbeatConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            mode: HeartbeatMode::Toggle,
+            address: 0,
+            interval: 5,
+        }
+    }
+}
+
+// A holding register the device keeps changing while its logic runs.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct WatchdogConfig {
+    pub enabled: bool,
+    pub address: u16,
+    // Seconds the register may keep the same value before the alarm is raised.
+    pub timeout: u64,
+    pub priority: AlarmPriority,
+}
+
+impl Default for WatchdogConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            address: 0,
+            timeout: 10,
+            priority: AlarmPriority::High,
+        }
+    }
+}
+
+// Kept by the worker of a device across reconnections, so an alarm raised
+// before the device went away is cleared once its heartbeat comes back.
+#[derive(Default)]
+pub struct Heartbeat {
+    last_write: Option<Instant>,
+    state: bool,
+    counter: u16,
+    write_failing: bool,
+    // The last value of the watched register and when it changed.
+    watched: Option<(u16, Instant)>,
+    lost: bool,
+}
+
+impl Heartbeat {
+    // The watchdog waits for a fresh value after a reconnection, the time
+    // the device was unreachable doesn't count against its heartbeat.
+    pub fn restart(&mut self) {
+        self.watched = None;
+        self.last_write = None;
+    }
+
+    // Writes our heartbeat when due and checks the one of the device,
+    // `heartbeat_lost` of the device is kept in sync with the watchdog.
+    pub fn process(&mut self, device: &mut Device, ctx: &mut dyn ModbusClient) -> Vec<Event> {
+        let mut events = Vec::new();
+        events.extend(self.write(device, ctx));
+        events.extend(self.watch(device, ctx));
+        device.heartbeat_lost = self.lost;
+        events
+    }
+
+    fn write(&mut self, device: &Device, ctx: &mut dyn ModbusClient) -> Option<Event> {
+        let config = &device.heartbeat;
+        if !config.enabled {
+            return None;
+        }
+        let due = self.last_write.map_or(true, |last_write| {
+            last_write.elapsed() >= Duration::from_secs(config.interval.max(1))
+        });
+        if !due {
+            return None;
+        }
+        self.last_write = Some(Instant::now());
+        let result = match config.mode {
+            HeartbeatMode::Toggle => {
+                let state = !self.state;
+                ctx.write_single_coil(config.address, state)
+                    .map(|_| self.state = state)
+            }
+            HeartbeatMode::Counter => {
+                let counter = self.counter.wrapping_add(1);
+                ctx.write_single_register(config.address, counter)
+                    .map(|_| self.counter = counter)
+            }
+        };
+        // Only the first failure is reported, the device usually stops
+        // answering the polls as well.
+        match result {
+            Ok(_) => {
+                self.write_failing = false;
+                None
+            }
+            Err(_) if self.write_failing => None,
+            Err(e) => {
+                self.write_failing = true;
+                Some(Event::new(
+                    EventKind::WriteFailed,
+                    device.id,
+                    format!("{} heartbeat write failed: {}", device, e),
+                ))
+            }
+        }
+    }
+
+    fn watch(&mut self, device: &Device, ctx: &mut dyn ModbusClient) -> Option<Event> {
+        let config = &device.watchdog;
+        if !config.enabled {
+            self.watched = None;
+            return match self.lost {
+                true => self.cleared(device, None),
+                false => None,
+            };
+        }
+        // A failed read is left to the poll, which reconnects.
+        let value = ctx
+            .read_holding_registers(config.address, 1)
+            .ok()?
+            .first()
+            .copied()?;
+        match self.watched {
+            Some((last, changed)) if last == value => {
+                let stalled = changed.elapsed() >= Duration::from_secs(config.timeout);
+                if stalled && !self.lost {
+                    self.lost = true;
+                    return Some(Event {
+                        priority: Some(config.priority),
+                        value: Some(value as f32),
+                        ..Event::new(
+                            EventKind::AlarmRaised,
+                            device.id,
+                            format!("{} heartbeat lost", device),
+                        )
+                    });
+                }
+                None
+            }
+            Some(_) => {
+                self.watched = Some((value, Instant::now()));
+                match self.lost {
+                    true => self.cleared(device, Some(value)),
+                    false => None,
+                }
+            }
+            None => {
+                self.watched = Some((value, Instant::now()));
+                None
+            }
+        }
+    }
+
+    fn cleared(&mut self, device: &Device, value: Option<u16>) -> Option<Event> {
+        self.lost = false;
+        Some(Event {
+            priority: Some(device.watchdog.priority),
+            value: value.map(|value| value as f32),
+            ..Event::new(
+                EventKind::AlarmCleared,
+                device.id,
+                format!("{} heartbeat restored", device),
+            )
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use super::{Heartbeat, HeartbeatMode};
+    use crate::{Device, EventKind, ModbusClient};
+
+    // Answers with the register value of the test and keeps what was written.
+    struct FakeClient {
+        register: u16,
+        written: Vec<u16>,
+    }
+
+    impl ModbusClient for FakeClient {
+        fn read_holding_registers(&mut self, _: u16, _: u16) -> io::Result<Vec<u16>> {
+            Ok(vec![self.register])
+        }
+        fn read_coils(&mut self, _: u16, count: u16) -> io::Result<Vec<bool>> {
+            Ok(vec![false; count as usize])
+        }
+        fn write_single_register(&mut self, _: u16, value: u16) -> io::Result<()> {
+            self.written.push(value);
+            Ok(())
+        }
+        fn write_multiple_registers(&mut self, _: u16, _: &[u16]) -> io::Result<()> {
+            Ok(())
+        }
+        fn write_single_coil(&mut self, _: u16, _: bool) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn counter_heartbeat_test() {
+        let mut device = Device::default();
+        device.heartbeat.enabled = true;
+        device.heartbeat.mode = HeartbeatMode::Counter;
+        let mut ctx = FakeClient {
+            register: 7,
+            written: Vec::new(),
+        };
+        let mut heartbeat = Heartbeat::default();
+        assert!(heartbeat.process(&mut device, &mut ctx).is_empty());
+        assert_eq!(ctx.written, [1]);
+    }
+
+    #[test]
+    fn heartbeat_lost_test() {
+        let mut device = Device::default();
+        device.watchdog.enabled = true;
+        device.watchdog.timeout = 0;
+        let mut ctx = FakeClient {
+            register: 7,
+            written: Vec::new(),
+        };
+        let mut heartbeat = Heartbeat::default();
+
+        // The first read only arms the watchdog.
+        assert!(heartbeat.process(&mut device, &mut ctx).is_empty());
+        let events = heartbeat.process(&mut device, &mut ctx);
+        assert_eq!(events[0].kind, EventKind::AlarmRaised);
+        assert!(device.heartbeat_lost);
+
+        ctx.register = 8;
+        let events = heartbeat.process(&mut device, &mut ctx);
+        assert_eq!(events[0].kind, EventKind::AlarmCleared);
+        assert!(!device.heartbeat_lost);
+    }
+}
@@ -0,0 +1,278 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::Device;
+
+// Samples kept per channel unless it says otherwise.
+pub const DEFAULT_HISTORY_DEPTH: usize = 3600;
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Sample {
+    // Unix timestamp in milliseconds.
+    pub timestamp: i64,
+    pub value: f32,
+}
+
+// The samples of one channel, oldest first. Timestamps are kept as
+// milliseconds from `epoch` so a sample takes 8 bytes.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ChannelHistory {
+    epoch: i64,
+    samples: VecDeque<(u32, f32)>,
+    depth: usize,
+}
+
+impl ChannelHistory {
+    pub fn new(depth: usize) -> Self {
+        Self {
+            epoch: 0,
+            samples: VecDeque::new(),
+            depth,
+        }
+    }
+
+    // Samples older than the last one are ignored, the buffer stays sorted.
+    pub fn push(&mut self, timestamp: i64, value: f32) {
+        if self.depth == 0 {
+            return;
+        }
+        match self.samples.back() {
+            Some(_) if timestamp < self.latest_timestamp() => return,
+            Some(_) => {}
+            None => self.epoch = timestamp,
+        }
+        if timestamp - self.epoch > u32::MAX as i64 {
+            self.rebase(timestamp);
+        }
+        self.samples
+            .push_back(((timestamp - self.epoch) as u32, value));
+        while self.samples.len() > self.depth {
+            self.samples.pop_front();
+        }
+    }
+
+    // Moves the epoch to the oldest sample that is still within reach of
+    // `timestamp`, after dropping those that aren't.
+    fn rebase(&mut self, timestamp: i64) {
+        let oldest = timestamp - u32::MAX as i64;
+        while let Some((offset, _)) = self.samples.front() {
+            match self.epoch + (*offset as i64) < oldest {
+                true => self.samples.pop_front(),
+                false => break,
+            };
+        }
+        let epoch = match self.samples.front() {
+            Some((offset, _)) => self.epoch + *offset as i64,
+            None => timestamp,
+        };
+        let shift = (epoch - self.epoch) as u32;
+        for (offset, _) in self.samples.iter_mut() {
+            *offset -= shift;
+        }
+        self.epoch = epoch;
+    }
+
+    // Adds samples fetched elsewhere, e.g. from the server, in front of
+    // those we have. The ones that aren't older than our first are dropped.
+    pub fn prepend(&mut self, older: &[Sample]) {
+        let first = match self.samples.front() {
+            Some((offset, _)) => self.epoch + *offset as i64,
+            None => i64::MAX,
+        };
+        let mut samples: Vec<Sample> = older
+            .iter()
+            .filter(|sample| sample.timestamp < first)
+            .copied()
+            .collect();
+        samples.sort_by_key(|sample| sample.timestamp);
+        samples.extend(self.query(i64::MIN, i64::MAX));
+        self.samples.clear();
+        for sample in samples {
+            self.push(sample.timestamp, sample.value);
+        }
+    }
+
+    pub fn set_depth(&mut self, depth: usize) {
+        self.depth = depth;
+        while self.samples.len() > depth {
+            self.samples.pop_front();
+        }
+    }
+
+    // The samples from `from` to `to`, both included.
+    pub fn query(&self, from: i64, to: i64) -> Vec<Sample> {
+        let start = self
+            .samples
+            .partition_point(|(offset, _)| self.epoch + (*offset as i64) < from);
+        self.samples
+            .range(start..)
+            .map(|(offset, value)| Sample {
+                timestamp: self.epoch + *offset as i64,
+                value: *value,
+            })
+            .take_while(|sample| sample.timestamp <= to)
+            .collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.samples.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.samples.is_empty()
+    }
+
+    fn latest_timestamp(&self) -> i64 {
+        self.samples
+            .back()
+            .map(|(offset, _)| self.epoch + *offset as i64)
+            .unwrap_or(i64::MIN)
+    }
+}
+
+// Used both by the GUI trends and the server query API.
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq)]
+pub struct HistoryFilter {
+    pub device_id: usize,
+    pub channel: usize,
+    // Unix timestamps in milliseconds.
+    pub from: Option<i64>,
+    pub to: Option<i64>,
+}
+
+// The samples of a channel, as sent to websocket clients joining late.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ChannelSamples {
+    pub device_id: usize,
+    pub channel: usize,
+    pub samples: Vec<Sample>,
+}
+
+// The recent values of every channel, filled from the device reports.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct History {
+    channels: HashMap<(usize, usize), ChannelHistory>,
+}
+
+impl History {
+    // Records the channels of the device, which may only be those that
+    // changed. Each channel keeps as many samples as its `history_depth`.
+    pub fn record(&mut self, device: &Device, timestamp: i64) {
+        for channel in device.channels.iter().filter(|channel| channel.enabled) {
+            let history = self
+                .channels
+                .entry((device.id, channel.id))
+                .or_insert_with(|| ChannelHistory::new(channel.history_depth));
+            if history.depth != channel.history_depth {
+                history.set_depth(channel.history_depth);
+            }
+            history.push(timestamp, channel.value);
+        }
+    }
+
+    pub fn channel(&self, device_id: usize, channel: usize) -> Option<&ChannelHistory> {
+        self.channels.get(&(device_id, channel))
+    }
+
+    pub fn query(&self, device_id: usize, channel: usize, from: i64, to: i64) -> Vec<Sample> {
+        self.channel(device_id, channel)
+            .map(|history| history.query(from, to))
+            .unwrap_or_default()
+    }
+
+    pub fn filter(&self, filter: &HistoryFilter) -> Vec<Sample> {
+        self.query(
+            filter.device_id,
+            filter.channel,
+            filter.from.unwrap_or(i64::MIN),
+            filter.to.unwrap_or(i64::MAX),
+        )
+    }
+
+    pub fn prepend(&mut self, device_id: usize, channel: usize, depth: usize, older: &[Sample]) {
+        self.channels
+            .entry((device_id, channel))
+            .or_insert_with(|| ChannelHistory::new(depth))
+            .prepend(older);
+    }
+
+    // Every channel with samples between `from` and `to`.
+    pub fn backfill(&self, from: i64, to: i64) -> Vec<ChannelSamples> {
+        let mut backfill: Vec<ChannelSamples> = self
+            .channels
+            .iter()
+            .map(|((device_id, channel), history)| ChannelSamples {
+                device_id: *device_id,
+                channel: *channel,
+                samples: history.query(from, to),
+            })
+            .filter(|channel| !channel.samples.is_empty())
+            .collect();
+        backfill.sort_by_key(|channel| (channel.device_id, channel.channel));
+        backfill
+    }
+
+    // Forgets the channels of devices that are gone, e.g. after a project is loaded.
+    pub fn retain_devices(&mut self, devices: &[Device]) {
+        self.channels.retain(|(device_id, channel), _| {
+            matches!(devices.get(*device_id), Some(device) if *channel < device.channels.len())
+        });
+    }
+}
+
+pub fn now_millis() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|time| time.as_millis() as i64)
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ChannelHistory, History, Sample};
+    use crate::Device;
+
+    #[test]
+    fn history_test() {
+        let mut history = ChannelHistory::new(3);
+        for (timestamp, value) in [(1000, 1.0), (2000, 2.0), (3000, 3.0), (4000, 4.0)] {
+            history.push(timestamp, value);
+        }
+        // The oldest sample made room, the out of order one is ignored.
+        history.push(3500, 9.0);
+        assert_eq!(history.len(), 3);
+        let values: Vec<f32> = history
+            .query(2500, 4000)
+            .iter()
+            .map(|sample| sample.value)
+            .collect();
+        assert_eq!(values, [3.0, 4.0]);
+
+        // Further than the offsets reach, only the new sample is left.
+        let later = 4000 + u32::MAX as i64 + 1;
+        history.push(later, 5.0);
+        assert_eq!(history.query(0, i64::MAX).len(), 1);
+        assert_eq!(history.query(later, later)[0].value, 5.0);
+        history.prepend(&[Sample {
+            timestamp: later - 10,
+            value: 6.0,
+        }]);
+        assert_eq!(history.query(0, i64::MAX)[0].value, 6.0);
+
+        let mut device = Device::default();
+        device.channels[1].enabled = true;
+        device.channels[1].value = 7.0;
+        device.channels[2].enabled = true;
+        device.channels[2].history_depth = 0;
+        let mut history = History::default();
+        history.record(&device, 10);
+        assert_eq!(history.query(0, 1, 0, 10)[0].value, 7.0);
+        assert_eq!(history.backfill(0, 10).len(), 1);
+        history.retain_devices(&[]);
+        assert!(history.backfill(0, 10).is_empty());
+    }
+}
@@ -1,39 +1,128 @@
 mod allen_bradley;
+mod bus;
 mod calculation;
 mod channel;
+mod client;
+mod clock_sync;
+mod condition;
 mod config;
+mod decode;
+mod discovery;
+mod event;
+mod heartbeat;
+mod history;
 mod logger_channel;
 mod modbus;
+mod probe;
+mod report;
+mod tag;
+mod traffic;
+mod transport;
 
-use std::{error::Error, fmt::Display};
+use std::{
+    collections::HashMap,
+    error::Error,
+    fmt::Display,
+    net::{SocketAddr, TcpStream},
+    time::Duration,
+};
 
 pub use allen_bradley::*;
+pub use bus::*;
 pub use calculation::*;
 pub use channel::*;
+pub use client::*;
+pub use clock_sync::*;
+pub use condition::*;
 pub use config::*;
+pub use decode::*;
+pub use discovery::*;
+pub use event::*;
+pub use heartbeat::*;
+pub use history::*;
 pub use logger_channel::*;
+pub use probe::*;
+pub use report::*;
 use serde::{Deserialize, Serialize};
-use tokio_modbus::{client::sync::Context, prelude::*};
-use tokio_serial;
+pub use tag::*;
+use tokio_modbus::prelude::*;
+pub use traffic::*;
 
+// The number of channels a new device starts with.
 const DEVICE_NUM_CHANNELS: usize = 20;
+// Seconds between two full-integrity reports of a device.
+const DEFAULT_INTEGRITY_RATE: u64 = 60;
+// How long the standby path of a redundant device gets to accept a connection.
+const HEALTH_CHECK_TIMEOUT: Duration = Duration::from_secs(1);
 
 #[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
 pub struct DataBlock {
     pub holding_regs: Vec<i16>,
 }
 
-#[derive(Deserialize, Clone, PartialEq)]
+#[derive(Deserialize, Clone, Default, PartialEq)]
 pub struct JsonWriteChannel {
     pub device_id: usize,
     pub channel: usize,
     pub value: f32,
+    // Echoed back in the write result so the client can match it.
+    #[serde(default)]
+    pub request_id: Option<String>,
+    #[serde(skip)]
+    pub origin: WriteOrigin,
+}
+
+// A write addressed by tag instead of device and channel position.
+#[derive(Deserialize, Clone, PartialEq)]
+pub struct JsonWriteTag {
+    pub tag: String,
+    pub value: f32,
+    #[serde(default)]
+    pub request_id: Option<String>,
+}
+
+// Confirms or cancels the write waiting on a channel with `confirm` set.
+#[derive(Deserialize, Clone, PartialEq)]
+pub struct JsonConfirmWrite {
+    pub device_id: usize,
+    pub channel: usize,
+    pub confirm: bool,
+}
+
+// An alarm acknowledgement coming from the GUI or an HMI client.
+// Without an alarm type, every alarm of the channel is acknowledged.
+#[derive(Deserialize, Clone, PartialEq)]
+pub struct JsonAckAlarm {
+    pub device_id: usize,
+    pub channel: usize,
+    #[serde(default)]
+    pub alarm_type: Option<AlarmType>,
+    pub user: String,
+}
+
+// Shelves alarms for `duration` seconds, a duration of 0 unshelves them.
+// Without an alarm type, every alarm of the channel is shelved.
+#[derive(Deserialize, Clone, PartialEq)]
+pub struct JsonShelveAlarm {
+    pub device_id: usize,
+    pub channel: usize,
+    #[serde(default)]
+    pub alarm_type: Option<AlarmType>,
+    pub duration: u64,
+    pub user: String,
 }
 
 #[derive(Clone)]
 pub enum DeviceMsg {
     Reconnect(DeviceConfig),
     WriteChannel(JsonWriteChannel),
+    ConfirmWrite(JsonConfirmWrite),
+    AckAlarm(JsonAckAlarm),
+    ShelveAlarm(JsonShelveAlarm),
+    // Writes the clock of the device right away.
+    SyncClock,
+    // Ends the worker, used when the device list changes.
+    Stop,
 }
 
 #[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq)]
@@ -53,6 +142,25 @@ pub struct Device {
     pub data_block: DataBlock,
     pub scan_rate: u64,
     pub status: String,
+    #[serde(default = "default_integrity_rate")]
+    pub integrity_rate: u64,
+    // The endpoint the worker last connected through, always primary for
+    // devices without a secondary.
+    #[serde(default)]
+    pub active_path: ConnectionPath,
+    #[serde(default)]
+    pub heartbeat: HeartbeatConfig,
+    #[serde(default)]
+    pub watchdog: WatchdogConfig,
+    #[serde(default)]
+    pub clock_sync: ClockSyncConfig,
+    // Set by the worker while the watchdog alarm is raised.
+    #[serde(skip)]
+    pub heartbeat_lost: bool,
+}
+
+fn default_integrity_rate() -> u64 {
+    DEFAULT_INTEGRITY_RATE
 }
 
 impl Device {
@@ -75,13 +183,19 @@ impl Device {
             data_block,
             scan_rate,
             status,
+            integrity_rate: DEFAULT_INTEGRITY_RATE,
+            active_path: ConnectionPath::Primary,
+            heartbeat: HeartbeatConfig::default(),
+            watchdog: WatchdogConfig::default(),
+            clock_sync: ClockSyncConfig::default(),
+            heartbeat_lost: false,
         }
     }
     pub fn initialize(id: usize, name: String) -> Self {
         let mut channels = Vec::new();
         let config = DeviceConfig::Tcp(TcpConfig {
             address: "127.0.0.1".to_owned(),
-            port: 502,
+            ..Default::default()
         });
         let data_block = DataBlock {
             holding_regs: Vec::new(),
@@ -103,28 +217,234 @@ impl Device {
             data_block,
             status: "Initialized".to_owned(),
             scan_rate: 1,
+            integrity_rate: DEFAULT_INTEGRITY_RATE,
+            active_path: ConnectionPath::Primary,
+            heartbeat: HeartbeatConfig::default(),
+            watchdog: WatchdogConfig::default(),
+            clock_sync: ClockSyncConfig::default(),
+            heartbeat_lost: false,
         }
     }
     // To be replaced with a DOP function.
-    pub fn connect(&mut self) -> Result<Context, Box<dyn Error>> {
-        let ctx = match &self.config {
+    // Redundant devices fall back to their secondary when the primary fails,
+    // serial devices go through the bus of their port.
+    pub fn connect(&mut self, buses: &BusManager) -> Result<Box<dyn ModbusClient>, Box<dyn Error>> {
+        match self.connect_path(ConnectionPath::Primary, buses) {
+            Ok(ctx) => Ok(ctx),
+            Err(e) if self.config.is_redundant() => self
+                .connect_path(ConnectionPath::Secondary, buses)
+                .map_err(|secondary| format!("primary: {}, secondary: {}", e, secondary).into()),
+            Err(e) => Err(e),
+        }
+    }
+    // Connects through one endpoint and makes it the active path.
+    pub fn connect_path(
+        &mut self,
+        path: ConnectionPath,
+        buses: &BusManager,
+    ) -> Result<Box<dyn ModbusClient>, Box<dyn Error>> {
+        let ctx: Box<dyn ModbusClient> = match &self.config {
             DeviceConfig::Tcp(config) => {
-                let address = config.address.to_owned();
-                let port = config.port;
-                let socket = format!("{}:{}", address, port).parse()?;
-                sync::tcp::connect(socket)?
-            }
-            DeviceConfig::Serial(config) => {
-                let path = config.com_port.to_owned();
-                let baudrate = config.baudrate;
-                let slave = Slave(config.slave);
-                let builder = tokio_serial::new(path, baudrate);
-                sync::rtu::connect_slave(&builder, slave)?
+                let socket = match config.endpoint(path) {
+                    Some(endpoint) => endpoint.parse()?,
+                    None => return Err("The device has no secondary endpoint".into()),
+                };
+                Box::new(sync::tcp::connect_slave(socket, Slave(config.unit))?)
             }
+            DeviceConfig::Serial(config) => Box::new(buses.client(config)?),
         };
+        self.active_path = path;
 
         Ok(ctx)
     }
+    // Whether an endpoint accepts connections, used on the path we're not polling through.
+    pub fn check_path(&self, path: ConnectionPath) -> bool {
+        let endpoint = match &self.config {
+            DeviceConfig::Tcp(config) => config.endpoint(path),
+            DeviceConfig::Serial(_) => None,
+        };
+        endpoint
+            .and_then(|endpoint| endpoint.parse::<SocketAddr>().ok())
+            .map_or(false, |address| {
+                TcpStream::connect_timeout(&address, HEALTH_CHECK_TIMEOUT).is_ok()
+            })
+    }
+    pub fn add_channel(&mut self) {
+        self.push_channel(Channel::default());
+    }
+    pub fn push_channel(&mut self, channel: Channel) {
+        self.channels.push(channel);
+        self.renumber_channels();
+    }
+    pub fn remove_channel(&mut self, index: usize) {
+        if index < self.channels.len() {
+            self.channels.remove(index);
+            self.renumber_channels();
+        }
+    }
+    // Inserts a copy of the channel right after it.
+    // The copy gets the first free tag of "_copy", "_copy2", ...
+    pub fn clone_channel(&mut self, index: usize, tags: &TagDatabase) {
+        if let Some(channel) = self.channels.get(index) {
+            let mut channel = channel.clone();
+            if !channel.tag.is_empty() {
+                let base = format!("{}_copy", channel.tag.trim());
+                let taken = |tag: &str| {
+                    tags.lookup(tag).is_some() || self.channels.iter().any(|c| c.tag.trim() == tag)
+                };
+                channel.tag = (1..)
+                    .map(|n| match n {
+                        1 => base.clone(),
+                        n => format!("{}{}", base, n),
+                    })
+                    .find(|tag| !taken(tag))
+                    .unwrap_or(base);
+            }
+            self.channels.insert(index + 1, channel);
+            self.renumber_channels();
+        }
+    }
+    pub fn move_channel(&mut self, from: usize, to: usize) {
+        if from < self.channels.len() && to < self.channels.len() {
+            let channel = self.channels.remove(from);
+            self.channels.insert(to, channel);
+            self.renumber_channels();
+        }
+    }
+    // Used by table imports, the channels keep their id until they're renumbered.
+    pub fn replace_channels(&mut self, channels: Vec<Channel>) {
+        self.channels = channels;
+        self.renumber_channels();
+    }
+    // Channel ids are their position in the device, so they're reassigned after
+    // every change. Deviation references of older projects follow the channel
+    // they pointed to until they're migrated.
+    fn renumber_channels(&mut self) {
+        let new_ids: HashMap<usize, usize> = self
+            .channels
+            .iter()
+            .enumerate()
+            // Reversed so a clone doesn't steal the references of its original.
+            .rev()
+            .map(|(index, channel)| (channel.id, index))
+            .collect();
+        for (index, channel) in self.channels.iter_mut().enumerate() {
+            channel.id = index;
+            channel.device_id = self.id;
+        }
+        for channel in self.channels.iter_mut() {
+            channel.alarm.deviation_reference = channel
+                .alarm
+                .deviation_reference
+                .and_then(|reference| new_ids.get(&reference).copied());
+        }
+    }
+    // Coils are not part of the holding register block,
+    // so every enabled Bool channel is read on its own.
+    pub fn read_coils(&mut self, ctx: &mut dyn ModbusClient) {
+        for channel in self
+            .channels
+            .iter_mut()
+            .filter(|channel| channel.enabled && channel.value_type == ValueType::BoolType)
+        {
+            channel.read_value(ctx);
+        }
+    }
+    // Finds a channel by tag, CH3 or D0:CH3 notation.
+    pub fn find_channel(&self, reference: &str) -> Option<&Channel> {
+        let reference = reference.trim();
+        let id = match reference.split_once(':') {
+            Some((device, channel)) if device == format!("D{}", self.id) => channel,
+            Some(_) => return None,
+            None => reference,
+        };
+        let by_id = id
+            .strip_prefix("CH")
+            .and_then(|id| id.parse::<usize>().ok())
+            .and_then(|id| self.channels.iter().find(|channel| channel.id == id));
+        by_id.or_else(|| {
+            self.channels
+                .iter()
+                .find(|channel| !channel.tag.is_empty() && channel.tag == reference)
+        })
+    }
+    // Evaluates the suppression condition of every channel and inhibits its
+    // alarms while it holds. Conditions can use any channel or calculation of
+    // the tags, errors end up in the channel status.
+    pub fn process_suppression(&mut self, tags: &TagDatabase) {
+        let mut results = Vec::new();
+        for channel in self.channels.iter().filter(|channel| channel.enabled) {
+            let suppress_when = channel.alarm.suppress_when.trim();
+            let result = match suppress_when.is_empty() {
+                true => Ok(false),
+                false => suppress_when
+                    .parse::<Condition>()
+                    .and_then(|condition| condition.evaluate_with_tags(self, tags)),
+            };
+            results.push((channel.id, result));
+        }
+        for (id, result) in results {
+            let channel = match self.channels.iter_mut().find(|channel| channel.id == id) {
+                Some(channel) => channel,
+                None => continue,
+            };
+            let suppressed = match result {
+                Ok(suppressed) => suppressed,
+                Err(e) => {
+                    channel.status = format!("Suppression error: {}", e);
+                    false
+                }
+            };
+            for alarm in channel.alarm.alarms_mut() {
+                alarm.suppressed = suppressed;
+                if alarm.is_inhibited() {
+                    alarm.inhibit();
+                }
+            }
+        }
+    }
+    // Compares every channel with a deviation alarm against its source. An
+    // unknown reference ends up in the channel status.
+    pub fn process_deviation_alarms(&mut self, tags: &TagDatabase) {
+        let mut sources = Vec::new();
+        for channel in self
+            .channels
+            .iter()
+            .filter(|channel| channel.enabled && channel.alarm.deviation.enabled)
+        {
+            let source = match &channel.alarm.deviation_source {
+                DeviationSource::None => continue,
+                DeviationSource::Reference(reference) => self
+                    .reference_value(reference, tags)
+                    .ok_or_else(|| format!("Unknown deviation reference: {}", reference)),
+                DeviationSource::Setpoint(setpoint) => Ok(*setpoint),
+            };
+            sources.push((channel.id, source));
+        }
+        for (id, source) in sources {
+            let channel = match self.channels.iter_mut().find(|channel| channel.id == id) {
+                Some(channel) => channel,
+                None => continue,
+            };
+            match source {
+                Ok(reference) => channel
+                    .alarm
+                    .deviation
+                    .process_alarm((channel.value - reference).abs()),
+                Err(e) => channel.status = e,
+            }
+        }
+    }
+    // A channel of this device, whose value is the freshest, or anything the
+    // tag database knows about on the other devices and the calculations.
+    pub fn reference_value(&self, reference: &str, tags: &TagDatabase) -> Option<f32> {
+        match self.find_channel(reference) {
+            Some(channel) => Some(channel.value),
+            None => tags
+                .resolve(reference)
+                .and_then(|address| tags.value(address)),
+        }
+    }
 }
 
 impl Default for Device {
@@ -132,7 +452,7 @@ impl Default for Device {
         let mut channels = Vec::new();
         let config = DeviceConfig::Tcp(TcpConfig {
             address: "127.0.0.1".to_owned(),
-            port: 502,
+            ..Default::default()
         });
 
         let data_block = DataBlock {
@@ -154,6 +474,12 @@ impl Default for Device {
             data_block,
             status: "Initialized".to_owned(),
             scan_rate: 1,
+            integrity_rate: DEFAULT_INTEGRITY_RATE,
+            active_path: ConnectionPath::Primary,
+            heartbeat: HeartbeatConfig::default(),
+            watchdog: WatchdogConfig::default(),
+            clock_sync: ClockSyncConfig::default(),
+            heartbeat_lost: false,
         }
     }
 }
@@ -164,6 +490,15 @@ impl Display for Device {
     }
 }
 
+// Device ids are their position in the list, which is also the index of
+// their worker, so they're reassigned after adding, removing or moving one.
+pub fn renumber_devices(devices: &mut [Device]) {
+    for (index, device) in devices.iter_mut().enumerate() {
+        device.id = index;
+        device.renumber_channels();
+    }
+}
+
 pub fn get_register_list(device: &Device) -> Vec<u16> {
     let mut register_list: Vec<u16> = Vec::new();
 
@@ -205,13 +540,14 @@ pub fn channel_values_from_buffer(
                 if edited_channel.enabled && edited_channel.index == *register {
                     match edited_channel.value_type {
                         ValueType::Int16 => {
-                            edited_channel.value = data_buffer[i] as f32;
+                            edited_channel.set_raw_value(data_buffer[i] as f32);
                         }
                         ValueType::Real32 => {
-                            let data_32bit_rep =
-                                ((data_buffer[i] as u32) << 16) | data_buffer[i + 1] as u32;
-                            let data_32_array = data_32bit_rep.to_ne_bytes();
-                            edited_channel.value = f32::from_ne_bytes(data_32_array);
+                            if let Ok(value) =
+                                decode(&data_buffer[i..], DataType::F32, WordOrder::Abcd)
+                            {
+                                edited_channel.set_raw_value(value as f32);
+                            }
                         }
                         _ => {}
                     }
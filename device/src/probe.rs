@@ -0,0 +1,349 @@
+use std::{
+    fmt::Display,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{channel, Receiver, Sender},
+        Arc,
+    },
+    thread,
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    decode,
+    transport::{Transport, READ_HOLDING_REGISTERS, READ_INPUT_REGISTERS},
+    Channel, DataType, DeviceConfig, ValueType, WordOrder,
+};
+
+// Registers asked for in one request, a block with an exception is then
+// probed one register at a time.
+const PROBE_BLOCK: u32 = 32;
+// Floats outside this range are more likely to be two unrelated registers.
+const PLAUSIBLE_FLOAT: (f64, f64) = (1e-3, 1e7);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RegisterTable {
+    Holding,
+    Input,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RegisterState {
+    Value(u16),
+    // The exception code the device answered with.
+    Exception(u8),
+    NoResponse,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ProbedRegister {
+    pub table: RegisterTable,
+    pub address: u16,
+    pub state: RegisterState,
+}
+
+// What the value starting at `address` most likely is.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TypeGuess {
+    pub table: RegisterTable,
+    pub address: u16,
+    pub data_type: DataType,
+    pub order: WordOrder,
+    pub value: f64,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ProbeEvent {
+    Progress { done: usize, total: usize },
+    Register(ProbedRegister),
+    Failed(String),
+    Finished,
+}
+
+// A probe running in its own thread. Dropping it cancels the probe.
+pub struct Probe {
+    pub receive: Receiver<ProbeEvent>,
+    cancel: Arc<AtomicBool>,
+}
+
+impl Probe {
+    // Walks every (table, first, last) range on its own connection to the device.
+    pub fn start(
+        config: DeviceConfig,
+        ranges: Vec<(RegisterTable, u16, u16)>,
+        timeout: Duration,
+    ) -> Self {
+        let (send, receive) = channel();
+        let cancel = Arc::new(AtomicBool::new(false));
+        let thread_cancel = Arc::clone(&cancel);
+        thread::spawn(move || {
+            let event = match probe_ranges(&config, &ranges, timeout, &send, &thread_cancel) {
+                Ok(_) => ProbeEvent::Finished,
+                Err(e) => ProbeEvent::Failed(e.to_string()),
+            };
+            let _ = send.send(event);
+        });
+        Self { receive, cancel }
+    }
+
+    pub fn cancel(&self) {
+        self.cancel.store(true, Ordering::Relaxed);
+    }
+}
+
+impl Drop for Probe {
+    fn drop(&mut self) {
+        self.cancel();
+    }
+}
+
+fn probe_ranges(
+    config: &DeviceConfig,
+    ranges: &[(RegisterTable, u16, u16)],
+    timeout: Duration,
+    send: &Sender<ProbeEvent>,
+    cancel: &AtomicBool,
+) -> anyhow::Result<()> {
+    let (mut transport, unit) = Transport::connect(config, timeout)?;
+    let total = ranges
+        .iter()
+        .map(|(_, first, last)| (*last as usize + 1).saturating_sub(*first as usize))
+        .sum();
+    let mut done = 0;
+    for (table, first, last) in ranges {
+        let mut start = *first as u32;
+        while start <= *last as u32 {
+            if cancel.load(Ordering::Relaxed) {
+                return Ok(());
+            }
+            let count = (*last as u32 + 1 - start).min(PROBE_BLOCK) as u16;
+            let states = match read(&mut transport, unit, *table, start as u16, count) {
+                Ok(Ok(values)) => values.into_iter().map(RegisterState::Value).collect(),
+                Ok(Err(_)) if count > 1 => (0..count)
+                    .map(|i| read_one(&mut transport, unit, *table, start as u16 + i))
+                    .collect(),
+                Ok(Err(code)) => vec![RegisterState::Exception(code)],
+                Err(_) => vec![RegisterState::NoResponse; count as usize],
+            };
+            for (i, state) in states.into_iter().enumerate() {
+                let register = ProbedRegister {
+                    table: *table,
+                    address: start as u16 + i as u16,
+                    state,
+                };
+                let _ = send.send(ProbeEvent::Register(register));
+            }
+            done += count as usize;
+            let _ = send.send(ProbeEvent::Progress { done, total });
+            start += count as u32;
+        }
+    }
+    Ok(())
+}
+
+fn read_one(
+    transport: &mut Transport,
+    unit: u8,
+    table: RegisterTable,
+    address: u16,
+) -> RegisterState {
+    match read(transport, unit, table, address, 1) {
+        Ok(Ok(values)) => RegisterState::Value(values[0]),
+        Ok(Err(code)) => RegisterState::Exception(code),
+        Err(_) => RegisterState::NoResponse,
+    }
+}
+
+// The inner error is the exception code of the device.
+fn read(
+    transport: &mut Transport,
+    unit: u8,
+    table: RegisterTable,
+    address: u16,
+    count: u16,
+) -> anyhow::Result<Result<Vec<u16>, u8>> {
+    let function = match table {
+        RegisterTable::Holding => READ_HOLDING_REGISTERS,
+        RegisterTable::Input => READ_INPUT_REGISTERS,
+    };
+    let mut request = vec![function];
+    request.extend(address.to_be_bytes());
+    request.extend(count.to_be_bytes());
+    let response = transport.request(unit, &request)?;
+    match response.as_slice() {
+        [code, exception] if *code == function | 0x80 => Ok(Err(*exception)),
+        [code, length, data @ ..]
+            if *code == function
+                && *length as usize == data.len()
+                && data.len() == count as usize * 2 =>
+        {
+            Ok(Ok(data
+                .chunks(2)
+                .map(|word| u16::from_be_bytes([word[0], word[1]]))
+                .collect()))
+        }
+        _ => anyhow::bail!("Unexpected response"),
+    }
+}
+
+// Pairs of registers that make a plausible float are guessed as f32, the
+// others as 16 bit integers, negative when close to 0xFFFF.
+pub fn guess_types(registers: &[ProbedRegister]) -> Vec<TypeGuess> {
+    let mut values: Vec<(RegisterTable, u16, u16)> = registers
+        .iter()
+        .filter_map(|register| match register.state {
+            RegisterState::Value(value) => Some((register.table, register.address, value)),
+            _ => None,
+        })
+        .collect();
+    values.sort_by_key(|(table, address, _)| (*table as u8, *address));
+
+    let mut guesses = Vec::new();
+    let mut i = 0;
+    while i < values.len() {
+        let (table, address, value) = values[i];
+        let pair = values
+            .get(i + 1)
+            .filter(|next| next.0 == table && next.1 == address.wrapping_add(1))
+            .map(|next| [value, next.2]);
+        let float = pair.and_then(|pair| {
+            [WordOrder::Abcd, WordOrder::Cdab]
+                .into_iter()
+                .find_map(|order| plausible_float(pair, order).map(|value| (order, value)))
+        });
+        let guess = match float {
+            Some((order, value)) => TypeGuess {
+                table,
+                address,
+                data_type: DataType::F32,
+                order,
+                value,
+            },
+            None => {
+                let data_type = match value >= 0xFF00 {
+                    true => DataType::I16,
+                    false => DataType::U16,
+                };
+                TypeGuess {
+                    table,
+                    address,
+                    data_type,
+                    order: WordOrder::Abcd,
+                    value: decode(&[value], data_type, WordOrder::Abcd).unwrap_or_default(),
+                }
+            }
+        };
+        i += guess.data_type.registers();
+        guesses.push(guess);
+    }
+    guesses
+}
+
+fn plausible_float(registers: [u16; 2], order: WordOrder) -> Option<f64> {
+    // Two zero registers are more likely two integers.
+    if registers == [0, 0] {
+        return None;
+    }
+    let value = decode(&registers, DataType::F32, order).ok()?;
+    let magnitude = value.abs();
+    let plausible = (value as f32).is_normal()
+        && magnitude >= PLAUSIBLE_FLOAT.0
+        && magnitude <= PLAUSIBLE_FLOAT.1;
+    match plausible {
+        true => Some(value),
+        false => None,
+    }
+}
+
+impl TypeGuess {
+    // Channels only read holding registers as 16 bit integers or ABCD floats.
+    pub fn to_channel(&self) -> Option<Channel> {
+        if self.table != RegisterTable::Holding {
+            return None;
+        }
+        let value_type = match (self.data_type, self.order) {
+            (DataType::U16 | DataType::I16, _) => ValueType::Int16,
+            (DataType::F32, WordOrder::Abcd) => ValueType::Real32,
+            _ => return None,
+        };
+        Some(Channel {
+            index: self.address,
+            value_type,
+            enabled: true,
+            description: format!("Probed as {}", self.data_type),
+            ..Default::default()
+        })
+    }
+}
+
+impl Display for RegisterTable {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RegisterTable::Holding => write!(f, "Holding"),
+            RegisterTable::Input => write!(f, "Input"),
+        }
+    }
+}
+
+impl Display for RegisterState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RegisterState::Value(value) => write!(f, "0x{:04X}", value),
+            RegisterState::Exception(code) => write!(f, "Exception {}", code),
+            RegisterState::NoResponse => write!(f, "No response"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{guess_types, ProbedRegister, RegisterState, RegisterTable};
+    use crate::{DataType, ValueType, WordOrder};
+
+    #[test]
+    fn guess_types_test() {
+        let register = |address, state| ProbedRegister {
+            table: RegisterTable::Holding,
+            address,
+            state,
+        };
+        let registers = [
+            // 123.456 in ABCD, then 10.0 in CDAB.
+            register(0, RegisterState::Value(0x42F6)),
+            register(1, RegisterState::Value(0xE979)),
+            register(2, RegisterState::Value(0x0000)),
+            register(3, RegisterState::Value(0x4120)),
+            register(4, RegisterState::Value(0xFFFE)),
+            register(5, RegisterState::Exception(2)),
+            register(6, RegisterState::Value(150)),
+            register(7, RegisterState::Value(200)),
+        ];
+        let guesses = guess_types(&registers);
+        let summary: Vec<(u16, DataType, WordOrder)> = guesses
+            .iter()
+            .map(|guess| (guess.address, guess.data_type, guess.order))
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                (0, DataType::F32, WordOrder::Abcd),
+                (2, DataType::F32, WordOrder::Cdab),
+                (4, DataType::I16, WordOrder::Abcd),
+                (6, DataType::U16, WordOrder::Abcd),
+                (7, DataType::U16, WordOrder::Abcd),
+            ]
+        );
+        assert_eq!(guesses[2].value, -2.0);
+        assert_eq!(guesses[1].value, 10.0);
+
+        assert_eq!(
+            guesses[0].to_channel().unwrap().value_type,
+            ValueType::Real32
+        );
+        assert_eq!(guesses[3].to_channel().unwrap().index, 6);
+        // There's no CDAB channel.
+        assert!(guesses[1].to_channel().is_none());
+    }
+}
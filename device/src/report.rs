@@ -0,0 +1,172 @@
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use crate::{AlarmState, Channel, Device, Event, WriteResult};
+
+// What a device worker hands over to the main thread after a poll cycle.
+// The device is always sent whole so the GUI stays in sync, `changed` only
+// lists the channels that moved outside of their deadband.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DeviceReport {
+    pub device: Device,
+    pub changed: Vec<usize>,
+    pub integrity: bool,
+    pub events: Vec<Event>,
+    // The results of the writes handled since the last report.
+    pub writes: Vec<WriteResult>,
+}
+
+impl DeviceReport {
+    // The channels that should be published for this report.
+    pub fn channels_to_publish(&self) -> Vec<Channel> {
+        self.device
+            .channels
+            .iter()
+            .filter(|channel| self.integrity || self.changed.contains(&channel.id))
+            .cloned()
+            .collect()
+    }
+
+    // The device with only the channels to publish.
+    pub fn published_device(&self) -> Device {
+        let mut device = self.device.clone();
+        device.channels = self.channels_to_publish();
+        device
+    }
+}
+
+pub struct ExceptionReporter {
+    last_values: HashMap<usize, f32>,
+    last_alarms: HashMap<usize, Vec<AlarmState>>,
+    last_status: Option<String>,
+    last_integrity: Option<Instant>,
+}
+
+impl ExceptionReporter {
+    pub fn new() -> Self {
+        Self {
+            last_values: HashMap::new(),
+            last_alarms: HashMap::new(),
+            last_status: None,
+            last_integrity: None,
+        }
+    }
+
+    // Compares the freshly polled device against what was last published.
+    // Returns None when nothing changed, no integrity refresh is due and
+    // there are no events or write results to forward.
+    pub fn report(
+        &mut self,
+        device: &Device,
+        events: Vec<Event>,
+        writes: Vec<WriteResult>,
+    ) -> Option<DeviceReport> {
+        let integrity = match self.last_integrity {
+            Some(time) => time.elapsed() >= Duration::from_secs(device.integrity_rate),
+            None => true,
+        };
+
+        let changed: Vec<usize> = device
+            .channels
+            .iter()
+            .filter(|channel| channel.enabled)
+            .filter(|channel| {
+                let value_changed = match self.last_values.get(&channel.id) {
+                    Some(last) => channel.deadband.exceeded(*last, channel.value),
+                    None => true,
+                };
+                // Alarm transitions are always reported, whatever the deadband.
+                let alarm_changed =
+                    self.last_alarms.get(&channel.id) != Some(&channel.alarm.states());
+                value_changed || alarm_changed
+            })
+            .map(|channel| channel.id)
+            .collect();
+
+        let status_changed = self.last_status.as_ref() != Some(&device.status);
+
+        if !integrity
+            && !status_changed
+            && changed.is_empty()
+            && events.is_empty()
+            && writes.is_empty()
+        {
+            return None;
+        }
+
+        for channel in &device.channels {
+            if integrity || changed.contains(&channel.id) {
+                self.last_values.insert(channel.id, channel.value);
+                self.last_alarms.insert(channel.id, channel.alarm.states());
+            }
+        }
+        if integrity {
+            self.last_integrity = Some(Instant::now());
+        }
+        self.last_status = Some(device.status.clone());
+
+        Some(DeviceReport {
+            device: device.clone(),
+            changed,
+            integrity,
+            events,
+            writes,
+        })
+    }
+}
+
+impl Default for ExceptionReporter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ExceptionReporter;
+    use crate::{Deadband, DeadbandType, Device};
+
+    #[test]
+    fn first_report_is_integrity_test() {
+        let device = Device::default();
+        let mut reporter = ExceptionReporter::new();
+        let report = reporter.report(&device, Vec::new(), Vec::new()).unwrap();
+        assert!(report.integrity);
+        assert_eq!(report.channels_to_publish().len(), device.channels.len());
+        // Nothing changed since.
+        assert!(reporter.report(&device, Vec::new(), Vec::new()).is_none());
+    }
+
+    #[test]
+    fn deadband_report_test() {
+        let mut device = Device::default();
+        device.channels[0].enabled = true;
+        device.channels[0].deadband = Deadband {
+            deadband_type: DeadbandType::Absolute,
+            value: 1.0,
+        };
+        let mut reporter = ExceptionReporter::new();
+        reporter.report(&device, Vec::new(), Vec::new());
+
+        device.channels[0].value = 0.5;
+        assert!(reporter.report(&device, Vec::new(), Vec::new()).is_none());
+        device.channels[0].value = 1.5;
+        let report = reporter.report(&device, Vec::new(), Vec::new()).unwrap();
+        assert!(!report.integrity);
+        assert_eq!(report.changed, [0]);
+        assert_eq!(report.published_device().channels.len(), 1);
+    }
+
+    #[test]
+    fn status_change_report_test() {
+        let mut device = Device::default();
+        let mut reporter = ExceptionReporter::new();
+        reporter.report(&device, Vec::new(), Vec::new());
+
+        device.status = "Error: timed out".to_owned();
+        let report = reporter.report(&device, Vec::new(), Vec::new()).unwrap();
+        assert!(report.changed.is_empty());
+    }
+}
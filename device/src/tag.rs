@@ -0,0 +1,382 @@
+use std::{
+    collections::HashMap,
+    sync::mpsc::{channel, Receiver, Sender},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{AccessType, Calculation, Device, JsonWriteChannel};
+
+// Where a tag points to. Tags are unique across every device and calculation.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum TagAddress {
+    Channel { device_id: usize, channel: usize },
+    // The id of the calculation, as in EVAL3.
+    Calculation(usize),
+}
+
+// What subscribers receive whenever the value of their tag changes.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TagValue {
+    pub tag: String,
+    pub address: TagAddress,
+    pub value: f32,
+}
+
+// A tag given to more than one channel or calculation.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DuplicateTag {
+    pub tag: String,
+    // Every place the tag is used, the first one being the one it resolves to.
+    pub addresses: Vec<TagAddress>,
+}
+
+#[derive(Clone, Debug)]
+struct TagEntry {
+    address: TagAddress,
+    value: f32,
+    writable: bool,
+}
+
+// Name-based access to channels and calculations, so clients don't depend on
+// positions that shift when the configuration changes.
+#[derive(Default)]
+pub struct TagDatabase {
+    entries: HashMap<String, TagEntry>,
+    subscribers: HashMap<String, Vec<Sender<TagValue>>>,
+    duplicates: Vec<DuplicateTag>,
+    // Untagged channels are only reachable by their address.
+    values: HashMap<TagAddress, f32>,
+}
+
+// Every tag with what it points to, channels first. Untagged ones have an
+// empty tag.
+fn all_entries<'a>(
+    devices: &'a [Device],
+    calculations: &'a [Calculation],
+) -> impl Iterator<Item = (&'a str, TagEntry)> {
+    let channels = devices
+        .iter()
+        .flat_map(|device| device.channels.iter())
+        .map(|channel| {
+            let entry = TagEntry {
+                address: TagAddress::Channel {
+                    device_id: channel.device_id,
+                    channel: channel.id,
+                },
+                value: channel.value,
+                writable: channel.access_type == AccessType::Write,
+            };
+            (channel.tag.trim(), entry)
+        });
+    let calculations = calculations.iter().map(|calculation| {
+        let entry = TagEntry {
+            address: TagAddress::Calculation(calculation.id),
+            value: calculation.value,
+            writable: false,
+        };
+        (calculation.tag.trim(), entry)
+    });
+    channels.chain(calculations)
+}
+
+fn tagged_entries<'a>(
+    devices: &'a [Device],
+    calculations: &'a [Calculation],
+) -> impl Iterator<Item = (&'a str, TagEntry)> {
+    all_entries(devices, calculations).filter(|(tag, _)| !tag.is_empty())
+}
+
+// Every tag used more than once, in the order they're first used.
+pub fn duplicate_tags(devices: &[Device], calculations: &[Calculation]) -> Vec<DuplicateTag> {
+    let mut uses: Vec<DuplicateTag> = Vec::new();
+    let mut index: HashMap<&str, usize> = HashMap::new();
+    for (tag, entry) in tagged_entries(devices, calculations) {
+        match index.get(tag) {
+            Some(i) => uses[*i].addresses.push(entry.address),
+            None => {
+                index.insert(tag, uses.len());
+                uses.push(DuplicateTag {
+                    tag: tag.to_owned(),
+                    addresses: vec![entry.address],
+                });
+            }
+        }
+    }
+    uses.retain(|duplicate| duplicate.addresses.len() > 1);
+    uses
+}
+
+// Fails with every duplicate tag, used when a project is loaded.
+pub fn check_tags(devices: &[Device], calculations: &[Calculation]) -> anyhow::Result<()> {
+    let duplicates = duplicate_tags(devices, calculations);
+    if !duplicates.is_empty() {
+        let duplicates: Vec<String> = duplicates.iter().map(|d| d.to_string()).collect();
+        anyhow::bail!("Duplicate tags: {}", duplicates.join(", "));
+    }
+    Ok(())
+}
+
+impl TagDatabase {
+    pub fn new(devices: &[Device], calculations: &[Calculation]) -> Self {
+        let mut tags = TagDatabase::default();
+        tags.update(devices, calculations);
+        tags
+    }
+
+    // Re-indexes the tags and notifies the subscribers of every tag whose value
+    // or address changed. When a tag is used twice the first one wins, the
+    // others are listed in `duplicates`.
+    pub fn update(&mut self, devices: &[Device], calculations: &[Calculation]) {
+        let mut entries: HashMap<String, TagEntry> = HashMap::new();
+        let mut duplicated = false;
+        self.values.clear();
+        for (tag, entry) in all_entries(devices, calculations) {
+            self.values.insert(entry.address, entry.value);
+            if tag.is_empty() {
+                continue;
+            }
+            match entries.contains_key(tag) {
+                true => duplicated = true,
+                false => {
+                    entries.insert(tag.to_owned(), entry);
+                }
+            }
+        }
+        self.duplicates = match duplicated {
+            true => duplicate_tags(devices, calculations),
+            false => Vec::new(),
+        };
+
+        for (tag, entry) in &entries {
+            let changed = self.entries.get(tag).map_or(true, |previous| {
+                previous.address != entry.address || previous.value != entry.value
+            });
+            if changed {
+                self.notify(tag, entry);
+            }
+        }
+        self.entries = entries;
+    }
+
+    fn notify(&mut self, tag: &str, entry: &TagEntry) {
+        if let Some(subscribers) = self.subscribers.get_mut(tag) {
+            let value = TagValue {
+                tag: tag.to_owned(),
+                address: entry.address,
+                value: entry.value,
+            };
+            // Dropped receivers are unsubscribed.
+            subscribers.retain(|subscriber| subscriber.send(value.clone()).is_ok());
+        }
+    }
+
+    pub fn duplicates(&self) -> &[DuplicateTag] {
+        &self.duplicates
+    }
+
+    pub fn lookup(&self, tag: &str) -> Option<TagAddress> {
+        self.entries.get(tag.trim()).map(|entry| entry.address)
+    }
+
+    // Finds a tag, or a channel or calculation in D0:CH3 or EVAL3 notation.
+    pub fn resolve(&self, reference: &str) -> Option<TagAddress> {
+        let reference = reference.trim();
+        if let Some(address) = self.lookup(reference) {
+            return Some(address);
+        }
+        if let Some(id) = reference.strip_prefix("EVAL") {
+            return id.parse().ok().map(TagAddress::Calculation);
+        }
+        let (device, channel) = reference.split_once(':')?;
+        let device_id = device.strip_prefix('D')?.parse().ok()?;
+        let channel = channel.strip_prefix("CH")?.parse().ok()?;
+        Some(TagAddress::Channel { device_id, channel })
+    }
+
+    // The value of any channel or calculation, tagged or not.
+    pub fn value(&self, address: TagAddress) -> Option<f32> {
+        self.values.get(&address).copied()
+    }
+
+    pub fn read(&self, tag: &str) -> Option<f32> {
+        self.entries.get(tag.trim()).map(|entry| entry.value)
+    }
+
+    // The receiver gets the current value right away, then every change.
+    pub fn subscribe(&mut self, tag: &str) -> anyhow::Result<Receiver<TagValue>> {
+        let tag = tag.trim();
+        let entry = match self.entries.get(tag) {
+            Some(entry) => entry,
+            None => anyhow::bail!("Unknown tag: {}", tag),
+        };
+        let (send, receive) = channel();
+        let value = TagValue {
+            tag: tag.to_owned(),
+            address: entry.address,
+            value: entry.value,
+        };
+        // Can't fail, the receiver is still in hand.
+        let _ = send.send(value);
+        self.subscribers
+            .entry(tag.to_owned())
+            .or_default()
+            .push(send);
+        Ok(receive)
+    }
+
+    // Turns a write by tag into the request the device worker understands.
+    pub fn write(&self, tag: &str, value: f32) -> anyhow::Result<JsonWriteChannel> {
+        let tag = tag.trim();
+        let entry = match self.entries.get(tag) {
+            Some(entry) => entry,
+            None => anyhow::bail!("Unknown tag: {}", tag),
+        };
+        match entry.address {
+            TagAddress::Channel { device_id, channel } if entry.writable => Ok(JsonWriteChannel {
+                device_id,
+                channel,
+                value,
+                ..Default::default()
+            }),
+            TagAddress::Channel { .. } => anyhow::bail!("{} is read only", tag),
+            TagAddress::Calculation(_) => anyhow::bail!("{} is a calculation", tag),
+        }
+    }
+
+    // Fails when the tag is already used somewhere other than `address`.
+    pub fn check_unique(&self, tag: &str, address: TagAddress) -> anyhow::Result<()> {
+        match self.lookup(tag) {
+            Some(existing) if existing != address => {
+                anyhow::bail!("The tag {} is already used by {}", tag.trim(), existing)
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+impl std::fmt::Display for DuplicateTag {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let addresses: Vec<String> = self.addresses.iter().map(|a| a.to_string()).collect();
+        write!(f, "{} ({})", self.tag, addresses.join(", "))
+    }
+}
+
+impl std::fmt::Display for TagAddress {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TagAddress::Channel { device_id, channel } => write!(f, "D{}:CH{}", device_id, channel),
+            TagAddress::Calculation(id) => write!(f, "EVAL{}", id),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{check_tags, duplicate_tags, TagAddress, TagDatabase};
+    use crate::{AccessType, Calculation, Device};
+
+    #[test]
+    fn lookup_test() {
+        let mut device = Device::default();
+        device.channels[2].tag = "Pump1".to_owned();
+        device.channels[5].tag = "Pump1".to_owned();
+        device.channels[6].tag = "Level".to_owned();
+        let tags = TagDatabase::new(&[device], &[]);
+
+        // The second Pump1 is ignored.
+        let pump = TagAddress::Channel {
+            device_id: 0,
+            channel: 2,
+        };
+        assert_eq!(tags.lookup("Pump1"), Some(pump));
+        assert_eq!(tags.duplicates()[0].tag, "Pump1");
+        assert!(tags.check_unique("Pump1", pump).is_ok());
+        assert!(tags.check_unique("Level", pump).is_err());
+    }
+
+    #[test]
+    fn resolve_test() {
+        let calculation = Calculation {
+            id: 1,
+            tag: "Total".to_owned(),
+            ..Default::default()
+        };
+        let tags = TagDatabase::new(&[Device::default()], &[calculation]);
+        assert_eq!(tags.resolve("Total"), Some(TagAddress::Calculation(1)));
+        assert_eq!(tags.resolve("EVAL1"), Some(TagAddress::Calculation(1)));
+        assert_eq!(
+            tags.resolve("D0:CH4").map(|a| a.to_string()),
+            Some("D0:CH4".to_owned())
+        );
+        // Untagged channels only have a value.
+        let untagged = TagAddress::Channel {
+            device_id: 0,
+            channel: 4,
+        };
+        assert_eq!(tags.value(untagged), Some(0.0));
+    }
+
+    #[test]
+    fn write_test() {
+        let mut device = Device::default();
+        device.channels[2].tag = "Pump1".to_owned();
+        device.channels[2].access_type = AccessType::Write;
+        device.channels[6].tag = "Level".to_owned();
+        let calculation = Calculation {
+            id: 1,
+            tag: "Total".to_owned(),
+            ..Default::default()
+        };
+        let tags = TagDatabase::new(&[device], &[calculation]);
+
+        let write = tags.write("Pump1", 1.0).unwrap();
+        assert_eq!((write.device_id, write.channel), (0, 2));
+        assert!(tags.write("Level", 1.0).is_err());
+        assert!(tags.write("Total", 1.0).is_err());
+    }
+
+    #[test]
+    fn subscribe_test() {
+        let mut device = Device::default();
+        device.channels[6].tag = "Level".to_owned();
+        let mut tags = TagDatabase::new(std::slice::from_ref(&device), &[]);
+
+        let level = tags.subscribe("Level").unwrap();
+        assert_eq!(level.try_recv().unwrap().value, 0.0);
+        device.channels[6].value = 4.5;
+        tags.update(std::slice::from_ref(&device), &[]);
+        assert_eq!(tags.read("Level"), Some(4.5));
+        assert_eq!(level.try_recv().unwrap().value, 4.5);
+        // Nothing changed, nothing is sent.
+        tags.update(&[device], &[]);
+        assert!(level.try_recv().is_err());
+    }
+
+    #[test]
+    fn duplicate_tags_test() {
+        let mut devices = [Device::default(), Device::default()];
+        devices[1].id = 1;
+        for channel in devices[1].channels.iter_mut() {
+            channel.device_id = 1;
+        }
+        devices[0].channels[1].tag = "Flow".to_owned();
+        devices[1].channels[3].tag = " Flow ".to_owned();
+        devices[1].channels[4].tag = "Level".to_owned();
+        let calculations = [Calculation {
+            id: 2,
+            tag: "Flow".to_owned(),
+            ..Default::default()
+        }];
+        assert!(check_tags(&devices, &[]).is_err());
+
+        let duplicates = duplicate_tags(&devices, &calculations);
+        assert_eq!(duplicates.len(), 1);
+        assert_eq!(duplicates[0].to_string(), "Flow (D0:CH1, D1:CH3, EVAL2)");
+
+        // Empty tags are never duplicates.
+        devices[1].channels[3].tag.clear();
+        assert!(check_tags(&devices, &[]).is_ok());
+        assert!(duplicate_tags(&devices, &[]).is_empty());
+    }
+}
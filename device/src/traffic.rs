@@ -0,0 +1,409 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt::Display,
+    io::{self, Write},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use crate::{now_millis, ModbusClient};
+
+// Frames kept per device, the oldest make room for new ones.
+const TRAFFIC_LOG_SIZE: usize = 5000;
+// Upper bounds of the latency histogram buckets, in milliseconds. The last
+// bucket holds everything slower.
+pub const LATENCY_BUCKETS: [u64; 10] = [1, 2, 5, 10, 20, 50, 100, 200, 500, 1000];
+
+// One request and what came back. Frames are kept as PDUs, rebuilt from
+// what the client sent and returned, so they look the same for TCP and RTU.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TrafficRecord {
+    // Unix timestamp in milliseconds.
+    pub timestamp: i64,
+    pub device_id: usize,
+    pub request: Vec<u8>,
+    // The response PDU, or why there's none.
+    pub response: Result<Vec<u8>, String>,
+    pub duration: Duration,
+}
+
+impl TrafficRecord {
+    pub fn function(&self) -> u8 {
+        self.request.first().copied().unwrap_or_default()
+    }
+
+    // The function, and the exception or error it ended with.
+    pub fn describe(&self) -> String {
+        match &self.response {
+            Ok(_) => function_name(self.function()).to_owned(),
+            Err(e) => format!("{}: {}", function_name(self.function()), e),
+        }
+    }
+}
+
+impl Display for TrafficRecord {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let response = match &self.response {
+            Ok(response) => hex(response),
+            Err(e) => e.clone(),
+        };
+        write!(
+            f,
+            "{} -> {} ({} µs)",
+            hex(&self.request),
+            response,
+            self.duration.as_micros()
+        )
+    }
+}
+
+pub fn function_name(code: u8) -> &'static str {
+    match code & 0x7F {
+        0x01 => "Read coils",
+        0x02 => "Read discrete inputs",
+        0x03 => "Read holding registers",
+        0x04 => "Read input registers",
+        0x05 => "Write single coil",
+        0x06 => "Write single register",
+        0x0F => "Write multiple coils",
+        0x10 => "Write multiple registers",
+        0x2B => "Encapsulated interface",
+        _ => "Unknown function",
+    }
+}
+
+pub fn exception_name(code: u8) -> &'static str {
+    match code {
+        0x01 => "Illegal function",
+        0x02 => "Illegal data address",
+        0x03 => "Illegal data value",
+        0x04 => "Server device failure",
+        0x05 => "Acknowledge",
+        0x06 => "Server device busy",
+        0x08 => "Memory parity error",
+        0x0A => "Gateway path unavailable",
+        0x0B => "Gateway target device failed to respond",
+        _ => "Unknown exception",
+    }
+}
+
+pub fn hex(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .map(|byte| format!("{:02X}", byte))
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct LatencyStats {
+    pub requests: usize,
+    // Requests that failed, exceptions included.
+    pub errors: usize,
+    pub min: Duration,
+    pub mean: Duration,
+    pub max: Duration,
+    // Requests per bucket of `LATENCY_BUCKETS`, plus the slower ones.
+    pub histogram: Vec<usize>,
+}
+
+impl LatencyStats {
+    pub fn new(records: &[TrafficRecord]) -> Self {
+        let mut histogram = vec![0; LATENCY_BUCKETS.len() + 1];
+        for record in records {
+            let millis = record.duration.as_millis() as u64;
+            let bucket = LATENCY_BUCKETS
+                .iter()
+                .position(|bound| millis < *bound)
+                .unwrap_or(LATENCY_BUCKETS.len());
+            histogram[bucket] += 1;
+        }
+        let durations = records.iter().map(|record| record.duration);
+        let total: Duration = durations.clone().sum();
+        Self {
+            requests: records.len(),
+            errors: records
+                .iter()
+                .filter(|record| record.response.is_err())
+                .count(),
+            min: durations.clone().min().unwrap_or_default(),
+            mean: total / records.len().max(1) as u32,
+            max: durations.max().unwrap_or_default(),
+            histogram,
+        }
+    }
+}
+
+// The traffic of the devices we capture, shared between their workers and
+// the diagnostics window. Devices are only captured once enabled.
+#[derive(Clone, Default)]
+pub struct TrafficMonitor {
+    logs: Arc<Mutex<HashMap<usize, VecDeque<TrafficRecord>>>>,
+}
+
+impl TrafficMonitor {
+    // Disabling the capture of a device drops what it recorded.
+    pub fn set_capture(&self, device_id: usize, enabled: bool) {
+        let mut logs = self.logs.lock().unwrap_or_else(|e| e.into_inner());
+        match enabled {
+            true => {
+                logs.entry(device_id).or_default();
+            }
+            false => {
+                logs.remove(&device_id);
+            }
+        }
+    }
+
+    pub fn is_capturing(&self, device_id: usize) -> bool {
+        let logs = self.logs.lock().unwrap_or_else(|e| e.into_inner());
+        logs.contains_key(&device_id)
+    }
+
+    pub fn clear(&self, device_id: usize) {
+        let mut logs = self.logs.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(log) = logs.get_mut(&device_id) {
+            log.clear();
+        }
+    }
+
+    // Oldest first.
+    pub fn records(&self, device_id: usize) -> Vec<TrafficRecord> {
+        let logs = self.logs.lock().unwrap_or_else(|e| e.into_inner());
+        logs.get(&device_id)
+            .map(|log| log.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    fn record(&self, record: TrafficRecord) {
+        let mut logs = self.logs.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(log) = logs.get_mut(&record.device_id) {
+            log.push_back(record);
+            if log.len() > TRAFFIC_LOG_SIZE {
+                log.pop_front();
+            }
+        }
+    }
+
+    // Every connection of a worker goes through this, the requests are only
+    // recorded while the capture of the device is enabled.
+    pub fn wrap(&self, client: Box<dyn ModbusClient>, device_id: usize) -> Box<dyn ModbusClient> {
+        Box::new(CapturingClient {
+            client,
+            monitor: self.clone(),
+            device_id,
+        })
+    }
+}
+
+struct CapturingClient {
+    client: Box<dyn ModbusClient>,
+    monitor: TrafficMonitor,
+    device_id: usize,
+}
+
+impl CapturingClient {
+    // Times the call and records it along with the response PDU built from its result.
+    fn capture<T>(
+        &mut self,
+        request: Vec<u8>,
+        call: impl FnOnce(&mut dyn ModbusClient) -> io::Result<T>,
+        response: impl FnOnce(&T) -> Vec<u8>,
+    ) -> io::Result<T> {
+        let start = Instant::now();
+        let result = call(self.client.as_mut());
+        let duration = start.elapsed();
+        self.monitor.record(TrafficRecord {
+            timestamp: now_millis(),
+            device_id: self.device_id,
+            request,
+            response: match &result {
+                Ok(value) => Ok(response(value)),
+                Err(e) => Err(e.to_string()),
+            },
+            duration,
+        });
+        result
+    }
+}
+
+// A function code followed by an address and a quantity or value.
+fn request_pdu(function: u8, address: u16, value: u16) -> Vec<u8> {
+    let mut request = vec![function];
+    request.extend(address.to_be_bytes());
+    request.extend(value.to_be_bytes());
+    request
+}
+
+impl ModbusClient for CapturingClient {
+    fn read_holding_registers(&mut self, address: u16, count: u16) -> io::Result<Vec<u16>> {
+        self.capture(
+            request_pdu(0x03, address, count),
+            |client| client.read_holding_registers(address, count),
+            |registers| {
+                let mut response = vec![0x03, (registers.len() * 2) as u8];
+                for register in registers {
+                    response.extend(register.to_be_bytes());
+                }
+                response
+            },
+        )
+    }
+
+    fn read_coils(&mut self, address: u16, count: u16) -> io::Result<Vec<bool>> {
+        self.capture(
+            request_pdu(0x01, address, count),
+            |client| client.read_coils(address, count),
+            |states| {
+                let mut bytes = vec![0; (states.len() + 7) / 8];
+                for (i, state) in states.iter().enumerate() {
+                    bytes[i / 8] |= (*state as u8) << (i % 8);
+                }
+                let mut response = vec![0x01, bytes.len() as u8];
+                response.extend(bytes);
+                response
+            },
+        )
+    }
+
+    fn write_single_register(&mut self, address: u16, value: u16) -> io::Result<()> {
+        let request = request_pdu(0x06, address, value);
+        let echo = request.clone();
+        self.capture(
+            request,
+            |client| client.write_single_register(address, value),
+            |_| echo,
+        )
+    }
+
+    fn write_multiple_registers(&mut self, address: u16, values: &[u16]) -> io::Result<()> {
+        let mut request = request_pdu(0x10, address, values.len() as u16);
+        let echo = request.clone();
+        request.push((values.len() * 2) as u8);
+        for value in values {
+            request.extend(value.to_be_bytes());
+        }
+        self.capture(
+            request,
+            |client| client.write_multiple_registers(address, values),
+            |_| echo,
+        )
+    }
+
+    fn write_single_coil(&mut self, address: u16, state: bool) -> io::Result<()> {
+        let value = match state {
+            true => 0xFF00,
+            false => 0x0000,
+        };
+        let request = request_pdu(0x05, address, value);
+        let echo = request.clone();
+        self.capture(
+            request,
+            |client| client.write_single_coil(address, state),
+            |_| echo,
+        )
+    }
+}
+
+// Writes the records as CSV, with a header row.
+pub fn export_traffic<W: Write>(writer: W, records: &[TrafficRecord]) -> anyhow::Result<()> {
+    let mut writer = csv::Writer::from_writer(writer);
+    writer.write_record([
+        "timestamp",
+        "device",
+        "function",
+        "request",
+        "response",
+        "duration_us",
+    ])?;
+    for record in records {
+        let response = match &record.response {
+            Ok(response) => hex(response),
+            Err(e) => e.clone(),
+        };
+        writer.write_record([
+            record.timestamp.to_string(),
+            record.device_id.to_string(),
+            record.describe(),
+            hex(&record.request),
+            response,
+            record.duration.as_micros().to_string(),
+        ])?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io, time::Duration};
+
+    use super::{LatencyStats, TrafficMonitor, TrafficRecord};
+    use crate::ModbusClient;
+
+    struct FakeClient;
+
+    impl ModbusClient for FakeClient {
+        fn read_holding_registers(&mut self, _: u16, count: u16) -> io::Result<Vec<u16>> {
+            Ok(vec![0x1234; count as usize])
+        }
+        fn read_coils(&mut self, _: u16, _: u16) -> io::Result<Vec<bool>> {
+            Err(io::Error::new(io::ErrorKind::TimedOut, "Timed out"))
+        }
+        fn write_single_register(&mut self, _: u16, _: u16) -> io::Result<()> {
+            Ok(())
+        }
+        fn write_multiple_registers(&mut self, _: u16, _: &[u16]) -> io::Result<()> {
+            Ok(())
+        }
+        fn write_single_coil(&mut self, _: u16, _: bool) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn capture_disabled_test() {
+        let monitor = TrafficMonitor::default();
+        let mut client = monitor.wrap(Box::new(FakeClient), 2);
+        client.read_holding_registers(100, 1).unwrap();
+        assert!(monitor.records(2).is_empty());
+    }
+
+    #[test]
+    fn capture_test() {
+        let monitor = TrafficMonitor::default();
+        let mut client = monitor.wrap(Box::new(FakeClient), 2);
+        monitor.set_capture(2, true);
+        client.read_holding_registers(100, 2).unwrap();
+        assert!(client.read_coils(0, 1).is_err());
+        let records = monitor.records(2);
+        assert_eq!(records[0].request, [0x03, 0x00, 0x64, 0x00, 0x02]);
+        assert_eq!(
+            records[0].response,
+            Ok(vec![0x03, 0x04, 0x12, 0x34, 0x12, 0x34])
+        );
+        assert_eq!(records[1].describe(), "Read coils: Timed out");
+    }
+
+    #[test]
+    fn latency_stats_test() {
+        let ok = TrafficRecord {
+            timestamp: 0,
+            device_id: 2,
+            request: vec![0x03, 0x00, 0x64, 0x00, 0x02],
+            response: Ok(vec![0x03, 0x04, 0x12, 0x34, 0x12, 0x34]),
+            duration: Duration::from_micros(1500),
+        };
+        let timed_out = TrafficRecord {
+            request: vec![0x01, 0x00, 0x00, 0x00, 0x01],
+            response: Err("Timed out".to_owned()),
+            duration: Duration::from_secs(2),
+            ..ok.clone()
+        };
+        let stats = LatencyStats::new(&[ok, timed_out]);
+        assert_eq!(stats.errors, 1);
+        assert_eq!(stats.histogram[1], 1);
+        assert_eq!(stats.histogram[10], 1);
+        assert_eq!(stats.max, Duration::from_secs(2));
+    }
+}
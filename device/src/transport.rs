@@ -0,0 +1,165 @@
+use std::{
+    io::{Read, Write},
+    net::{SocketAddr, TcpStream},
+    time::Duration,
+};
+
+use crate::DeviceConfig;
+
+// Modbus over plain sockets and serial ports, for discovery, probing and
+// shared serial buses: the sync client of tokio-modbus waits forever for
+// units that don't answer and can't send function 43.
+
+pub(crate) const READ_COILS: u8 = 0x01;
+pub(crate) const READ_HOLDING_REGISTERS: u8 = 0x03;
+pub(crate) const READ_INPUT_REGISTERS: u8 = 0x04;
+pub(crate) const WRITE_SINGLE_COIL: u8 = 0x05;
+pub(crate) const WRITE_SINGLE_REGISTER: u8 = 0x06;
+pub(crate) const WRITE_MULTIPLE_REGISTERS: u8 = 0x10;
+pub(crate) const ENCAPSULATED_INTERFACE: u8 = 0x2B;
+
+pub(crate) enum Transport {
+    // The stream and the last transaction id.
+    Tcp(TcpStream, u16),
+    Rtu(Box<dyn tokio_serial::SerialPort>),
+}
+
+impl Transport {
+    pub(crate) fn tcp(address: SocketAddr, timeout: Duration) -> anyhow::Result<Self> {
+        let stream = TcpStream::connect_timeout(&address, timeout)?;
+        stream.set_read_timeout(Some(timeout))?;
+        stream.set_write_timeout(Some(timeout))?;
+        Ok(Transport::Tcp(stream, 0))
+    }
+
+    pub(crate) fn rtu(com_port: &str, baudrate: u32, timeout: Duration) -> anyhow::Result<Self> {
+        let port = tokio_serial::new(com_port, baudrate)
+            .timeout(timeout)
+            .open()
+            .map_err(|e| anyhow::anyhow!("Couldn't open {}: {}", com_port, e))?;
+        Ok(Transport::Rtu(port))
+    }
+
+    // Connects to a configured device, along with the unit id to address.
+    pub(crate) fn connect(config: &DeviceConfig, timeout: Duration) -> anyhow::Result<(Self, u8)> {
+        match config {
+            DeviceConfig::Tcp(config) => {
+                let address = format!("{}:{}", config.address, config.port).parse()?;
+                Ok((Transport::tcp(address, timeout)?, config.unit))
+            }
+            DeviceConfig::Serial(config) => Ok((
+                Transport::rtu(&config.com_port, config.baudrate, timeout)?,
+                config.slave,
+            )),
+        }
+    }
+
+    pub(crate) fn set_timeout(&mut self, timeout: Duration) -> anyhow::Result<()> {
+        match self {
+            Transport::Tcp(stream, _) => {
+                stream.set_read_timeout(Some(timeout))?;
+                stream.set_write_timeout(Some(timeout))?;
+            }
+            Transport::Rtu(port) => port.set_timeout(timeout)?,
+        }
+        Ok(())
+    }
+
+    // Sends a request PDU and returns the response PDU, exceptions included.
+    pub(crate) fn request(&mut self, unit: u8, pdu: &[u8]) -> anyhow::Result<Vec<u8>> {
+        match self {
+            Transport::Tcp(stream, transaction) => {
+                *transaction = transaction.wrapping_add(1);
+                let mut frame = transaction.to_be_bytes().to_vec();
+                frame.extend([0, 0]);
+                frame.extend((pdu.len() as u16 + 1).to_be_bytes());
+                frame.push(unit);
+                frame.extend(pdu);
+                stream.write_all(&frame)?;
+
+                let mut header = [0; 7];
+                stream.read_exact(&mut header)?;
+                let length = u16::from_be_bytes([header[4], header[5]]) as usize;
+                if header[..2] != transaction.to_be_bytes() || length < 2 {
+                    anyhow::bail!("Unexpected response");
+                }
+                let mut response = vec![0; length - 1];
+                stream.read_exact(&mut response)?;
+                Ok(response)
+            }
+            Transport::Rtu(port) => {
+                let mut frame = vec![unit];
+                frame.extend(pdu);
+                frame.extend(crc16(&frame).to_le_bytes());
+                port.clear(tokio_serial::ClearBuffer::Input)?;
+                port.write_all(&frame)?;
+
+                let mut response = read_rtu_frame(port)?;
+                let crc = response.split_off(response.len() - 2);
+                if crc16(&response).to_le_bytes()[..] != crc[..] || response[0] != unit {
+                    anyhow::bail!("Invalid response");
+                }
+                Ok(response.split_off(1))
+            }
+        }
+    }
+}
+
+// RTU frames have no length, so it's worked out from the function code.
+fn read_rtu_frame(port: &mut Box<dyn tokio_serial::SerialPort>) -> anyhow::Result<Vec<u8>> {
+    let mut frame = Vec::new();
+    let mut read = |frame: &mut Vec<u8>, count: usize| -> anyhow::Result<()> {
+        let start = frame.len();
+        frame.resize(start + count, 0);
+        port.read_exact(&mut frame[start..])?;
+        Ok(())
+    };
+    read(&mut frame, 2)?;
+    match frame[1] {
+        code if code & 0x80 != 0 => read(&mut frame, 1)?,
+        // Writes echo the address and the value or quantity.
+        WRITE_SINGLE_COIL | WRITE_SINGLE_REGISTER | WRITE_MULTIPLE_REGISTERS => {
+            read(&mut frame, 4)?
+        }
+        ENCAPSULATED_INTERFACE => {
+            read(&mut frame, 6)?;
+            for _ in 0..frame[7] {
+                read(&mut frame, 2)?;
+                let length = frame[frame.len() - 1] as usize;
+                read(&mut frame, length)?;
+            }
+        }
+        _ => {
+            read(&mut frame, 1)?;
+            let count = frame[2] as usize;
+            read(&mut frame, count)?;
+        }
+    }
+    read(&mut frame, 2)?;
+    Ok(frame)
+}
+
+fn crc16(data: &[u8]) -> u16 {
+    let mut crc = 0xFFFF;
+    for byte in data {
+        crc ^= *byte as u16;
+        for _ in 0..8 {
+            crc = match crc & 1 {
+                1 => (crc >> 1) ^ 0xA001,
+                _ => crc >> 1,
+            };
+        }
+    }
+    crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::crc16;
+
+    #[test]
+    fn crc16_test() {
+        // Reading one holding register of unit 1, from the Modbus RTU spec.
+        assert_eq!(crc16(&[0x01, 0x03, 0x00, 0x00, 0x00, 0x01]), 0x0A84);
+    }
+}
@@ -110,6 +110,50 @@ pub fn parse_pattern(
     Ok(channel_vec)
 }
 
+// Like `parse_pattern`, but entries can also be single tags, D0:CH3 or EVAL3.
+pub fn parse_pattern_with_tags(
+    channel_pattern: &ChannelPattern,
+    re: (&Regex, &Regex),
+    tags: &TagDatabase,
+) -> Result<Vec<LoggerChannel>> {
+    if channel_pattern.pattern.trim().is_empty() {
+        bail!("Pattern string is empty!")
+    }
+    let mut channel_vec = Vec::new();
+    for pattern in channel_pattern.pattern.split(",") {
+        let pattern = pattern.trim();
+        if pattern.is_empty() {
+            continue;
+        }
+        // Checked first, tags may contain a dash.
+        match tags.resolve(pattern) {
+            Some(TagAddress::Channel { device_id, channel }) => {
+                channel_vec.push(LoggerChannel::Channel(Channel {
+                    id: channel,
+                    device_id,
+                    tag: pattern.to_owned(),
+                    ..Default::default()
+                }))
+            }
+            Some(TagAddress::Calculation(id)) => {
+                channel_vec.push(LoggerChannel::Calculation(Calculation {
+                    id,
+                    tag: pattern.to_owned(),
+                    ..Default::default()
+                }))
+            }
+            None if pattern.contains("-") => channel_vec.append(&mut parse_pattern(
+                &ChannelPattern::from_str(pattern.to_owned()),
+                re,
+            )?),
+            // A single CH entry names no device, it has always been ignored.
+            None if re.0.find(pattern).map_or(false, |m| m.as_str() == pattern) => continue,
+            None => bail!("Unknown tag: {}", pattern),
+        }
+    }
+    Ok(channel_vec)
+}
+
 fn construct_channel_vec(channel_index_vec: Vec<usize>) -> Vec<LoggerChannel> {
     let mut channel_vec: Vec<LoggerChannel> = Vec::new();
     for id in channel_index_vec {
@@ -140,8 +184,10 @@ mod tests {
     use lib_device::LoggerChannel;
 
     use super::parse_pattern;
+    use super::parse_pattern_with_tags;
     use super::ChannelPattern;
     use super::Regex;
+    use lib_device::{Device, TagDatabase};
 
     #[test]
     fn parse_pattern_test() {
@@ -161,4 +207,43 @@ mod tests {
         }
         assert_eq!(id_list, [4, 5, 6, 7, 1, 2, 1, 1, 2, 1, 2, 3]);
     }
+
+    #[test]
+    fn single_channel_pattern_test() {
+        let channel_pattern = ChannelPattern::from_str("CH3, CH1-CH2".to_owned());
+        let re_channel = Regex::new(r"CH+(?:([0-9]+))").unwrap();
+        let re_calc = Regex::new(r"EVAL+(?:([0-9]+))").unwrap();
+        let tags = TagDatabase::new(&[Device::default()], &[]);
+        let channels =
+            parse_pattern_with_tags(&channel_pattern, (&re_channel, &re_calc), &tags).unwrap();
+        assert_eq!(channels.len(), 2);
+        assert_eq!(
+            parse_pattern(&channel_pattern, (&re_channel, &re_calc))
+                .unwrap()
+                .len(),
+            2
+        );
+    }
+
+    #[test]
+    fn parse_pattern_with_tags_test() {
+        let mut device = Device::default();
+        device.channels[3].tag = "Flow-In".to_owned();
+        let tags = TagDatabase::new(&[device], &[]);
+        let re_channel = Regex::new(r"CH+(?:([0-9]+))").unwrap();
+        let re_calc = Regex::new(r"EVAL+(?:([0-9]+))").unwrap();
+        let channel_pattern = ChannelPattern::from_str("Flow-In, CH1-CH2, EVAL4".to_owned());
+        let channels =
+            parse_pattern_with_tags(&channel_pattern, (&re_channel, &re_calc), &tags).unwrap();
+        let mut id_list = Vec::new();
+        for channel in &channels {
+            match channel {
+                LoggerChannel::Channel(channel) => id_list.push(channel.id),
+                LoggerChannel::Calculation(calculation) => id_list.push(calculation.id),
+            }
+        }
+        assert_eq!(id_list, [3, 1, 2, 4]);
+        let channel_pattern = ChannelPattern::from_str("Unknown".to_owned());
+        assert!(parse_pattern_with_tags(&channel_pattern, (&re_channel, &re_calc), &tags).is_err());
+    }
 }
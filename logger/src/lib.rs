@@ -1,8 +1,8 @@
 mod channel_pattern;
 use regex::Regex;
-use std::{fmt::Display, path::PathBuf};
+use std::{fmt::Display, fs::OpenOptions, io::Write, path::PathBuf};
 
-pub use channel_pattern::{parse_pattern, ChannelPattern};
+pub use channel_pattern::{parse_pattern, parse_pattern_with_tags, ChannelPattern};
 use chrono::prelude::*;
 
 use lib_device::*;
@@ -45,8 +45,9 @@ impl Logger {
         log_rate: usize,
         is_logging: bool,
         re: (&Regex, &Regex),
+        tags: &TagDatabase,
     ) -> anyhow::Result<Self> {
-        let channels = parse_pattern(channel_pattern, re)?;
+        let channels = parse_pattern_with_tags(channel_pattern, re, tags)?;
 
         let logger = Self {
             name,
@@ -59,4 +60,52 @@ impl Logger {
 
         Ok(logger)
     }
+
+    // Appends a line with the current value of every channel of the logger to
+    // its file, the header is written first when the file is new.
+    pub fn log(&self, devices: &[Device], calculations: &[Calculation]) -> anyhow::Result<()> {
+        if self.logger_type == LoggerType::DataBase {
+            anyhow::bail!("{}: database loggers are written by the server", self.name);
+        }
+        let new_file = !self.path.exists();
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        if new_file {
+            let names: Vec<String> = self.channels.iter().map(column_name).collect();
+            writeln!(file, "time,{}", names.join(","))?;
+        }
+        let values: Vec<String> = self
+            .channels
+            .iter()
+            .map(|channel| {
+                let value = match channel {
+                    LoggerChannel::Channel(channel) => devices
+                        .get(channel.device_id)
+                        .and_then(|device| device.channels.get(channel.id))
+                        .map(|channel| channel.value),
+                    LoggerChannel::Calculation(calculation) => calculations
+                        .iter()
+                        .find(|c| c.id == calculation.id)
+                        .map(|calculation| calculation.value),
+                };
+                // Channels that no longer exist leave an empty cell.
+                value.map(|value| value.to_string()).unwrap_or_default()
+            })
+            .collect();
+        writeln!(file, "{},{}", Local::now().to_rfc3339(), values.join(","))?;
+        Ok(())
+    }
+}
+
+fn column_name(channel: &LoggerChannel) -> String {
+    match channel {
+        LoggerChannel::Channel(channel) if !channel.tag.is_empty() => channel.tag.clone(),
+        LoggerChannel::Channel(channel) => channel.to_string(),
+        LoggerChannel::Calculation(calculation) if !calculation.tag.is_empty() => {
+            calculation.tag.clone()
+        }
+        LoggerChannel::Calculation(calculation) => format!("EVAL{}", calculation.id),
+    }
 }
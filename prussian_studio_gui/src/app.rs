@@ -1,13 +1,21 @@
 use crate::{
-    app_threads::{spawn_device_thread, spawn_socket_write_msg},
-    crossbeam::{CrossBeamChannel, CrossBeamSocketChannel, DeviceBeam, DeviceMsgBeam},
+    app_threads::{spawn_device_threads, spawn_socket_write_msg, stop_device_threads},
+    crossbeam::{CrossBeamSocketChannel, DeviceBeam, DeviceMsgBeam},
     fonts::*,
+    server::{
+        send_calculations_to_server, send_events_to_server, send_to_server,
+        send_write_results_to_server, URL,
+    },
     setup_app::{setup_app_defaults, setup_visuals},
     status::Status,
     ui::{
         menu_bars::*,
         panels::{central_panel::*, left_panel::left_panel, right_panel::right_panel},
-        windows::{device_windows::*, logger_windows::logger_config_window},
+        windows::{
+            device_windows::*, diagnostics_windows::diagnostics_window, discovery_windows::*,
+            event_windows::event_viewer_window, logger_windows::logger_config_window,
+            trend_windows::trend_window,
+        },
     },
     window::*,
 };
@@ -21,17 +29,20 @@ pub use lib_logger::{parse_pattern, Logger, LoggerType};
 use egui::Window;
 use regex::Regex;
 use rhai::{Engine, EvalAltResult};
-use serde::Serialize;
-use std::{net::TcpStream, path::PathBuf};
+use std::{
+    net::TcpStream,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+use tungstenite::WebSocket;
 use tungstenite::{connect, stream::MaybeTlsStream};
-use tungstenite::{Message, WebSocket};
 use url::Url;
 
-pub const URL: &str = "wss://127.0.0.1:3000/websocket";
-#[derive(Serialize, Clone)]
-pub struct DataSerialized {
-    pub devices: Vec<Device>,
-}
+pub const EVENTS_URL: &str = "http://127.0.0.1:3000/events";
+pub const HISTORY_URL: &str = "http://127.0.0.1:3000/history";
+// The number of events kept in memory for the event viewer.
+const EVENT_JOURNAL_SIZE: usize = 1000;
 #[derive(serde::Deserialize, serde::Serialize)]
 #[serde(default)] // if we add new fields, give them default values when deserializing old state
 pub struct TemplateApp {
@@ -46,9 +57,28 @@ pub struct TemplateApp {
     #[serde(skip)]
     pub channel_windows_buffer: ChannelWindowsBuffer,
     #[serde(skip)]
+    pub calculation_windows_buffer: CalculationWindowsBuffer,
+    #[serde(skip)]
     pub windows_open: WindowsOpen,
+    #[serde(skip)]
+    pub event_window_buffer: EventWindowBuffer,
+    #[serde(skip)]
+    pub discovery_window_buffer: DiscoveryWindowBuffer,
+    #[serde(skip)]
+    pub probe_window_buffer: ProbeWindowBuffer,
+    #[serde(skip)]
+    pub trend_window_buffer: TrendWindowBuffer,
+    #[serde(skip)]
+    pub diagnostics_window_buffer: DiagnosticsWindowBuffer,
+    // The events received from the device workers during this session.
+    #[serde(skip)]
+    pub event_journal: Vec<Event>,
+    // The recent values of the channels, shown in the trend window.
+    #[serde(skip)]
+    pub history: History,
     pub devices: Vec<Device>,
     pub loggers: Vec<Logger>,
+    pub calculations: Vec<Calculation>,
     // We use this beam to send and receive Device data and config.
     #[serde(skip)]
     pub device_beam: Vec<DeviceBeam>,
@@ -58,6 +88,24 @@ pub struct TemplateApp {
     // The crossbeam channel that we receive any write requests from the HMI on.
     #[serde(skip)]
     pub socket_channel: Option<CrossBeamSocketChannel>,
+    // Hands the new worker beams to the HMI socket thread after a restart.
+    #[serde(skip)]
+    pub socket_beams: Option<crossbeam_channel::Sender<Vec<DeviceMsgBeam>>>,
+    // The serial ports the workers share, kept across restarts so they're opened once.
+    #[serde(skip)]
+    pub buses: BusManager,
+    // The frames of the devices captured for the diagnostics window.
+    #[serde(skip)]
+    pub traffic: TrafficMonitor,
+    // Set when devices are added, removed or moved, the workers are then restarted.
+    #[serde(skip)]
+    pub devices_changed: bool,
+    // Shared with the HMI socket thread so writes can be addressed by tag.
+    #[serde(skip)]
+    pub tags: Arc<Mutex<TagDatabase>>,
+    // When the calculations were last evaluated.
+    #[serde(skip)]
+    pub last_evaluation: Option<Instant>,
     // ---------------------
     #[serde(skip)]
     pub spawn_logging_thread: bool,
@@ -115,13 +163,28 @@ impl eframe::App for TemplateApp {
             logger_window_buffer,
             device_windows_buffer,
             channel_windows_buffer,
+            calculation_windows_buffer,
             windows_open,
+            event_window_buffer,
+            discovery_window_buffer,
+            probe_window_buffer,
+            trend_window_buffer,
+            diagnostics_window_buffer,
+            event_journal,
+            history,
             devices,
             loggers,
+            calculations,
             device_beam,
             device_msg_beam,
             spawn_logging_thread,
             socket_channel,
+            socket_beams,
+            buses,
+            traffic,
+            devices_changed,
+            tags,
+            last_evaluation,
             socket,
             re,
             svg_logo,
@@ -129,8 +192,6 @@ impl eframe::App for TemplateApp {
             ..
         } = self;
 
-        // rhai_engine.run(r#"print("hello, world!")"#).unwrap();
-
         let num_devices = devices.len();
 
         // We keep trying to reconnect to the websocket server if
@@ -142,20 +203,82 @@ impl eframe::App for TemplateApp {
             }
         }
 
+        // Workers are tied to the position of their device, so they're all
+        // restarted when the device list changes while polling.
+        if *devices_changed {
+            *devices_changed = false;
+            history.retain_devices(devices);
+            if !device_beam.is_empty() {
+                stop_device_threads(device_beam, device_msg_beam);
+                *spawn_logging_thread = true;
+            }
+        }
+
         // We try to receive any pending messages from all the threads.
         // Each thread has its own crossbeam channel.
         // --------------------------------
         for i in 0..(num_devices) {
             if let Some(crossbeam) = device_beam.iter().nth(i) {
-                if let Some(devices_received) = crossbeam.read.clone() {
-                    if let Ok(device_received) = devices_received.receive.try_recv() {
-                        devices[i] = device_received[i].clone();
-                        // We only send data to the server when there is a new update to avoid overworking it.
-                        send_to_server(devices, status, socket);
+                if let Some(reports_received) = crossbeam.read.clone() {
+                    if let Ok(report) = reports_received.receive.try_recv() {
+                        devices[i] = report.device.clone();
+                        // Unchanged channels keep their last sample, so we only record the others.
+                        history.record(&report.published_device(), now_millis());
+                        // Every poll cycle brings new values for the calculations.
+                        let previous = calculations.clone();
+                        evaluate_calculations(rhai_engine, calculations, devices);
+                        *last_evaluation = Some(Instant::now());
+                        let mut events = report.events.clone();
+                        events.append(&mut calculation_events(&previous, calculations));
+                        // The worker only reports by exception, so we forward what changed.
+                        send_to_server(&report, calculations, status, socket);
+                        // Results of writes made from this GUI end up in the write window,
+                        // the server passes all of them on to its HMI clients.
+                        for result in &report.writes {
+                            if result.origin == WriteOrigin::Gui {
+                                channel_windows_buffer.write_status = result.to_string();
+                                channel_windows_buffer.awaiting_confirmation = match result.outcome
+                                {
+                                    WriteOutcome::AwaitingConfirmation => {
+                                        Some((result.device_id, result.channel))
+                                    }
+                                    _ => None,
+                                };
+                            }
+                        }
+                        if !report.writes.is_empty() {
+                            send_write_results_to_server(&report.writes, status, socket);
+                        }
+                        if !events.is_empty() {
+                            send_events_to_server(&events, status, socket);
+                            event_journal.extend(events);
+                            let overflow = event_journal.len().saturating_sub(EVENT_JOURNAL_SIZE);
+                            event_journal.drain(..overflow);
+                        }
                     }
                 }
             }
         }
+        // Without reports the calculations are evaluated on their own, so the
+        // time based blocks keep running.
+        let due = last_evaluation.map_or(true, |last| last.elapsed() >= CALCULATION_RATE);
+        if !device_beam.is_empty() && !calculations.is_empty() && due {
+            let previous = calculations.clone();
+            evaluate_calculations(rhai_engine, calculations, devices);
+            *last_evaluation = Some(Instant::now());
+            send_calculations_to_server(calculations, status, socket);
+            let events = calculation_events(&previous, calculations);
+            if !events.is_empty() {
+                send_events_to_server(&events, status, socket);
+                event_journal.extend(events);
+                let overflow = event_journal.len().saturating_sub(EVENT_JOURNAL_SIZE);
+                event_journal.drain(..overflow);
+            }
+        }
+        // Re-indexed every frame so configuration changes are picked up right away.
+        tags.lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .update(devices, calculations);
         // --------------------------------
 
         // --------------------------------
@@ -165,10 +288,12 @@ impl eframe::App for TemplateApp {
 
         if let Some(socket_channel) = socket_channel {
             if let Ok(json_channel) = socket_channel.receive.try_recv() {
-                match devices[json_channel.device_id].channels[json_channel.channel].access_type {
-                    AccessType::Write => {
-                        devices[json_channel.device_id].channels[json_channel.channel].value =
-                            json_channel.value;
+                let channel = devices
+                    .get_mut(json_channel.device_id)
+                    .and_then(|device| device.channels.get_mut(json_channel.channel));
+                match channel {
+                    Some(channel) if channel.access_type == AccessType::Write => {
+                        channel.value = json_channel.value;
                         println!("channel modified");
                         if let Some(device_beam) = device_beam.iter().nth(json_channel.device_id) {
                             if let Some(updated_channel) = device_beam.update.clone() {
@@ -206,50 +331,20 @@ impl eframe::App for TemplateApp {
 
             //spawn_socket_recv(socket_channel_init);
 
-            for i in 0..(num_devices) {
-                let (device_msg_s, device_msg_r): (
-                    crossbeam_channel::Sender<DeviceMsg>,
-                    crossbeam_channel::Receiver<DeviceMsg>,
-                ) = unbounded();
-                let (read_s, read_r): (
-                    crossbeam_channel::Sender<Vec<Device>>,
-                    crossbeam_channel::Receiver<Vec<Device>>,
-                ) = unbounded();
-                let (update_s, update_r): (
-                    crossbeam_channel::Sender<Vec<Device>>,
-                    crossbeam_channel::Receiver<Vec<Device>>,
-                ) = unbounded();
-
-                let device_msg_channel = DeviceMsgBeam {
-                    send: device_msg_s,
-                    receive: device_msg_r,
-                };
-                let read_channel = CrossBeamChannel {
-                    send: read_s.clone(),
-                    receive: read_r.clone(),
-                };
-                let update_channel = CrossBeamChannel {
-                    send: update_s,
-                    receive: update_r.clone(),
-                };
-
-                let device_channel = DeviceBeam {
-                    read: Some(read_channel),
-                    update: Some(update_channel),
-                };
-
-                device_msg_beam.push(device_msg_channel.clone());
-
-                device_beam.push(device_channel.clone());
-
-                spawn_device_thread(
-                    devices_to_read.clone(),
-                    device_channel.clone(),
-                    device_msg_channel.clone(),
-                    i,
-                );
+            // The GUI doesn't wait for its workers, they end on their own
+            // once they get the Stop message.
+            let (beams, msg_beams, _) =
+                spawn_device_threads(&devices_to_read, buses, traffic, tags);
+            device_beam.extend(beams);
+            device_msg_beam.extend(msg_beams);
+            match socket_beams {
+                Some(socket_beams) => if socket_beams.send(device_msg_beam.to_vec()).is_ok() {},
+                None => {
+                    let (beams_s, beams_r) = unbounded();
+                    spawn_socket_write_msg(device_msg_beam.to_vec(), beams_r, Arc::clone(tags));
+                    *socket_beams = Some(beams_s);
+                }
             }
-            spawn_socket_write_msg(device_msg_beam.to_vec());
         }
         // --------------------------------
 
@@ -284,19 +379,53 @@ impl eframe::App for TemplateApp {
                 windows_open,
                 device_windows_buffer,
                 devices,
+                devices_changed,
                 loggers,
+                calculations,
                 channel_windows_buffer,
                 spawn_logging_thread,
                 config_save_path,
+                status,
             );
 
-            plc_channels_window(windows_open, ctx, devices, channel_windows_buffer);
+            plc_channels_window(
+                windows_open,
+                ctx,
+                devices,
+                channel_windows_buffer,
+                device_msg_beam,
+                device_beam,
+                &tags.lock().unwrap_or_else(|e| e.into_inner()),
+            );
 
             write_channel_value_ui(windows_open, ctx, channel_windows_buffer, device_msg_beam);
 
             preferences_ui(windows_open, ctx);
 
-            logger_config_window(windows_open, ctx, logger_window_buffer, re, loggers);
+            logger_config_window(
+                windows_open,
+                ctx,
+                logger_window_buffer,
+                re,
+                loggers,
+                &tags.lock().unwrap_or_else(|e| e.into_inner()),
+            );
+
+            calculations_window(
+                windows_open,
+                ctx,
+                calculations,
+                calculation_windows_buffer,
+                &tags.lock().unwrap_or_else(|e| e.into_inner()),
+            );
+
+            event_viewer_window(
+                windows_open,
+                ctx,
+                event_window_buffer,
+                event_journal,
+                devices,
+            );
 
             channel_config_window(
                 windows_open,
@@ -304,17 +433,26 @@ impl eframe::App for TemplateApp {
                 channel_windows_buffer,
                 devices,
                 device_beam,
+                &tags.lock().unwrap_or_else(|e| e.into_inner()),
             );
 
-            plc_config_window(
+            channel_import_window(
                 windows_open,
                 ctx,
-                device_windows_buffer,
+                channel_windows_buffer,
                 devices,
-                device_msg_beam,
                 device_beam,
             );
 
+            devices_window(
+                windows_open,
+                ctx,
+                devices,
+                device_windows_buffer,
+                channel_windows_buffer,
+                devices_changed,
+            );
+
             device_config_window(
                 windows_open,
                 ctx,
@@ -323,6 +461,26 @@ impl eframe::App for TemplateApp {
                 device_msg_beam,
                 device_beam,
             );
+
+            discovery_window(
+                windows_open,
+                ctx,
+                discovery_window_buffer,
+                devices,
+                devices_changed,
+            );
+
+            probe_window(windows_open, ctx, probe_window_buffer, devices, device_beam);
+
+            trend_window(windows_open, ctx, trend_window_buffer, history, devices);
+
+            diagnostics_window(
+                windows_open,
+                ctx,
+                diagnostics_window_buffer,
+                traffic,
+                devices,
+            );
         });
 
         right_panel(ctx, &self);
@@ -331,24 +489,6 @@ impl eframe::App for TemplateApp {
     }
 }
 
-fn send_to_server(
-    devices: &mut Vec<Device>,
-    status: &mut Status,
-    socket: &mut Option<WebSocket<MaybeTlsStream<TcpStream>>>,
-) {
-    let data_to_serialize = DataSerialized {
-        devices: devices.clone(),
-    };
-    // We send the data over the web socket to the HMI and update our status.
-    status.websocket = match send_over_socket(socket, &data_to_serialize) {
-        Ok(_) => "Connected to WebSocket.".to_owned(),
-        Err(e) => {
-            *socket = None;
-            format!("ERROR: {}", e)
-        }
-    };
-}
-
 fn write_channel_value_ui(
     windows_open: &mut WindowsOpen,
     ctx: &egui::Context,
@@ -360,15 +500,9 @@ fn write_channel_value_ui(
         .open(&mut windows_open.channel_write_value)
         .show(ctx, |ui| {
             ui.horizontal(|ui| {
-                ui.text_edit_singleline(
-                    &mut channel_windows_buffer.channel_write_value
-                        [channel_windows_buffer.selected_channel.id],
-                );
+                ui.text_edit_singleline(&mut channel_windows_buffer.channel_write_value);
                 if ui.button("Write").clicked() {
-                    if let Ok(value) = channel_windows_buffer.channel_write_value
-                        [channel_windows_buffer.selected_channel.id]
-                        .parse::<f32>()
-                    {
+                    if let Ok(value) = channel_windows_buffer.channel_write_value.parse::<f32>() {
                         //devices[channel_windows_buffer.device_id].channels
                         //  [channel_windows_buffer.selected_channel.id]
                         //.value = value;
@@ -380,16 +514,50 @@ fn write_channel_value_ui(
                                 device_id: channel_windows_buffer.device_id,
                                 channel: channel_windows_buffer.selected_channel.id,
                                 value,
+                                request_id: None,
+                                origin: WriteOrigin::Gui,
                             };
-                            if let Ok(_) = device_msg_beam
+                            channel_windows_buffer.write_status = match device_msg_beam
                                 .send
                                 .send(DeviceMsg::WriteChannel(channel_to_write))
                             {
-                            }
+                                Ok(_) => "Writing...".to_owned(),
+                                Err(_) => "The device isn't being polled.".to_owned(),
+                            };
                         }
+                    } else {
+                        channel_windows_buffer.write_status = "Not a number.".to_owned();
                     }
                 }
             });
+            ui.label(&channel_windows_buffer.write_status);
+            if let Some((device_id, channel)) = channel_windows_buffer.awaiting_confirmation {
+                ui.horizontal(|ui| {
+                    let confirm = match (
+                        ui.button("Confirm").clicked(),
+                        ui.button("Cancel").clicked(),
+                    ) {
+                        (true, _) => Some(true),
+                        (_, true) => Some(false),
+                        _ => None,
+                    };
+                    if let Some(confirm) = confirm {
+                        let confirm_write = JsonConfirmWrite {
+                            device_id,
+                            channel,
+                            confirm,
+                        };
+                        if let Some(device_msg_beam) = device_msg_beam.get(device_id) {
+                            if device_msg_beam
+                                .send
+                                .send(DeviceMsg::ConfirmWrite(confirm_write))
+                                .is_ok()
+                            {}
+                        }
+                        channel_windows_buffer.awaiting_confirmation = None;
+                    }
+                });
+            }
         });
 }
 
@@ -400,16 +568,3 @@ fn preferences_ui(windows_open: &mut WindowsOpen, ctx: &egui::Context) {
             ctx.settings_ui(ui);
         });
 }
-
-pub fn send_over_socket(
-    socket: &mut Option<WebSocket<MaybeTlsStream<TcpStream>>>,
-    data: &DataSerialized,
-) -> anyhow::Result<()> {
-    let json = serde_json::to_string(data)?;
-    if let Some(socket) = socket {
-        socket.write_message(Message::Text(json))?;
-        Ok(())
-    } else {
-        anyhow::bail!("There is no socket connected!")
-    }
-}
@@ -1,16 +1,13 @@
 #![warn(clippy::all, rust_2018_idioms)]
 
 mod app;
-mod app_threads;
-mod config;
-mod crossbeam;
 mod fonts;
 mod setup_app;
-mod status;
 mod ui;
 mod window;
 //mod windows;
 pub use app::TemplateApp;
+use prussian_runtime::{app_threads, config, crossbeam, server, status};
 
 // ----------------------------------------------------------------------------
 // When compiling for web:
@@ -2,19 +2,22 @@ use std::path::PathBuf;
 
 use egui::{Color32, Rounding};
 use extras::RetainedImage;
-use lib_device::Device;
+use lib_device::{BusManager, Device, History, TrafficMonitor};
 use regex::Regex;
 use rhai::Engine;
 use tungstenite::connect;
 use url::Url;
 
 use crate::{
-    app::URL,
+    server::URL,
     status::Status,
-    window::{ChannelWindowsBuffer, DeviceWindowsBuffer, LoggerWindowBuffer, WindowsOpen},
+    window::{
+        CalculationWindowsBuffer, ChannelWindowsBuffer, DeviceWindowsBuffer,
+        DiagnosticsWindowBuffer, DiscoveryWindowBuffer, EventWindowBuffer, LoggerWindowBuffer,
+        ProbeWindowBuffer, TrendWindowBuffer, WindowsOpen,
+    },
     TemplateApp,
 };
-const NUM_CHANNELS: usize = 20;
 
 pub fn setup_app_defaults() -> TemplateApp {
     let socket = match connect(Url::parse(URL).unwrap()) {
@@ -27,20 +30,31 @@ pub fn setup_app_defaults() -> TemplateApp {
         status: Status::default(),
         logger_window_buffer: LoggerWindowBuffer::default(),
         device_windows_buffer: DeviceWindowsBuffer::default(),
-        channel_windows_buffer: ChannelWindowsBuffer {
-            channel_write_value: vec![String::new(); NUM_CHANNELS],
-            device_id: 0,
-            ..Default::default()
-        },
+        channel_windows_buffer: ChannelWindowsBuffer::default(),
+        calculation_windows_buffer: CalculationWindowsBuffer::default(),
         windows_open: WindowsOpen::default(),
+        event_window_buffer: EventWindowBuffer::default(),
+        discovery_window_buffer: DiscoveryWindowBuffer::default(),
+        probe_window_buffer: ProbeWindowBuffer::default(),
+        trend_window_buffer: TrendWindowBuffer::default(),
+        diagnostics_window_buffer: DiagnosticsWindowBuffer::default(),
+        event_journal: Vec::new(),
+        history: History::default(),
         devices: vec![
             Device::initialize(0, "PLC".to_owned()),
             Device::initialize(1, "Modbus device".to_owned()),
         ],
         loggers: Vec::new(),
+        calculations: Vec::new(),
         device_beam: Vec::new(),
         device_msg_beam: Vec::new(),
         socket_channel: None,
+        socket_beams: None,
+        buses: BusManager::default(),
+        traffic: TrafficMonitor::default(),
+        devices_changed: false,
+        tags: Default::default(),
+        last_evaluation: None,
         spawn_logging_thread: false,
         re: (
             Regex::new(r"CH+(?:([0-9]+))").unwrap(),
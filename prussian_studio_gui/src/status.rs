@@ -1,4 +0,0 @@
-#[derive(Clone, Default)]
-pub struct Status {
-    pub websocket: String,
-}
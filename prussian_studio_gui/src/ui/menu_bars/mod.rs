@@ -1,8 +1,7 @@
-use ron::ser::{to_string_pretty, PrettyConfig};
 use std::{fs, io::Write, path::PathBuf};
 
 use extras::RetainedImage;
-use lib_device::Device;
+use lib_device::{Calculation, Device};
 use lib_logger::Logger;
 
 use crate::{
@@ -32,21 +31,26 @@ pub fn bottom_bar(ui: &mut egui::Ui, svg_logo: &mut RetainedImage, status: &mut
 
                 ui.spinner();
                 ui.label(format!("{}", &status.websocket));
+                ui.label(&status.config);
             });
         });
     });
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn top_bar(
     ui: &mut egui::Ui,
     frame: &mut eframe::Frame,
     windows_open: &mut WindowsOpen,
     device_windows_buffer: &mut DeviceWindowsBuffer,
     devices: &mut Vec<Device>,
+    devices_changed: &mut bool,
     loggers: &mut Vec<Logger>,
+    calculations: &mut Vec<Calculation>,
     channel_windows_buffer: &mut ChannelWindowsBuffer,
     spawn_logging_thread: &mut bool,
     config_save_path: &mut PathBuf,
+    status: &mut Status,
 ) {
     // The top panel is often a good place for a menu bar:
     egui::menu::bar(ui, |ui| {
@@ -59,11 +63,22 @@ pub fn top_bar(
                     .set_directory(&path)
                     .pick_file()
                 {
-                    let config_str = fs::read_to_string(res).expect("Couldn't read");
-                    if let Ok(config) = ron::de::from_str(&config_str) {
-                        let app_config: AppConfig = config;
-                        *devices = app_config.devices;
-                        *loggers = app_config.loggers;
+                    let app_config = fs::read_to_string(&res)
+                        .map_err(anyhow::Error::from)
+                        .and_then(|config_str| AppConfig::from_ron(&config_str));
+                    match app_config {
+                        Ok(app_config) => {
+                            *devices = app_config.devices;
+                            // The workers still poll the previous devices.
+                            *devices_changed = true;
+                            *loggers = app_config.loggers;
+                            *calculations = app_config.calculations;
+                            status.config = format!("Loaded {}", res.display());
+                            for warning in &app_config.warnings {
+                                status.config = format!("{}. {}", status.config, warning);
+                            }
+                        }
+                        Err(e) => status.config = format!("Couldn't load {}: {}", res.display(), e),
                     }
                 }
             }
@@ -74,21 +89,20 @@ pub fn top_bar(
                     .set_directory(&config_path)
                     .save_file()
                 {
-                    let pretty = PrettyConfig::new()
-                        .depth_limit(7)
-                        .separate_tuple_members(true)
-                        .enumerate_arrays(true);
-                    let app_config = AppConfig {
-                        devices: devices.clone(),
-                        loggers: loggers.clone(),
+                    let app_config =
+                        AppConfig::new(devices.clone(), loggers.clone(), calculations.clone());
+                    let saved = app_config.to_ron().and_then(|s| {
+                        let mut f = fs::File::create(&selected_path)?;
+                        f.write_all(s.as_bytes())?;
+                        Ok(())
+                    });
+                    status.config = match saved {
+                        Ok(_) => {
+                            *config_save_path = selected_path.clone();
+                            format!("Saved {}", selected_path.display())
+                        }
+                        Err(e) => format!("Couldn't save {}: {}", selected_path.display(), e),
                     };
-
-                    // Needs better error handling.
-                    if let Ok(s) = to_string_pretty(&app_config, pretty) {
-                        *config_save_path = selected_path.clone();
-                        let mut f = fs::File::create(&selected_path).expect("Couldn't create file");
-                        f.write_all(&s.as_bytes()).expect("Couldn't write");
-                    }
                 }
             }
             ui.separator();
@@ -102,42 +116,52 @@ pub fn top_bar(
             }
         });
         ui.menu_button("Devices", |ui| {
-            // if ui.button("Add new device").clicked() {
-            //     windows_open.new_device = !windows_open.new_device;
-            // }
-            ui.menu_button("PLC", |ui| {
-                if ui.button("Configure").clicked() {
-                    device_windows_buffer.status = "".to_owned();
-                    windows_open.plc = !windows_open.plc;
-                    device_windows_buffer.name = devices[0].name.clone();
-                    device_windows_buffer.scan_rate = devices[0].scan_rate.clone();
-                    device_windows_buffer.config = devices[0].config.clone();
-                }
-                if ui.button("Channels").clicked() {
-                    windows_open.device_channels = !windows_open.device_channels;
-                    channel_windows_buffer.device_id = 0;
-                }
-            });
+            if ui.button("Manage devices").clicked() {
+                windows_open.devices = !windows_open.devices;
+            }
+            if ui.button("Discover devices").clicked() {
+                windows_open.discovery = !windows_open.discovery;
+            }
+            if ui.button("Probe registers").clicked() {
+                windows_open.probe = !windows_open.probe;
+            }
+            if ui.button("Traffic diagnostics").clicked() {
+                windows_open.diagnostics = !windows_open.diagnostics;
+            }
             ui.separator();
-            ui.menu_button("Modbus Device", |ui| {
-                if ui.button("Configure").clicked() {
-                    windows_open.modbus_device = !windows_open.modbus_device;
-                    device_windows_buffer.status = "".to_owned();
-                    device_windows_buffer.name = devices[1].name.clone();
-                    device_windows_buffer.scan_rate = devices[1].scan_rate.clone();
-                    device_windows_buffer.config = devices[1].config.clone();
-                }
-                if ui.button("Channels").clicked() {
-                    windows_open.device_channels = !windows_open.device_channels;
-                    channel_windows_buffer.device_id = 1;
-                }
-            });
+            for device in devices.iter() {
+                ui.menu_button(format!("D{} {}", device.id, device.name), |ui| {
+                    if ui.button("Configure").clicked() {
+                        device_windows_buffer.load(device);
+                        windows_open.device_config = true;
+                    }
+                    if ui.button("Channels").clicked() {
+                        channel_windows_buffer.device_id = device.id;
+                        windows_open.device_channels = true;
+                    }
+                });
+            }
         });
         ui.menu_button("Logger", |ui| {
             if ui.button("Configure").clicked() {
                 windows_open.logger_configure = !windows_open.logger_configure;
             }
         });
+        ui.menu_button("Calculations", |ui| {
+            if ui.button("Configure").clicked() {
+                windows_open.calculations = !windows_open.calculations;
+            }
+        });
+        ui.menu_button("Events", |ui| {
+            if ui.button("Viewer").clicked() {
+                windows_open.event_viewer = !windows_open.event_viewer;
+            }
+        });
+        ui.menu_button("Trends", |ui| {
+            if ui.button("Viewer").clicked() {
+                windows_open.trends = !windows_open.trends;
+            }
+        });
         ui.menu_button("Help", |ui| if ui.button("About").clicked() {});
 
         ui.with_layout(egui::Layout::right_to_left(), |ui| {
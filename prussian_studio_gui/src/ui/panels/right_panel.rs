@@ -1,5 +1,5 @@
 use crate::app::TemplateApp;
-use egui::{Context, Grid, InnerResponse};
+use egui::{Color32, Context, Grid, InnerResponse};
 
 const NUM_COLUMNS: usize = 2;
 
@@ -15,15 +15,40 @@ pub fn right_panel(ctx: &Context, app: &TemplateApp) -> InnerResponse<()> {
                 ui.separator();
             }
             ui.end_row();
-            ui.label(format!("{}", app.devices[0]));
-            ui.label(format!("{}", app.devices[0].status));
-            ui.end_row();
-            ui.label(format!("{}", app.devices[1]));
-            ui.label(format!("{}", app.devices[1].status));
-            ui.end_row();
+            for device in &app.devices {
+                ui.label(format!("{}", device));
+                match device.heartbeat_lost {
+                    true => {
+                        ui.colored_label(Color32::RED, format!("{} Heartbeat lost.", device.status))
+                    }
+                    false => ui.label(format!("{}", device.status)),
+                };
+                ui.end_row();
+            }
 
             ui.end_row();
         });
+        // Only serial devices go through a bus.
+        let buses = app.buses.stats();
+        if !buses.is_empty() {
+            ui.separator();
+            ui.label("Serial Buses");
+            ui.separator();
+            Grid::new("right_panel_buses")
+                .num_columns(3)
+                .show(ui, |ui| {
+                    ui.label("Port");
+                    ui.label("Utilisation");
+                    ui.label("Errors");
+                    ui.end_row();
+                    for bus in buses {
+                        ui.label(&bus.com_port);
+                        ui.label(format!("{:.0}%", bus.utilisation * 100.0));
+                        ui.label(format!("{}/{}", bus.errors, bus.transactions));
+                        ui.end_row();
+                    }
+                });
+        }
         // let max_size = ui.available_size();
         // app.svg_logo.show_max_size(ui, max_size);
     })
@@ -1,102 +1,40 @@
-use egui::{Button, Color32, ComboBox, Grid, Slider, Window};
+use egui::{Button, Color32, ComboBox, DragValue, Grid, Slider, Window};
 use lib_device::*;
 
 use crate::{
     crossbeam::{DeviceBeam, DeviceMsgBeam},
-    window::{ChannelWindowsBuffer, DeviceType, DeviceWindowsBuffer, WindowsOpen},
+    window::{
+        CalculationWindowsBuffer, ChannelWindowsBuffer, DeviceType, DeviceWindowsBuffer,
+        WindowsOpen,
+    },
 };
 
-pub fn plc_config_window(
-    windows_open: &mut WindowsOpen,
-    ctx: &egui::Context,
-    device_windows_buffer: &mut DeviceWindowsBuffer,
-    devices: &mut Vec<Device>,
-    device_msg_beam: &mut Vec<DeviceMsgBeam>,
-    device_beam: &mut Vec<DeviceBeam>,
-) {
-    Window::new("PLC")
-        .open(&mut windows_open.plc)
-        .show(ctx, |ui| {
-            ui.label("Configuration");
-            ui.separator();
-            egui::Grid::new("add_device").num_columns(2).show(ui, |ui| {
-                ui.label("Device name:");
-                ui.text_edit_singleline(&mut device_windows_buffer.name);
-                ui.end_row();
-                match device_windows_buffer.device_type {
-                    DeviceType::Tcp => {
-                        ui.label("IP address:");
-                        ui.text_edit_singleline(&mut device_windows_buffer.address);
-                        ui.end_row();
-                        ui.label("Port:");
-                        ui.text_edit_singleline(&mut device_windows_buffer.port);
-                        ui.end_row();
-                    }
-                    DeviceType::Serial => {
-                        ui.label("COM port:");
-                        ui.text_edit_singleline(&mut device_windows_buffer.path);
-                        ui.end_row();
-                        ui.label("Baudrate:");
-                        ui.text_edit_singleline(&mut device_windows_buffer.baudrate);
-                        ui.end_row();
-                        ui.label("Slave:");
-                        ui.text_edit_singleline(&mut device_windows_buffer.slave);
-                        ui.end_row();
-                    }
-                }
-                ui.label("Scan rate:");
-                ui.add(Slider::new(&mut device_windows_buffer.scan_rate, 0..=60).text(""));
-                ui.end_row();
-            });
-            ui.vertical_centered_justified(|ui| {
-                if ui.button("Save").clicked() {
-                    match device_windows_buffer.device_type {
-                        DeviceType::Tcp => {
-                            if let Ok(port) = device_windows_buffer.port.parse::<usize>() {
-                                let config = DeviceConfig::Tcp(TcpConfig {
-                                    address: device_windows_buffer.address.to_owned(),
-                                    port,
-                                });
-                                devices[0].name = device_windows_buffer.name.clone();
-                                devices[0].config = config.clone();
-                                devices[0].scan_rate = device_windows_buffer.scan_rate;
-                                device_windows_buffer.status =
-                                    "Device configuration saved successfully!".to_owned();
-                                if let Some(device_msg) = device_msg_beam.iter().nth(0) {
-                                    if device_msg.send.send(DeviceMsg::Reconnect(config)).is_ok() {
-                                        println!("config sent!");
-                                    }
-                                }
-                                if let Some(device_beam) = device_beam.iter().nth(0) {
-                                    if let Some(updated_device) = device_beam.update.clone() {
-                                        if updated_device.send.send(devices.to_vec()).is_ok() {}
-                                    }
-                                }
-                            } else {
-                                device_windows_buffer.status = "Error!".to_owned();
-                            }
-                        }
-                        DeviceType::Serial => {}
-                    }
-                }
-                ui.label(device_windows_buffer.status.to_owned());
-            });
-        });
-}
-
 pub fn plc_channels_window(
     windows_open: &mut WindowsOpen,
     ctx: &egui::Context,
     devices: &mut Vec<Device>,
     channel_windows_buffer: &mut ChannelWindowsBuffer,
+    device_msg_beam: &mut Vec<DeviceMsgBeam>,
+    device_beam: &mut Vec<DeviceBeam>,
+    tags: &TagDatabase,
 ) {
-    Window::new("PLC Channels")
+    let mut action = None;
+    Window::new("Channels")
         .open(&mut windows_open.device_channels)
         .scroll2([true, true])
         .show(ctx, |ui| {
+            // Only the first channel of a duplicate tag can be reached by it.
+            for duplicate in tags.duplicates().iter().filter(|duplicate| {
+                duplicate.addresses.iter().any(|address| {
+                    matches!(address, TagAddress::Channel { device_id, .. }
+                        if *device_id == channel_windows_buffer.device_id)
+                })
+            }) {
+                ui.colored_label(Color32::RED, format!("Duplicate tag: {}", duplicate));
+            }
             Grid::new("Channel List")
                 .striped(true)
-                .num_columns(9)
+                .num_columns(10)
                 .min_col_width(160.0)
                 .show(ui, |ui| {
                     if let Some(device) = &devices.iter().nth(channel_windows_buffer.device_id) {
@@ -116,11 +54,13 @@ pub fn plc_channels_window(
                         ui.label("Address");
                         ui.label("Device");
                         ui.label("Status");
+                        ui.label("");
                         ui.end_row();
-                        for _ in 0..9 {
+                        for _ in 0..10 {
                             ui.separator();
                         }
                         ui.end_row();
+                        let num_channels = device.channels.len();
                         for channel in device.channels.clone() {
                             let button = Button::new(format!("CH{}", channel.id)).frame(true);
                             if ui.add(button).clicked() {
@@ -128,13 +68,14 @@ pub fn plc_channels_window(
                                 windows_open.channel_config = !windows_open.channel_config;
                                 channel_windows_buffer.edited_channel =
                                     channel_windows_buffer.selected_channel.clone();
+                                channel_windows_buffer.status = "".to_owned();
                             }
                             // ui.label(format!("CH{}", channel.id));
                             if channel.enabled {
                                 match channel.access_type {
                                     AccessType::Write => {
                                         ui.horizontal(|ui| {
-                                            ui.label(format!("{:.2}", channel.value));
+                                            ui.label(channel_value_text(&channel));
                                             if ui.button("Write").clicked() {
                                                 channel_windows_buffer.selected_channel =
                                                     channel.clone();
@@ -145,25 +86,49 @@ pub fn plc_channels_window(
                                         });
                                     }
                                     AccessType::Read => {
-                                        ui.label(format!("{:.2}", channel.value));
+                                        ui.label(channel_value_text(&channel));
                                     }
                                 };
                             } else {
                                 ui.label("Disabled.");
                             }
-                            let mut alarm = "";
-                            if channel.alarm.low.active {
-                                alarm = "LOW ALARM";
-                            }
-
-                            if channel.alarm.high.active {
-                                alarm = "HIGH ALARM";
-                            }
-                            if channel.alarm.low.active && channel.alarm.high.active {
-                                alarm = "LOW ALARM/HIGH ALARM";
-                            }
-
-                            ui.colored_label(Color32::RED, alarm);
+                            ui.horizontal(|ui| {
+                                if channel.alarm.is_unacked() {
+                                    ui.colored_label(Color32::RED, channel.alarm.active_text());
+                                    if ui.small_button("Ack").clicked() {
+                                        if let Some(device_msg_beam) =
+                                            device_msg_beam.iter().nth(channel.device_id)
+                                        {
+                                            let ack = JsonAckAlarm {
+                                                device_id: channel.device_id,
+                                                channel: channel.id,
+                                                alarm_type: None,
+                                                user: local_user(),
+                                            };
+                                            if device_msg_beam
+                                                .send
+                                                .send(DeviceMsg::AckAlarm(ack))
+                                                .is_ok()
+                                            {
+                                            }
+                                        }
+                                    }
+                                } else {
+                                    ui.colored_label(
+                                        Color32::DARK_RED,
+                                        channel.alarm.active_text(),
+                                    );
+                                }
+                                ui.colored_label(Color32::GRAY, channel.alarm.inhibited_text());
+                                ui.menu_button("Shelve", |ui| {
+                                    for (text, duration) in SHELVE_DURATIONS {
+                                        if ui.button(text).clicked() {
+                                            shelve_alarms(device_msg_beam, &channel, duration);
+                                            ui.close_menu();
+                                        }
+                                    }
+                                });
+                            });
 
                             ui.label(format!("{}", channel.tag));
                             ui.label(format!("{}", channel.value_type));
@@ -171,108 +136,371 @@ pub fn plc_channels_window(
                             ui.label(format!("{}", channel.index));
                             ui.label(format!("{}", channel.device_id));
                             ui.label(format!("{}", channel.status));
-                            // if ui.small_button("Configure").clicked() {}
+                            let i = channel.id;
+                            ui.horizontal(|ui| {
+                                if ui.add_enabled(i > 0, Button::new("⬆").small()).clicked() {
+                                    action = Some(ChannelAction::Move(i, i - 1));
+                                }
+                                if ui
+                                    .add_enabled(i + 1 < num_channels, Button::new("⬇").small())
+                                    .clicked()
+                                {
+                                    action = Some(ChannelAction::Move(i, i + 1));
+                                }
+                                if ui.small_button("Clone").clicked() {
+                                    action = Some(ChannelAction::Clone(i));
+                                }
+                                if ui.small_button("Remove").clicked() {
+                                    action = Some(ChannelAction::Remove(i));
+                                }
+                            });
                             ui.end_row();
                         }
                     }
                 });
+            ui.horizontal(|ui| {
+                if ui.button("Add channel").clicked() {
+                    action = Some(ChannelAction::Add);
+                }
+                if let Some(device) = devices.get(channel_windows_buffer.device_id) {
+                    if ui.button("Export CSV").clicked() {
+                        channel_windows_buffer.table_status = match export_channel_table(device) {
+                            Ok(Some(path)) => format!("Exported to {}", path.display()),
+                            Ok(None) => "".to_owned(),
+                            Err(e) => format!("Error: {}", e),
+                        };
+                    }
+                    if ui.button("Import CSV").clicked() {
+                        match import_channel_table(device, tags) {
+                            Ok(Some(import)) => {
+                                channel_windows_buffer.import = Some(import);
+                                channel_windows_buffer.table_status = "".to_owned();
+                                windows_open.channel_import = true;
+                            }
+                            Ok(None) => {}
+                            Err(e) => channel_windows_buffer.table_status = format!("Error: {}", e),
+                        }
+                    }
+                }
+                ui.label(&channel_windows_buffer.table_status);
+            });
         });
+
+    let device_id = channel_windows_buffer.device_id;
+    if let (Some(action), Some(device)) = (action, devices.get_mut(device_id)) {
+        match action {
+            ChannelAction::Add => device.add_channel(),
+            ChannelAction::Clone(i) => device.clone_channel(i, tags),
+            ChannelAction::Remove(i) => device.remove_channel(i),
+            ChannelAction::Move(from, to) => device.move_channel(from, to),
+        }
+        // The channel config window would save over the wrong channel otherwise.
+        windows_open.channel_config = false;
+        if let Some(device_beam) = device_beam.iter().nth(device_id) {
+            if let Some(updated_device) = device_beam.update.clone() {
+                if updated_device.send.send(devices.to_vec()).is_ok() {}
+            }
+        }
+    }
 }
 
-pub fn calculations_window(
+fn export_channel_table(device: &Device) -> anyhow::Result<Option<std::path::PathBuf>> {
+    let path = match rfd::FileDialog::new()
+        .add_filter("csv", &["csv"])
+        .set_file_name(&format!("{}.csv", device.name))
+        .save_file()
+    {
+        Some(path) => path,
+        None => return Ok(None),
+    };
+    export_channels(std::fs::File::create(&path)?, &device.channels)?;
+    Ok(Some(path))
+}
+
+fn import_channel_table(
+    device: &Device,
+    tags: &TagDatabase,
+) -> anyhow::Result<Option<ChannelImport>> {
+    let path = match rfd::FileDialog::new()
+        .add_filter("csv", &["csv"])
+        .pick_file()
+    {
+        Some(path) => path,
+        None => return Ok(None),
+    };
+    let import = import_channels(std::fs::File::open(path)?, &device.channels, tags)?;
+    Ok(Some(import))
+}
+
+// Previews an imported channel table, it's only applied when every row is valid.
+pub fn channel_import_window(
     windows_open: &mut WindowsOpen,
     ctx: &egui::Context,
+    channel_windows_buffer: &mut ChannelWindowsBuffer,
     devices: &mut Vec<Device>,
-    calculations_windows_buffer: &mut ChannelWindowsBuffer,
+    device_beam: &mut Vec<DeviceBeam>,
 ) {
-    Window::new("PLC Channels")
-        .open(&mut windows_open.device_channels)
-        .scroll2([true, true])
+    let mut apply = false;
+    let mut open = windows_open.channel_import;
+    Window::new("Channel Import")
+        .open(&mut open)
+        .scroll2([false, true])
         .show(ctx, |ui| {
-            Grid::new("Channel List")
-                .striped(true)
-                .num_columns(9)
-                .min_col_width(160.0)
-                .show(ui, |ui| {
-                    if let Some(device) = &devices.iter().nth(calculations_windows_buffer.device_id)
-                    {
-                        ui.label(format!("{}", &device));
-                        ui.label("Device status:");
-                        ui.label(format!("{}", &device.status));
+            let import = match &channel_windows_buffer.import {
+                Some(import) => import,
+                None => return,
+            };
+            ui.label(format!(
+                "D{}: {} channels, {} changes.",
+                channel_windows_buffer.device_id,
+                import.channels.len(),
+                import.changes.len()
+            ));
+            ui.separator();
+            for error in &import.errors {
+                ui.colored_label(Color32::RED, error.to_string());
+            }
+            for change in &import.changes {
+                let color = match change {
+                    ChannelChange::Added(_) => Color32::DARK_GREEN,
+                    ChannelChange::Modified(..) => Color32::GOLD,
+                    ChannelChange::Removed(_) => Color32::RED,
+                };
+                ui.colored_label(color, change.to_string());
+            }
+            ui.separator();
+            if !import.errors.is_empty() {
+                ui.label("Fix the rows above and import the file again.");
+            }
+            let enabled = import.errors.is_empty() && !import.changes.is_empty();
+            if ui.add_enabled(enabled, Button::new("Apply")).clicked() {
+                apply = true;
+            }
+        });
+    windows_open.channel_import = open;
 
-                        ui.end_row();
-                        ui.separator();
-                        ui.end_row();
-                        ui.label("Channel");
-                        ui.label("Value");
-                        ui.label("Alarm");
-                        ui.label("Tag");
-                        ui.label("Value type");
-                        ui.label("Access");
-                        ui.label("Address");
-                        ui.label("Device");
-                        ui.label("Status");
-                        ui.end_row();
-                        for _ in 0..9 {
-                            ui.separator();
-                        }
-                        ui.end_row();
-                        for channel in device.channels.clone() {
-                            let button = Button::new(format!("Calc{}", channel.id)).frame(true);
-                            if ui.add(button).clicked() {
-                                calculations_windows_buffer.selected_channel = channel.clone();
-                                windows_open.channel_config = !windows_open.channel_config;
-                                calculations_windows_buffer.edited_channel =
-                                    calculations_windows_buffer.selected_channel.clone();
-                            }
-                            // ui.label(format!("CH{}", channel.id));
-                            if channel.enabled {
-                                match channel.access_type {
-                                    AccessType::Write => {
-                                        ui.horizontal(|ui| {
-                                            ui.label(format!("{:.2}", channel.value));
-                                            if ui.button("Write").clicked() {
-                                                calculations_windows_buffer.selected_channel =
-                                                    channel.clone();
+    if apply {
+        let device_id = channel_windows_buffer.device_id;
+        if let (Some(import), Some(device)) = (
+            channel_windows_buffer.import.take(),
+            devices.get_mut(device_id),
+        ) {
+            device.replace_channels(import.channels);
+            channel_windows_buffer.table_status = "Import applied.".to_owned();
+            windows_open.channel_import = false;
+            windows_open.channel_config = false;
+            if let Some(device_beam) = device_beam.iter().nth(device_id) {
+                if let Some(updated_device) = device_beam.update.clone() {
+                    if updated_device.send.send(devices.to_vec()).is_ok() {}
+                }
+            }
+        }
+    }
+}
 
-                                                windows_open.channel_write_value =
-                                                    !windows_open.channel_write_value;
-                                            }
-                                        });
-                                    }
-                                    AccessType::Read => {
-                                        ui.label(format!("{:.2}", channel.value));
-                                    }
-                                };
-                            } else {
-                                ui.label("Disabled.");
-                            }
-                            let mut alarm = "";
-                            if channel.alarm.low.active {
-                                alarm = "LOW ALARM";
-                            }
+enum ChannelAction {
+    Add,
+    Clone(usize),
+    Remove(usize),
+    Move(usize, usize),
+}
 
-                            if channel.alarm.high.active {
-                                alarm = "HIGH ALARM";
-                            }
-                            if channel.alarm.low.active && channel.alarm.high.active {
-                                alarm = "LOW ALARM/HIGH ALARM";
-                            }
+// Shelving durations offered in the channels window, 0 unshelves.
+const SHELVE_DURATIONS: [(&str, u64); 4] = [
+    ("15 minutes", 900),
+    ("1 hour", 3600),
+    ("8 hours", 28800),
+    ("Unshelve", 0),
+];
 
-                            ui.colored_label(Color32::RED, alarm);
+fn shelve_alarms(device_msg_beam: &[DeviceMsgBeam], channel: &Channel, duration: u64) {
+    if let Some(device_msg_beam) = device_msg_beam.iter().nth(channel.device_id) {
+        let shelve = JsonShelveAlarm {
+            device_id: channel.device_id,
+            channel: channel.id,
+            alarm_type: None,
+            duration,
+            user: local_user(),
+        };
+        if device_msg_beam
+            .send
+            .send(DeviceMsg::ShelveAlarm(shelve))
+            .is_ok()
+        {}
+    }
+}
 
-                            ui.label(format!("{}", channel.tag));
-                            ui.label(format!("{}", channel.value_type));
-                            ui.label(format!("{}", channel.access_type));
-                            ui.label(format!("{}", channel.index));
-                            ui.label(format!("{}", channel.device_id));
-                            ui.label(format!("{}", channel.status));
-                            // if ui.small_button("Configure").clicked() {}
-                            ui.end_row();
+// The value followed by its state label, if it has one.
+fn channel_value_text(channel: &Channel) -> String {
+    match channel.state_text.is_empty() {
+        true => format!("{:.2}", channel.value),
+        false => format!("{:.0} {}", channel.value, channel.state_text),
+    }
+}
+
+// The name recorded with alarm acknowledgements made from the GUI.
+fn local_user() -> String {
+    std::env::var("USER")
+        .or_else(|_| std::env::var("USERNAME"))
+        .unwrap_or_else(|_| "GUI".to_owned())
+}
+
+pub fn calculations_window(
+    windows_open: &mut WindowsOpen,
+    ctx: &egui::Context,
+    calculations: &mut Vec<Calculation>,
+    calculation_windows_buffer: &mut CalculationWindowsBuffer,
+    tags: &TagDatabase,
+) {
+    Window::new("Calculations")
+        .open(&mut windows_open.calculations)
+        .scroll2([true, true])
+        .show(ctx, |ui| {
+            ui.label("Expressions are Rhai, channels are referenced as D0:CH3 or by tag.");
+            ui.separator();
+            let mut to_remove = None;
+            Grid::new("Calculation List")
+                .striped(true)
+                .num_columns(8)
+                .show(ui, |ui| {
+                    ui.label("Calculation");
+                    ui.label("Enabled");
+                    ui.label("Tag");
+                    ui.label("Expression");
+                    ui.label("Value");
+                    ui.label("Alarm");
+                    ui.label("Status");
+                    ui.label("");
+                    ui.end_row();
+                    for (i, calculation) in calculations.iter_mut().enumerate() {
+                        let button = Button::new(format!("EVAL{}", calculation.id)).frame(true);
+                        if ui.add(button).on_hover_text("Configure alarms").clicked() {
+                            calculation_windows_buffer.selected_calculation = Some(i);
                         }
+                        ui.checkbox(&mut calculation.enabled, "");
+                        let address = TagAddress::Calculation(calculation.id);
+                        let tag_text_color = match tags.check_unique(&calculation.tag, address) {
+                            Ok(_) => None,
+                            Err(_) => Some(Color32::RED),
+                        };
+                        ui.add(
+                            egui::TextEdit::singleline(&mut calculation.tag)
+                                .text_color_opt(tag_text_color)
+                                .desired_width(100.0),
+                        )
+                        .on_hover_text("Tags have to be unique across devices and calculations");
+                        ui.add(
+                            egui::TextEdit::singleline(&mut calculation.expression)
+                                .hint_text("D0:CH1 * 0.5 + Flow")
+                                .desired_width(240.0),
+                        );
+                        ui.label(format!("{:.2}", calculation.value));
+                        ui.colored_label(Color32::RED, calculation.alarm.active_text());
+                        match calculation.status.starts_with("ERROR") {
+                            true => ui.colored_label(Color32::RED, &calculation.status),
+                            false => ui.label(&calculation.status),
+                        };
+                        if ui.small_button("Remove").clicked() {
+                            to_remove = Some(i);
+                        }
+                        ui.end_row();
+                    }
+                });
+            if let Some(i) = to_remove {
+                calculations.remove(i);
+                calculation_windows_buffer.selected_calculation = None;
+            }
+            if ui.button("Add calculation").clicked() {
+                let id = calculations
+                    .iter()
+                    .map(|calculation| calculation.id + 1)
+                    .max()
+                    .unwrap_or_default();
+                calculations.push(Calculation {
+                    id,
+                    ..Default::default()
+                });
+            }
+            if let Some(calculation) = calculation_windows_buffer
+                .selected_calculation
+                .and_then(|i| calculations.get_mut(i))
+            {
+                ui.separator();
+                ui.label(format!("EVAL{} function block", calculation.id));
+                function_block_grid(ui, &mut calculation.block);
+                ui.separator();
+                ui.label(format!("EVAL{} alarms", calculation.id));
+                alarm_config_grid(ui, &mut calculation.alarm);
+            }
+        });
+}
+
+fn function_block_grid(ui: &mut egui::Ui, block: &mut FunctionBlock) {
+    Grid::new("Function block").num_columns(2).show(ui, |ui| {
+        ui.label("Block");
+        let previous_type = block.block_type;
+        ComboBox::from_id_source("Block type")
+            .selected_text(format!("{}", block.block_type))
+            .show_ui(ui, |ui| {
+                for block_type in [
+                    BlockType::None,
+                    BlockType::Totalizer,
+                    BlockType::MovingAverage,
+                    BlockType::MovingMedian,
+                    BlockType::WindowMin,
+                    BlockType::WindowMax,
+                    BlockType::Integral,
+                    BlockType::Counter,
+                    BlockType::Lag,
+                ] {
+                    ui.selectable_value(
+                        &mut block.block_type,
+                        block_type,
+                        format!("{}", block_type),
+                    );
+                }
+            });
+        // The old state means nothing to a different block.
+        if block.block_type != previous_type {
+            block.reset();
+        }
+        ui.end_row();
+        let parameter = match block.block_type {
+            BlockType::Totalizer => "Time base (s)",
+            BlockType::MovingAverage | BlockType::MovingMedian => "Window (samples)",
+            BlockType::WindowMin | BlockType::WindowMax => "Window (s)",
+            BlockType::Counter => "Rollover value",
+            BlockType::Lag => "Time constant (s)",
+            BlockType::None | BlockType::Integral => "",
+        };
+        ui.label(parameter);
+        ui.add_enabled(
+            !parameter.is_empty(),
+            DragValue::new(&mut block.parameter).clamp_range(0.0..=f32::MAX),
+        );
+        ui.end_row();
+        ui.label("Reset");
+        ui.add_enabled_ui(block.block_type == BlockType::Totalizer, |ui| {
+            ComboBox::from_id_source("Block reset")
+                .selected_text(format!("{}", block.reset))
+                .show_ui(ui, |ui| {
+                    for reset in [
+                        ResetSchedule::Never,
+                        ResetSchedule::Hourly,
+                        ResetSchedule::Daily,
+                        ResetSchedule::Monthly,
+                    ] {
+                        ui.selectable_value(&mut block.reset, reset, format!("{}", reset));
                     }
                 });
         });
+        ui.end_row();
+        ui.label(format!("Accumulated: {:.3}", block.state.total));
+        if ui.button("Reset state").clicked() {
+            block.reset();
+        }
+        ui.end_row();
+    });
 }
 pub fn channel_config_window(
     windows_open: &mut WindowsOpen,
@@ -280,6 +508,7 @@ pub fn channel_config_window(
     channel_windows_buffer: &mut ChannelWindowsBuffer,
     devices: &mut Vec<Device>,
     device_beam: &mut Vec<DeviceBeam>,
+    tags: &TagDatabase,
 ) {
     Window::new("Channel Configuration")
         .open(&mut windows_open.channel_config)
@@ -303,6 +532,9 @@ pub fn channel_config_window(
                     ui.label("Tag");
                     ui.text_edit_singleline(&mut channel_windows_buffer.edited_channel.tag);
                     ui.end_row();
+                    ui.label("Description");
+                    ui.text_edit_singleline(&mut channel_windows_buffer.edited_channel.description);
+                    ui.end_row();
                     ui.add(
                         Slider::new(&mut channel_windows_buffer.edited_channel.index, 0..=49999)
                             .text("Index"),
@@ -349,62 +581,120 @@ pub fn channel_config_window(
                             );
                         });
                     ui.end_row();
-                    ui.label("Low alarm:");
-                    ui.label("High alarm:");
-                    ui.end_row();
-                    ui.checkbox(
-                        &mut channel_windows_buffer.edited_channel.alarm.low.enabled,
-                        "Enabled",
-                    );
                     ui.checkbox(
-                        &mut channel_windows_buffer.edited_channel.alarm.high.enabled,
-                        "Enabled",
-                    );
+                        &mut channel_windows_buffer.edited_channel.verify_writes,
+                        "Verify writes",
+                    )
+                    .on_hover_text("Read the value back after each write");
                     ui.end_row();
-                    ui.add_enabled_ui(
-                        channel_windows_buffer.edited_channel.alarm.low.enabled,
-                        |ui| {
-                            ui.add(
-                                Slider::new(
-                                    &mut channel_windows_buffer.edited_channel.alarm.low.setpoint,
-                                    0.0..=1000.0,
-                                )
-                                .text(""),
+                    ui.horizontal(|ui| {
+                        ui.label("History depth:");
+                        ui.add(
+                            DragValue::new(
+                                &mut channel_windows_buffer.edited_channel.history_depth,
+                            )
+                            .speed(10.0),
+                        )
+                        .on_hover_text("Samples kept in memory for trends");
+                    });
+                    ui.end_row();
+                    ui.horizontal(|ui| {
+                        let scaling = &mut channel_windows_buffer.edited_channel.scaling;
+                        ui.label("Scaling: raw ×");
+                        ui.add(DragValue::new(&mut scaling.gain).speed(0.01));
+                        ui.label("+");
+                        ui.add(DragValue::new(&mut scaling.offset).speed(0.1));
+                    });
+                    ui.end_row();
+                    ComboBox::from_label("Deadband")
+                        .selected_text(format!(
+                            "{}",
+                            channel_windows_buffer.edited_channel.deadband.deadband_type
+                        ))
+                        .show_ui(ui, |ui| {
+                            ui.selectable_value(
+                                &mut channel_windows_buffer.edited_channel.deadband.deadband_type,
+                                DeadbandType::Disabled,
+                                format!("{}", DeadbandType::Disabled),
                             );
-                        },
-                    );
+                            ui.selectable_value(
+                                &mut channel_windows_buffer.edited_channel.deadband.deadband_type,
+                                DeadbandType::Absolute,
+                                format!("{}", DeadbandType::Absolute),
+                            );
+                            ui.selectable_value(
+                                &mut channel_windows_buffer.edited_channel.deadband.deadband_type,
+                                DeadbandType::Percent,
+                                format!("{}", DeadbandType::Percent),
+                            );
+                        });
+                    ui.end_row();
                     ui.add_enabled_ui(
-                        channel_windows_buffer.edited_channel.alarm.high.enabled,
+                        channel_windows_buffer.edited_channel.deadband.deadband_type
+                            != DeadbandType::Disabled,
                         |ui| {
                             ui.add(
                                 Slider::new(
-                                    &mut channel_windows_buffer.edited_channel.alarm.high.setpoint,
-                                    0.0..=1000.0,
+                                    &mut channel_windows_buffer.edited_channel.deadband.value,
+                                    0.0..=100.0,
                                 )
-                                .text(""),
+                                .text("Deadband value"),
                             );
                         },
                     );
-
                     ui.end_row();
                 });
+            ui.separator();
+            let edited_channel = &mut channel_windows_buffer.edited_channel;
+            ui.add_enabled_ui(edited_channel.access_type == AccessType::Write, |ui| {
+                write_limits_grid(ui, &mut edited_channel.write_limits);
+            });
+            ui.separator();
+            alarm_config_grid(ui, &mut channel_windows_buffer.edited_channel.alarm);
+            rate_and_deviation_config_grid(ui, &mut channel_windows_buffer.edited_channel.alarm);
+            ui.separator();
+            state_labels_grid(ui, &mut channel_windows_buffer.edited_channel.state_labels);
             ui.vertical_centered_justified(|ui| {
+                let address = TagAddress::Channel {
+                    device_id: channel_windows_buffer.device_id,
+                    channel: channel_windows_buffer.selected_channel.id,
+                };
                 if ui.button("Save").clicked() {
-                    devices[channel_windows_buffer.device_id].channels
-                        [channel_windows_buffer.selected_channel.id] =
-                        channel_windows_buffer.edited_channel.clone();
-                    if let Some(device_beam) =
-                        device_beam.iter().nth(channel_windows_buffer.device_id)
-                    {
-                        if let Some(updated_channel) = device_beam.update.clone() {
-                            if updated_channel.send.send(devices.to_vec()).is_ok() {}
+                    match tags.check_unique(&channel_windows_buffer.edited_channel.tag, address) {
+                        Ok(_) => {
+                            save_channel(channel_windows_buffer, devices, device_beam);
+                            channel_windows_buffer.status = "Saved.".to_owned();
                         }
+                        Err(e) => channel_windows_buffer.status = format!("Error: {}", e),
                     }
                 }
+                ui.label(&channel_windows_buffer.status);
             });
         });
 }
 
+fn save_channel(
+    channel_windows_buffer: &ChannelWindowsBuffer,
+    devices: &mut Vec<Device>,
+    device_beam: &mut Vec<DeviceBeam>,
+) {
+    if let Some(channel) = devices
+        .get_mut(channel_windows_buffer.device_id)
+        .and_then(|device| {
+            device
+                .channels
+                .get_mut(channel_windows_buffer.selected_channel.id)
+        })
+    {
+        *channel = channel_windows_buffer.edited_channel.clone();
+    }
+    if let Some(device_beam) = device_beam.iter().nth(channel_windows_buffer.device_id) {
+        if let Some(updated_channel) = device_beam.update.clone() {
+            if updated_channel.send.send(devices.to_vec()).is_ok() {}
+        }
+    }
+}
+
 pub fn device_config_window(
     windows_open: &mut WindowsOpen,
     ctx: &egui::Context,
@@ -413,16 +703,31 @@ pub fn device_config_window(
     device_msg_beam: &mut Vec<DeviceMsgBeam>,
     device_beam: &mut Vec<DeviceBeam>,
 ) {
-    Window::new("Modbus Device")
-        .open(&mut windows_open.modbus_device)
+    let device_id = device_windows_buffer.device_id;
+    Window::new("Device Configuration")
+        .open(&mut windows_open.device_config)
         .scroll2([false, true])
         .show(ctx, |ui| {
-            ui.label("Configuration");
+            ui.label(format!("D{} configuration", device_id));
             ui.separator();
             egui::Grid::new("add_device").num_columns(2).show(ui, |ui| {
                 ui.label("Device name:");
                 ui.text_edit_singleline(&mut device_windows_buffer.name);
                 ui.end_row();
+                ui.label("Connection:");
+                ui.horizontal(|ui| {
+                    ui.selectable_value(
+                        &mut device_windows_buffer.device_type,
+                        DeviceType::Tcp,
+                        "TCP",
+                    );
+                    ui.selectable_value(
+                        &mut device_windows_buffer.device_type,
+                        DeviceType::Serial,
+                        "Serial",
+                    );
+                });
+                ui.end_row();
                 match device_windows_buffer.device_type {
                     DeviceType::Tcp => {
                         ui.label("IP address:");
@@ -431,6 +736,22 @@ pub fn device_config_window(
                         ui.label("Port:");
                         ui.text_edit_singleline(&mut device_windows_buffer.port);
                         ui.end_row();
+                        ui.label("Unit id:");
+                        ui.text_edit_singleline(&mut device_windows_buffer.slave);
+                        ui.end_row();
+                        ui.label("Secondary address:")
+                            .on_hover_text("Leave empty for devices with a single network card");
+                        ui.text_edit_singleline(&mut device_windows_buffer.secondary_address);
+                        ui.end_row();
+                        ui.label("Secondary port:");
+                        ui.text_edit_singleline(&mut device_windows_buffer.secondary_port);
+                        ui.end_row();
+                        ui.label("Health check rate:");
+                        ui.add(
+                            Slider::new(&mut device_windows_buffer.health_check_rate, 1..=600)
+                                .text("Seconds"),
+                        );
+                        ui.end_row();
                     }
                     DeviceType::Serial => {
                         ui.label("COM port:");
@@ -442,41 +763,562 @@ pub fn device_config_window(
                         ui.label("Slave:");
                         ui.text_edit_singleline(&mut device_windows_buffer.slave);
                         ui.end_row();
+                        ui.label("Timeout:")
+                            .on_hover_text("How long the slave gets to answer on a shared line");
+                        ui.add(
+                            Slider::new(&mut device_windows_buffer.timeout, 50..=5000)
+                                .text("Milliseconds"),
+                        );
+                        ui.end_row();
                     }
                 }
                 ui.label("Scan rate:");
                 ui.add(Slider::new(&mut device_windows_buffer.scan_rate, 0..=60).text(""));
                 ui.end_row();
+                ui.label("Integrity rate:");
+                ui.add(
+                    Slider::new(&mut device_windows_buffer.integrity_rate, 1..=3600)
+                        .text("Seconds"),
+                );
+                ui.end_row();
             });
+            ui.separator();
+            heartbeat_grid(ui, device_windows_buffer);
+            ui.separator();
+            clock_sync_grid(ui, device_windows_buffer);
+            // Uses the saved settings, like the scheduled syncs.
+            if ui.button("Sync clock now").clicked() {
+                if let Some(device_msg) = device_msg_beam.iter().nth(device_id) {
+                    if device_msg.send.send(DeviceMsg::SyncClock).is_ok() {
+                        device_windows_buffer.status =
+                            "Clock sync requested, see the event viewer.".to_owned();
+                    }
+                }
+            }
+            ui.separator();
             ui.vertical_centered_justified(|ui| {
                 if ui.button("Save").clicked() {
-                    match device_windows_buffer.device_type {
-                        DeviceType::Tcp => {
-                            if let Ok(port) = device_windows_buffer.port.parse::<usize>() {
-                                let config = DeviceConfig::Tcp(TcpConfig {
-                                    address: device_windows_buffer.address.to_owned(),
-                                    port,
-                                });
-                                devices[1].name = device_windows_buffer.name.clone();
-                                devices[1].config = config.clone();
-                                device_windows_buffer.status =
-                                    "Device configuration saved successfully!".to_owned();
-                                if let Some(device_msg) = device_msg_beam.iter().nth(1) {
-                                    if device_msg.send.send(DeviceMsg::Reconnect(config)).is_ok() {}
-                                }
-                                if let Some(device_beam) = device_beam.iter().nth(1) {
-                                    if let Some(updated_device) = device_beam.update.clone() {
-                                        if updated_device.send.send(devices.to_vec()).is_ok() {}
-                                    }
-                                }
-                            } else {
-                                device_windows_buffer.status = "Error!".to_owned();
-                            }
+                    let config = match device_config_from_buffer(device_windows_buffer) {
+                        Ok(config) => config,
+                        Err(e) => {
+                            device_windows_buffer.status = format!("Error: {}", e);
+                            return;
+                        }
+                    };
+                    let device = match devices.get_mut(device_id) {
+                        Some(device) => device,
+                        None => {
+                            device_windows_buffer.status = "The device was removed.".to_owned();
+                            return;
+                        }
+                    };
+                    device.name = device_windows_buffer.name.clone();
+                    device.config = config.clone();
+                    device.scan_rate = device_windows_buffer.scan_rate;
+                    device.integrity_rate = device_windows_buffer.integrity_rate;
+                    device.heartbeat = device_windows_buffer.heartbeat.clone();
+                    device.watchdog = device_windows_buffer.watchdog.clone();
+                    device.clock_sync = device_windows_buffer.clock_sync.clone();
+                    device_windows_buffer.status =
+                        "Device configuration saved successfully!".to_owned();
+                    if let Some(device_msg) = device_msg_beam.iter().nth(device_id) {
+                        if device_msg.send.send(DeviceMsg::Reconnect(config)).is_ok() {}
+                    }
+                    if let Some(device_beam) = device_beam.iter().nth(device_id) {
+                        if let Some(updated_device) = device_beam.update.clone() {
+                            if updated_device.send.send(devices.to_vec()).is_ok() {}
                         }
-                        DeviceType::Serial => {}
                     }
                 }
                 ui.label(device_windows_buffer.status.to_owned());
             });
         });
 }
+
+// What we write to the device to tell it we're alive, and what it writes back.
+fn heartbeat_grid(ui: &mut egui::Ui, device_windows_buffer: &mut DeviceWindowsBuffer) {
+    let heartbeat = &mut device_windows_buffer.heartbeat;
+    let watchdog = &mut device_windows_buffer.watchdog;
+    Grid::new("device_heartbeat").num_columns(2).show(ui, |ui| {
+        ui.label("Heartbeat:")
+            .on_hover_text("Written at every interval so the device knows we're alive");
+        ui.checkbox(&mut heartbeat.enabled, "Enabled");
+        ui.end_row();
+        ui.label("Mode:");
+        ui.horizontal(|ui| {
+            ui.selectable_value(&mut heartbeat.mode, HeartbeatMode::Toggle, "Toggle coil");
+            ui.selectable_value(
+                &mut heartbeat.mode,
+                HeartbeatMode::Counter,
+                "Increment register",
+            );
+        });
+        ui.end_row();
+        ui.label("Address:");
+        ui.add(DragValue::new(&mut heartbeat.address));
+        ui.end_row();
+        ui.label("Interval:");
+        ui.add(Slider::new(&mut heartbeat.interval, 1..=600).text("Seconds"));
+        ui.end_row();
+        ui.label("Watchdog:")
+            .on_hover_text("Raises an alarm when the register stops changing");
+        ui.checkbox(&mut watchdog.enabled, "Enabled");
+        ui.end_row();
+        ui.label("Register:");
+        ui.add(DragValue::new(&mut watchdog.address));
+        ui.end_row();
+        ui.label("Timeout:");
+        ui.add(Slider::new(&mut watchdog.timeout, 1..=600).text("Seconds"));
+        ui.end_row();
+        ui.label("Priority:");
+        ComboBox::from_id_source("Watchdog priority")
+            .selected_text(format!("{}", watchdog.priority))
+            .show_ui(ui, |ui| {
+                for priority in [
+                    AlarmPriority::Low,
+                    AlarmPriority::Medium,
+                    AlarmPriority::High,
+                    AlarmPriority::Critical,
+                ] {
+                    ui.selectable_value(&mut watchdog.priority, priority, format!("{}", priority));
+                }
+            });
+        ui.end_row();
+    });
+}
+
+// Where the time of the host goes in the device, and how often.
+fn clock_sync_grid(ui: &mut egui::Ui, device_windows_buffer: &mut DeviceWindowsBuffer) {
+    let clock_sync = &mut device_windows_buffer.clock_sync;
+    Grid::new("device_clock_sync")
+        .num_columns(2)
+        .show(ui, |ui| {
+            ui.label("Clock sync:")
+                .on_hover_text("Writes the date and time of this computer to the device");
+            ui.checkbox(&mut clock_sync.enabled, "Enabled");
+            ui.end_row();
+            ui.label("Layout:");
+            ui.horizontal(|ui| {
+                ui.selectable_value(&mut clock_sync.layout, ClockLayout::Fields, "Y/M/D h:m:s")
+                    .on_hover_text("Six consecutive registers");
+                ui.selectable_value(
+                    &mut clock_sync.layout,
+                    ClockLayout::UnixTimestamp,
+                    "Unix timestamp",
+                )
+                .on_hover_text("Two registers");
+            });
+            ui.end_row();
+            ui.label("Time zone:");
+            ui.horizontal(|ui| {
+                ui.selectable_value(&mut clock_sync.zone, ClockZone::Local, "Local");
+                ui.selectable_value(&mut clock_sync.zone, ClockZone::Utc, "UTC");
+            });
+            ui.end_row();
+            ui.label("First register:");
+            ui.add(DragValue::new(&mut clock_sync.address));
+            ui.end_row();
+            if clock_sync.layout == ClockLayout::UnixTimestamp {
+                ui.label("Word order:");
+                ComboBox::from_id_source("Clock sync word order")
+                    .selected_text(format!("{}", clock_sync.word_order))
+                    .show_ui(ui, |ui| {
+                        for order in [
+                            WordOrder::Abcd,
+                            WordOrder::Cdab,
+                            WordOrder::Badc,
+                            WordOrder::Dcba,
+                        ] {
+                            ui.selectable_value(
+                                &mut clock_sync.word_order,
+                                order,
+                                format!("{}", order),
+                            );
+                        }
+                    });
+                ui.end_row();
+            }
+            ui.label("Interval:");
+            ui.add(Slider::new(&mut clock_sync.interval, 1..=1440).text("Minutes"));
+            ui.end_row();
+        });
+}
+
+fn device_config_from_buffer(
+    device_windows_buffer: &DeviceWindowsBuffer,
+) -> anyhow::Result<DeviceConfig> {
+    let config = match device_windows_buffer.device_type {
+        DeviceType::Tcp => {
+            let secondary = match device_windows_buffer.secondary_address.trim() {
+                "" => None,
+                address => Some(TcpEndpoint {
+                    address: address.to_owned(),
+                    port: device_windows_buffer.secondary_port.trim().parse()?,
+                }),
+            };
+            DeviceConfig::Tcp(TcpConfig {
+                address: device_windows_buffer.address.trim().to_owned(),
+                port: device_windows_buffer.port.trim().parse()?,
+                unit: device_windows_buffer.slave.trim().parse()?,
+                secondary,
+                health_check_rate: device_windows_buffer.health_check_rate,
+            })
+        }
+        DeviceType::Serial => {
+            // The parity isn't editable yet, so we keep the current one.
+            let parity = match &device_windows_buffer.config {
+                DeviceConfig::Serial(config) => config.parity,
+                DeviceConfig::Tcp(_) => Parity::NoneParity,
+            };
+            DeviceConfig::Serial(SerialConfig {
+                com_port: device_windows_buffer.path.trim().to_owned(),
+                baudrate: device_windows_buffer.baudrate.trim().parse()?,
+                slave: device_windows_buffer.slave.trim().parse()?,
+                parity,
+                timeout: device_windows_buffer.timeout,
+            })
+        }
+    };
+    Ok(config)
+}
+
+enum DeviceAction {
+    Add,
+    Clone(usize),
+    Remove(usize),
+    Move(usize, usize),
+}
+
+pub fn devices_window(
+    windows_open: &mut WindowsOpen,
+    ctx: &egui::Context,
+    devices: &mut Vec<Device>,
+    device_windows_buffer: &mut DeviceWindowsBuffer,
+    channel_windows_buffer: &mut ChannelWindowsBuffer,
+    devices_changed: &mut bool,
+) {
+    let mut open_config = false;
+    let mut open_channels = false;
+    Window::new("Devices")
+        .open(&mut windows_open.devices)
+        .scroll2([false, true])
+        .show(ctx, |ui| {
+            let mut action = None;
+            let num_devices = devices.len();
+            Grid::new("Device list")
+                .striped(true)
+                .num_columns(5)
+                .show(ui, |ui| {
+                    ui.label("Device");
+                    ui.label("Name");
+                    ui.label("Channels");
+                    ui.label("Status");
+                    ui.label("");
+                    ui.end_row();
+                    for (i, device) in devices.iter().enumerate() {
+                        ui.label(format!("D{}", device.id));
+                        ui.label(format!("{}", device));
+                        ui.label(format!("{}", device.channels.len()));
+                        ui.label(&device.status);
+                        ui.horizontal(|ui| {
+                            if ui.small_button("Configure").clicked() {
+                                device_windows_buffer.load(device);
+                                open_config = true;
+                            }
+                            if ui.small_button("Channels").clicked() {
+                                channel_windows_buffer.device_id = i;
+                                open_channels = true;
+                            }
+                            if ui.add_enabled(i > 0, Button::new("⬆").small()).clicked() {
+                                action = Some(DeviceAction::Move(i, i - 1));
+                            }
+                            if ui
+                                .add_enabled(i + 1 < num_devices, Button::new("⬇").small())
+                                .clicked()
+                            {
+                                action = Some(DeviceAction::Move(i, i + 1));
+                            }
+                            if ui.small_button("Clone").clicked() {
+                                action = Some(DeviceAction::Clone(i));
+                            }
+                            if ui.small_button("Remove").clicked() {
+                                action = Some(DeviceAction::Remove(i));
+                            }
+                        });
+                        ui.end_row();
+                    }
+                });
+            if ui.button("Add device").clicked() {
+                action = Some(DeviceAction::Add);
+            }
+            if let Some(action) = action {
+                match action {
+                    DeviceAction::Add => {
+                        let name = format!("Device {}", devices.len());
+                        devices.push(Device::initialize(devices.len(), name));
+                    }
+                    DeviceAction::Clone(i) => {
+                        let mut device = devices[i].clone();
+                        device.name = format!("{} copy", device.name);
+                        devices.insert(i + 1, device);
+                    }
+                    DeviceAction::Remove(i) => {
+                        devices.remove(i);
+                    }
+                    DeviceAction::Move(from, to) => {
+                        let device = devices.remove(from);
+                        devices.insert(to, device);
+                    }
+                }
+                renumber_devices(devices);
+                *devices_changed = true;
+            }
+        });
+    if open_config {
+        windows_open.device_config = true;
+    }
+    if open_channels {
+        windows_open.device_channels = true;
+    }
+}
+
+fn alarm_config_grid(ui: &mut egui::Ui, channel_alarm: &mut ChannelAlarm) {
+    Grid::new("Alarm config").num_columns(8).show(ui, |ui| {
+        ui.label("Alarm");
+        for alarm in channel_alarm.alarms() {
+            ui.label(format!("{}", alarm.alarm_type));
+        }
+        ui.end_row();
+        ui.label("Enabled");
+        for alarm in channel_alarm.alarms_mut() {
+            ui.checkbox(&mut alarm.enabled, "");
+        }
+        ui.end_row();
+        ui.label("Setpoint");
+        for alarm in channel_alarm.alarms_mut() {
+            ui.add_enabled(
+                alarm.enabled,
+                DragValue::new(&mut alarm.setpoint).speed(0.1),
+            );
+        }
+        ui.end_row();
+        ui.label("Hysteresis");
+        for alarm in channel_alarm.alarms_mut() {
+            ui.add_enabled(
+                alarm.enabled,
+                DragValue::new(&mut alarm.hysteresis)
+                    .speed(0.1)
+                    .clamp_range(0.0..=f32::MAX),
+            );
+        }
+        ui.end_row();
+        ui.label("On delay (s)");
+        for alarm in channel_alarm.alarms_mut() {
+            ui.add_enabled(alarm.enabled, DragValue::new(&mut alarm.on_delay));
+        }
+        ui.end_row();
+        ui.label("Off delay (s)");
+        for alarm in channel_alarm.alarms_mut() {
+            ui.add_enabled(alarm.enabled, DragValue::new(&mut alarm.off_delay));
+        }
+        ui.end_row();
+        ui.label("Priority");
+        for alarm in channel_alarm.alarms_mut() {
+            ui.add_enabled_ui(alarm.enabled, |ui| {
+                ComboBox::from_id_source(format!("{} priority", alarm.alarm_type))
+                    .selected_text(format!("{}", alarm.priority))
+                    .show_ui(ui, |ui| {
+                        for priority in [
+                            AlarmPriority::Low,
+                            AlarmPriority::Medium,
+                            AlarmPriority::High,
+                            AlarmPriority::Critical,
+                        ] {
+                            ui.selectable_value(
+                                &mut alarm.priority,
+                                priority,
+                                format!("{}", priority),
+                            );
+                        }
+                    });
+            });
+        }
+        ui.end_row();
+        ui.label("Latching");
+        for alarm in channel_alarm.alarms_mut() {
+            ui.add_enabled(alarm.enabled, egui::Checkbox::new(&mut alarm.latching, ""));
+        }
+        ui.end_row();
+        ui.label("State");
+        for alarm in channel_alarm.alarms() {
+            let state = match (alarm.is_shelved(), alarm.suppressed) {
+                (true, _) => "Shelved".to_owned(),
+                (false, true) => "Suppressed".to_owned(),
+                (false, false) => format!("{}", alarm.state),
+            };
+            ui.label(state).on_hover_text(format!(
+                "Last acknowledged by: {}\nLast shelved by: {}",
+                alarm.acked_by, alarm.shelved_by
+            ));
+        }
+        ui.end_row();
+        ui.label("Message");
+        for alarm in channel_alarm.alarms_mut() {
+            let hint = format!("{} ALARM", alarm.alarm_type).to_uppercase();
+            ui.add_enabled(
+                alarm.enabled,
+                egui::TextEdit::singleline(&mut alarm.message)
+                    .hint_text(hint)
+                    .desired_width(120.0),
+            );
+        }
+        ui.end_row();
+    });
+}
+
+fn write_limits_grid(ui: &mut egui::Ui, write_limits: &mut WriteLimits) {
+    // A limit is only kept while its box is ticked.
+    fn optional_limit(ui: &mut egui::Ui, label: &str, limit: &mut Option<f32>) {
+        let mut enabled = limit.is_some();
+        ui.checkbox(&mut enabled, label);
+        let mut value = limit.unwrap_or_default();
+        ui.add_enabled(enabled, DragValue::new(&mut value).speed(0.1));
+        *limit = match enabled {
+            true => Some(value),
+            false => None,
+        };
+        ui.end_row();
+    }
+    Grid::new("Write limits").num_columns(2).show(ui, |ui| {
+        optional_limit(ui, "Minimum", &mut write_limits.min);
+        optional_limit(ui, "Maximum", &mut write_limits.max);
+        optional_limit(ui, "Max step", &mut write_limits.max_step);
+        ui.label("Min interval (s)")
+            .on_hover_text("With the max step, bounds how fast a setpoint can move.");
+        ui.add(DragValue::new(&mut write_limits.min_interval));
+        ui.end_row();
+        ui.label("Interlock")
+            .on_hover_text("Writes are only allowed while this holds, e.g. Pump1 == 0");
+        ui.text_edit_singleline(&mut write_limits.interlock);
+        ui.end_row();
+        ui.checkbox(&mut write_limits.confirm, "Confirm writes")
+            .on_hover_text("Writes wait until they are confirmed a second time");
+        ui.end_row();
+    });
+}
+
+fn rate_and_deviation_config_grid(ui: &mut egui::Ui, channel_alarm: &mut ChannelAlarm) {
+    Grid::new("Rate and deviation config")
+        .num_columns(2)
+        .show(ui, |ui| {
+            ui.label("ROC window (s)")
+                .on_hover_text("The ROC setpoint is in units per second.");
+            ui.add_enabled(
+                channel_alarm.rate_of_change.enabled,
+                DragValue::new(&mut channel_alarm.rate_window),
+            );
+            ui.end_row();
+            ui.label("Deviation reference").on_hover_text(
+                "The deviation setpoint is the maximum absolute difference from a tag, \
+                 a channel of any device as D0:CH3, a calculation as EVAL1 or a fixed value.",
+            );
+            ui.add_enabled_ui(channel_alarm.deviation.enabled, |ui| {
+                ui.horizontal(|ui| {
+                    deviation_source_combo(ui, &mut channel_alarm.deviation_source);
+                    match &mut channel_alarm.deviation_source {
+                        DeviationSource::None => {}
+                        DeviationSource::Reference(reference) => {
+                            ui.text_edit_singleline(reference);
+                        }
+                        DeviationSource::Setpoint(setpoint) => {
+                            ui.add(DragValue::new(setpoint).speed(0.1));
+                        }
+                    }
+                });
+            });
+            ui.end_row();
+            ui.label("Suppress when").on_hover_text(
+                "The alarms are suppressed while this holds, e.g. Pump1 == 0 || D1:CH3 < 10",
+            );
+            ui.text_edit_singleline(&mut channel_alarm.suppress_when);
+            ui.end_row();
+            ui.label("Discrete trigger");
+            ui.add_enabled_ui(channel_alarm.discrete.enabled, |ui| {
+                ComboBox::from_id_source("Discrete trigger")
+                    .selected_text(format!("{}", channel_alarm.discrete_trigger))
+                    .show_ui(ui, |ui| {
+                        for trigger in [
+                            DiscreteTrigger::OnTrue,
+                            DiscreteTrigger::OnFalse,
+                            DiscreteTrigger::OnChange,
+                        ] {
+                            ui.selectable_value(
+                                &mut channel_alarm.discrete_trigger,
+                                trigger,
+                                format!("{}", trigger),
+                            );
+                        }
+                    });
+            });
+            ui.end_row();
+        });
+}
+
+fn state_labels_grid(ui: &mut egui::Ui, state_labels: &mut Vec<StateLabel>) {
+    ui.label("State labels");
+    let mut to_remove = None;
+    Grid::new("State labels").num_columns(3).show(ui, |ui| {
+        for (i, state) in state_labels.iter_mut().enumerate() {
+            ui.add(DragValue::new(&mut state.value));
+            ui.add(
+                egui::TextEdit::singleline(&mut state.label)
+                    .hint_text("Running")
+                    .desired_width(120.0),
+            );
+            if ui.small_button("Remove").clicked() {
+                to_remove = Some(i);
+            }
+            ui.end_row();
+        }
+    });
+    if let Some(i) = to_remove {
+        state_labels.remove(i);
+    }
+    if ui.small_button("Add state").clicked() {
+        let value = state_labels.iter().map(|state| state.value + 1).max();
+        state_labels.push(StateLabel {
+            value: value.unwrap_or_default(),
+            label: String::new(),
+        });
+    }
+}
+
+// Switching to another kind of source starts it empty.
+fn deviation_source_combo(ui: &mut egui::Ui, source: &mut DeviationSource) {
+    let selected = match source {
+        DeviationSource::None => "None",
+        DeviationSource::Reference(_) => "Reference",
+        DeviationSource::Setpoint(_) => "Setpoint",
+    };
+    ComboBox::from_id_source("Deviation source")
+        .selected_text(selected)
+        .show_ui(ui, |ui| {
+            if ui.selectable_label(selected == "None", "None").clicked() {
+                *source = DeviationSource::None;
+            }
+            if ui
+                .selectable_label(selected == "Reference", "Reference")
+                .clicked()
+                && selected != "Reference"
+            {
+                *source = DeviationSource::Reference(String::new());
+            }
+            if ui
+                .selectable_label(selected == "Setpoint", "Setpoint")
+                .clicked()
+                && selected != "Setpoint"
+            {
+                *source = DeviationSource::Setpoint(0.0);
+            }
+        });
+}
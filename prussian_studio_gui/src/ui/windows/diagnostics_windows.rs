@@ -0,0 +1,150 @@
+use chrono::{Local, TimeZone};
+use egui::{
+    plot::{Bar, BarChart, Plot},
+    Color32, ComboBox, Grid, ScrollArea, Window,
+};
+use lib_device::*;
+
+use crate::window::{DiagnosticsWindowBuffer, WindowsOpen};
+
+// Only the latest frames are listed, the export has all of them.
+const LISTED_RECORDS: usize = 200;
+
+// The request and response frames of a device, with their latencies.
+pub fn diagnostics_window(
+    windows_open: &mut WindowsOpen,
+    ctx: &egui::Context,
+    diagnostics_window_buffer: &mut DiagnosticsWindowBuffer,
+    traffic: &TrafficMonitor,
+    devices: &[Device],
+) {
+    let device_id = diagnostics_window_buffer.device_id;
+    let mut capturing = traffic.is_capturing(device_id);
+    if windows_open.diagnostics && capturing {
+        ctx.request_repaint();
+    }
+    Window::new("Diagnostics")
+        .open(&mut windows_open.diagnostics)
+        .scroll2([false, true])
+        .show(ctx, |ui| {
+            ui.horizontal(|ui| {
+                let selected_device = match devices.get(device_id) {
+                    Some(device) => format!("{}", device),
+                    None => format!("D{}", device_id),
+                };
+                ComboBox::from_label("Device")
+                    .selected_text(selected_device)
+                    .show_ui(ui, |ui| {
+                        for device in devices {
+                            ui.selectable_value(
+                                &mut diagnostics_window_buffer.device_id,
+                                device.id,
+                                format!("{}", device),
+                            );
+                        }
+                    });
+                if ui.checkbox(&mut capturing, "Capture traffic").changed() {
+                    traffic.set_capture(device_id, capturing);
+                }
+                if ui.button("Clear").clicked() {
+                    traffic.clear(device_id);
+                }
+                if ui.button("Export").clicked() {
+                    diagnostics_window_buffer.status = match export_traffic_log(traffic, device_id)
+                    {
+                        Ok(Some(path)) => format!("Exported to {}", path.display()),
+                        Ok(None) => String::new(),
+                        Err(e) => format!("ERROR: {}", e),
+                    };
+                }
+                ui.label(&diagnostics_window_buffer.status);
+            });
+            ui.separator();
+
+            let records = traffic.records(device_id);
+            let stats = LatencyStats::new(&records);
+            Grid::new("Latency statistics")
+                .num_columns(5)
+                .show(ui, |ui| {
+                    ui.label(format!("Requests: {}", stats.requests));
+                    ui.label(format!("Errors: {}", stats.errors));
+                    ui.label(format!("Min: {:.1} ms", millis(stats.min)));
+                    ui.label(format!("Mean: {:.1} ms", millis(stats.mean)));
+                    ui.label(format!("Max: {:.1} ms", millis(stats.max)));
+                    ui.end_row();
+                });
+            let bars = stats
+                .histogram
+                .iter()
+                .enumerate()
+                .map(|(bucket, count)| {
+                    let name = match LATENCY_BUCKETS.get(bucket) {
+                        Some(bound) => format!("< {} ms", bound),
+                        None => format!("≥ {} ms", LATENCY_BUCKETS[LATENCY_BUCKETS.len() - 1]),
+                    };
+                    Bar::new(bucket as f64, *count as f64).name(name)
+                })
+                .collect();
+            Plot::new("Latency histogram")
+                .height(120.0)
+                .allow_drag(false)
+                .allow_zoom(false)
+                .show(ui, |plot_ui| plot_ui.bar_chart(BarChart::new(bars)));
+            ui.separator();
+
+            ScrollArea::vertical().max_height(300.0).show(ui, |ui| {
+                Grid::new("Traffic records")
+                    .striped(true)
+                    .num_columns(5)
+                    .show(ui, |ui| {
+                        ui.label("Time");
+                        ui.label("Function");
+                        ui.label("Request");
+                        ui.label("Response");
+                        ui.label("Duration");
+                        ui.end_row();
+                        for record in records.iter().rev().take(LISTED_RECORDS) {
+                            ui.label(format_timestamp(record.timestamp));
+                            match &record.response {
+                                Ok(_) => ui.label(record.describe()),
+                                Err(_) => ui.colored_label(Color32::RED, record.describe()),
+                            };
+                            ui.monospace(hex(&record.request));
+                            match &record.response {
+                                Ok(response) => ui.monospace(hex(response)),
+                                Err(_) => ui.label(""),
+                            };
+                            ui.label(format!("{:.1} ms", millis(record.duration)));
+                            ui.end_row();
+                        }
+                    });
+            });
+        });
+}
+
+fn millis(duration: std::time::Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+fn format_timestamp(timestamp: i64) -> String {
+    match Local.timestamp_millis_opt(timestamp).single() {
+        Some(datetime) => datetime.format("%H:%M:%S%.3f").to_string(),
+        None => timestamp.to_string(),
+    }
+}
+
+fn export_traffic_log(
+    traffic: &TrafficMonitor,
+    device_id: usize,
+) -> anyhow::Result<Option<std::path::PathBuf>> {
+    let path = match rfd::FileDialog::new()
+        .add_filter("csv", &["csv"])
+        .set_file_name(&format!("D{}_traffic.csv", device_id))
+        .save_file()
+    {
+        Some(path) => path,
+        None => return Ok(None),
+    };
+    export_traffic(std::fs::File::create(&path)?, &traffic.records(device_id))?;
+    Ok(Some(path))
+}
@@ -0,0 +1,429 @@
+use std::time::Duration;
+
+use egui::{Button, Color32, ComboBox, DragValue, Grid, ProgressBar, Window};
+use lib_device::*;
+
+use crate::{
+    crossbeam::DeviceBeam,
+    window::{DiscoveryWindowBuffer, ProbeWindowBuffer, WindowsOpen},
+};
+
+// Scans a serial port or an IP range and adds what answered to the devices.
+pub fn discovery_window(
+    windows_open: &mut WindowsOpen,
+    ctx: &egui::Context,
+    discovery_window_buffer: &mut DiscoveryWindowBuffer,
+    devices: &mut Vec<Device>,
+    devices_changed: &mut bool,
+) {
+    receive_scan_events(discovery_window_buffer);
+    if discovery_window_buffer.scan.is_some() {
+        ctx.request_repaint();
+    }
+    Window::new("Device Discovery")
+        .open(&mut windows_open.discovery)
+        .scroll2([false, true])
+        .show(ctx, |ui| {
+            let buffer = &mut *discovery_window_buffer;
+            let scanning = buffer.scan.is_some();
+            ui.add_enabled_ui(!scanning, |ui| {
+                ui.horizontal(|ui| {
+                    ui.selectable_value(&mut buffer.serial, false, "TCP");
+                    ui.selectable_value(&mut buffer.serial, true, "Serial");
+                });
+                Grid::new("Discovery settings")
+                    .num_columns(2)
+                    .show(ui, |ui| {
+                        if buffer.serial {
+                            ui.label("COM port:");
+                            ui.text_edit_singleline(&mut buffer.com_port);
+                            ui.end_row();
+                            ui.label("Baudrate:");
+                            ui.text_edit_singleline(&mut buffer.baudrate);
+                            ui.end_row();
+                            ui.label("Unit ids:")
+                                .on_hover_text("A range such as 1-247.");
+                            ui.text_edit_singleline(&mut buffer.units);
+                            ui.end_row();
+                        } else {
+                            ui.label("IP addresses:")
+                                .on_hover_text("192.168.0.0/24 or 192.168.0.10-192.168.0.20.");
+                            ui.text_edit_singleline(&mut buffer.ips);
+                            ui.end_row();
+                            ui.label("Ports:")
+                                .on_hover_text("502 or a range such as 502-510.");
+                            ui.text_edit_singleline(&mut buffer.ports);
+                            ui.end_row();
+                            ui.label("Unit id:");
+                            ui.text_edit_singleline(&mut buffer.unit);
+                            ui.end_row();
+                        }
+                        ui.label("Timeout (ms):");
+                        ui.add(DragValue::new(&mut buffer.timeout).clamp_range(10..=5000));
+                        ui.end_row();
+                    });
+            });
+            ui.horizontal(|ui| {
+                if scanning {
+                    if ui.button("Cancel").clicked() {
+                        buffer.scan = None;
+                        buffer.status = "Scan cancelled.".to_owned();
+                    }
+                } else if ui.button("Scan").clicked() {
+                    match scan_target(buffer) {
+                        Ok(target) => {
+                            let timeout = Duration::from_millis(buffer.timeout);
+                            buffer.scan = Some(Scan::start(target, timeout));
+                            buffer.found.clear();
+                            buffer.progress = (0, 0);
+                            buffer.status = "Scanning...".to_owned();
+                        }
+                        Err(e) => buffer.status = format!("Error: {}", e),
+                    }
+                }
+                if ui.button("Open results").clicked() {
+                    match open_scan_results() {
+                        Ok(Some(found)) => {
+                            buffer.status = format!("{} devices loaded.", found.len());
+                            buffer.found = found;
+                        }
+                        Ok(None) => {}
+                        Err(e) => buffer.status = format!("Error: {}", e),
+                    }
+                }
+            });
+            if scanning {
+                let (done, total) = buffer.progress;
+                let progress = done as f32 / total.max(1) as f32;
+                ui.add(ProgressBar::new(progress).text(format!("{}/{}", done, total)));
+            }
+            ui.label(&buffer.status);
+            ui.separator();
+
+            let mut added = Vec::new();
+            Grid::new("Discovered devices")
+                .striped(true)
+                .num_columns(5)
+                .show(ui, |ui| {
+                    ui.label("Address");
+                    ui.label("Vendor");
+                    ui.label("Product");
+                    ui.label("Revision");
+                    ui.label("");
+                    ui.end_row();
+                    for (i, found) in buffer.found.iter().enumerate() {
+                        ui.label(found.to_string());
+                        let identification = found.identification.clone().unwrap_or_default();
+                        ui.label(identification.vendor);
+                        ui.label(identification.product_code);
+                        ui.label(identification.revision);
+                        if ui.small_button("Add").clicked() {
+                            added.push(i);
+                        }
+                        ui.end_row();
+                    }
+                });
+            if ui
+                .add_enabled(!buffer.found.is_empty(), egui::Button::new("Add all"))
+                .clicked()
+            {
+                added = (0..buffer.found.len()).collect();
+            }
+            if !added.is_empty() {
+                for i in &added {
+                    devices.push(buffer.found[*i].to_device(devices.len()));
+                }
+                for i in added.iter().rev() {
+                    buffer.found.remove(*i);
+                }
+                renumber_devices(devices);
+                *devices_changed = true;
+                buffer.status = format!("{} devices added.", added.len());
+            }
+        });
+}
+
+fn receive_scan_events(discovery_window_buffer: &mut DiscoveryWindowBuffer) {
+    let scan = match &discovery_window_buffer.scan {
+        Some(scan) => scan,
+        None => return,
+    };
+    let mut finished = false;
+    for event in scan.receive.try_iter() {
+        match event {
+            ScanEvent::Progress { done, total } => discovery_window_buffer.progress = (done, total),
+            ScanEvent::Found(found) => discovery_window_buffer.found.push(found),
+            ScanEvent::Failed(e) => {
+                discovery_window_buffer.status = format!("Error: {}", e);
+                finished = true;
+            }
+            ScanEvent::Finished => {
+                discovery_window_buffer.status = format!(
+                    "Scan finished, {} devices found.",
+                    discovery_window_buffer.found.len()
+                );
+                finished = true;
+            }
+        }
+    }
+    if finished {
+        discovery_window_buffer.scan = None;
+    }
+}
+
+fn scan_target(discovery_window_buffer: &DiscoveryWindowBuffer) -> anyhow::Result<ScanTarget> {
+    let buffer = discovery_window_buffer;
+    let target = match buffer.serial {
+        true => ScanTarget::Serial {
+            com_port: buffer.com_port.trim().to_owned(),
+            baudrate: buffer.baudrate.trim().parse()?,
+            units: parse_range(&buffer.units)?,
+        },
+        false => ScanTarget::Tcp {
+            addresses: parse_ip_range(&buffer.ips)?,
+            ports: parse_range(&buffer.ports)?,
+            unit: buffer.unit.trim().parse()?,
+        },
+    };
+    Ok(target)
+}
+
+// Results saved by `prussian-modbus discover --save`.
+fn open_scan_results() -> anyhow::Result<Option<Vec<DiscoveredDevice>>> {
+    let path = match rfd::FileDialog::new()
+        .add_filter("ron", &["ron"])
+        .pick_file()
+    {
+        Some(path) => path,
+        None => return Ok(None),
+    };
+    let found = ron::de::from_str(&std::fs::read_to_string(path)?)?;
+    Ok(Some(found))
+}
+
+// Walks the registers of a device, guesses what they hold and turns the
+// guesses into channels.
+pub fn probe_window(
+    windows_open: &mut WindowsOpen,
+    ctx: &egui::Context,
+    probe_window_buffer: &mut ProbeWindowBuffer,
+    devices: &mut [Device],
+    device_beam: &[DeviceBeam],
+) {
+    receive_probe_events(probe_window_buffer);
+    if probe_window_buffer.probe.is_some() {
+        ctx.request_repaint();
+    }
+    Window::new("Register Probe")
+        .open(&mut windows_open.probe)
+        .scroll2([false, true])
+        .show(ctx, |ui| {
+            let buffer = &mut *probe_window_buffer;
+            let probing = buffer.probe.is_some();
+            ui.add_enabled_ui(!probing, |ui| {
+                Grid::new("Probe settings").num_columns(2).show(ui, |ui| {
+                    ui.label("Device:");
+                    let selected = devices
+                        .get(buffer.device_id)
+                        .map(|device| format!("D{} {}", device.id, device.name))
+                        .unwrap_or_default();
+                    ComboBox::from_id_source("Probe device")
+                        .selected_text(selected)
+                        .show_ui(ui, |ui| {
+                            for device in devices.iter() {
+                                ui.selectable_value(
+                                    &mut buffer.device_id,
+                                    device.id,
+                                    format!("D{} {}", device.id, device.name),
+                                );
+                            }
+                        });
+                    ui.end_row();
+                    ui.label("Tables:");
+                    ui.horizontal(|ui| {
+                        ui.checkbox(&mut buffer.holding, "Holding");
+                        ui.checkbox(&mut buffer.input, "Input");
+                    });
+                    ui.end_row();
+                    ui.label("Addresses:");
+                    ui.horizontal(|ui| {
+                        ui.add(DragValue::new(&mut buffer.first));
+                        ui.label("to");
+                        ui.add(DragValue::new(&mut buffer.last));
+                    });
+                    ui.end_row();
+                    ui.label("Timeout (ms):");
+                    ui.add(DragValue::new(&mut buffer.timeout).clamp_range(10..=5000));
+                    ui.end_row();
+                });
+            });
+            ui.horizontal(|ui| {
+                if probing {
+                    if ui.button("Cancel").clicked() {
+                        buffer.probe = None;
+                        buffer.guesses = guess_types(&buffer.registers);
+                        buffer.status = "Probe cancelled.".to_owned();
+                    }
+                } else if ui.button("Probe").clicked() {
+                    start_probe(buffer, devices);
+                }
+            });
+            if probing {
+                let (done, total) = buffer.progress;
+                let progress = done as f32 / total.max(1) as f32;
+                ui.add(ProgressBar::new(progress).text(format!("{}/{}", done, total)));
+            }
+            ui.label(&buffer.status);
+            ui.separator();
+
+            let mut added = Vec::new();
+            Grid::new("Type guesses")
+                .striped(true)
+                .num_columns(6)
+                .show(ui, |ui| {
+                    ui.label("Table");
+                    ui.label("Address");
+                    ui.label("Type");
+                    ui.label("Order");
+                    ui.label("Value");
+                    ui.label("");
+                    ui.end_row();
+                    for guess in &buffer.guesses {
+                        ui.label(guess.table.to_string());
+                        ui.label(guess.address.to_string());
+                        ui.label(guess.data_type.to_string());
+                        ui.label(guess.order.to_string());
+                        ui.label(guess.value.to_string());
+                        let channel = guess.to_channel();
+                        let button = ui
+                            .add_enabled(channel.is_some(), Button::new("Add channel").small())
+                            .on_disabled_hover_text(
+                                "Channels read holding registers as Int16 or ABCD Real32.",
+                            );
+                        if let (true, Some(channel)) = (button.clicked(), channel) {
+                            added.push(channel);
+                        }
+                        ui.end_row();
+                    }
+                });
+            if ui
+                .add_enabled(!buffer.guesses.is_empty(), Button::new("Add all channels"))
+                .clicked()
+            {
+                added = buffer
+                    .guesses
+                    .iter()
+                    .filter_map(|guess| guess.to_channel())
+                    .collect();
+            }
+            if !added.is_empty() {
+                buffer.status = add_channels(buffer.device_id, added, devices, device_beam);
+            }
+
+            ui.collapsing("Registers", |ui| {
+                Grid::new("Probed registers")
+                    .striped(true)
+                    .num_columns(3)
+                    .show(ui, |ui| {
+                        for register in &buffer.registers {
+                            ui.label(register.table.to_string());
+                            ui.label(register.address.to_string());
+                            match register.state {
+                                RegisterState::Value(_) => ui.label(register.state.to_string()),
+                                _ => ui.colored_label(Color32::RED, register.state.to_string()),
+                            };
+                            ui.end_row();
+                        }
+                    });
+            });
+        });
+}
+
+fn start_probe(buffer: &mut ProbeWindowBuffer, devices: &[Device]) {
+    let device = match devices.get(buffer.device_id) {
+        Some(device) => device,
+        None => {
+            buffer.status = "Select a device.".to_owned();
+            return;
+        }
+    };
+    if buffer.first > buffer.last {
+        buffer.status = "The first address is after the last one.".to_owned();
+        return;
+    }
+    let mut ranges = Vec::new();
+    if buffer.holding {
+        ranges.push((RegisterTable::Holding, buffer.first, buffer.last));
+    }
+    if buffer.input {
+        ranges.push((RegisterTable::Input, buffer.first, buffer.last));
+    }
+    let timeout = Duration::from_millis(buffer.timeout);
+    buffer.probe = Some(Probe::start(device.config.clone(), ranges, timeout));
+    buffer.registers.clear();
+    buffer.guesses.clear();
+    buffer.progress = (0, 0);
+    // A serial port can't be shared with the worker polling it.
+    buffer.status = match device.config {
+        DeviceConfig::Serial(_) => "Probing, stop polling if the port is busy...".to_owned(),
+        DeviceConfig::Tcp(_) => "Probing...".to_owned(),
+    };
+}
+
+fn receive_probe_events(probe_window_buffer: &mut ProbeWindowBuffer) {
+    let probe = match &probe_window_buffer.probe {
+        Some(probe) => probe,
+        None => return,
+    };
+    let mut finished = false;
+    for event in probe.receive.try_iter() {
+        match event {
+            ProbeEvent::Progress { done, total } => probe_window_buffer.progress = (done, total),
+            ProbeEvent::Register(register) => probe_window_buffer.registers.push(register),
+            ProbeEvent::Failed(e) => {
+                probe_window_buffer.status = format!("Error: {}", e);
+                finished = true;
+            }
+            ProbeEvent::Finished => {
+                let answered = probe_window_buffer
+                    .registers
+                    .iter()
+                    .filter(|register| matches!(register.state, RegisterState::Value(_)))
+                    .count();
+                probe_window_buffer.status = format!(
+                    "Probe finished, {} of {} registers answered.",
+                    answered,
+                    probe_window_buffer.registers.len()
+                );
+                finished = true;
+            }
+        }
+    }
+    if finished {
+        probe_window_buffer.probe = None;
+        probe_window_buffer.guesses = guess_types(&probe_window_buffer.registers);
+    }
+}
+
+fn add_channels(
+    device_id: usize,
+    channels: Vec<Channel>,
+    devices: &mut [Device],
+    device_beam: &[DeviceBeam],
+) -> String {
+    let device = match devices.get_mut(device_id) {
+        Some(device) => device,
+        None => return "The device was removed.".to_owned(),
+    };
+    let count = channels.len();
+    for channel in channels {
+        device.push_channel(channel);
+    }
+    if let Some(updated_device) = device_beam
+        .get(device_id)
+        .and_then(|beam| beam.update.as_ref())
+    {
+        if updated_device.send.send(devices.to_vec()).is_ok() {}
+    }
+    format!("{} channels added to D{}.", count, device_id)
+}
@@ -0,0 +1,197 @@
+use std::time::Duration;
+
+use chrono::{Local, NaiveDateTime, TimeZone};
+use egui::{Color32, ComboBox, Grid, Window};
+use lib_device::{AlarmPriority, Device, Event, EventFilter, EventKind};
+
+use crate::{
+    app::EVENTS_URL,
+    window::{EventWindowBuffer, WindowsOpen},
+};
+
+const TIME_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+
+pub fn event_viewer_window(
+    windows_open: &mut WindowsOpen,
+    ctx: &egui::Context,
+    event_window_buffer: &mut EventWindowBuffer,
+    event_journal: &[Event],
+    devices: &[Device],
+) {
+    Window::new("Event Viewer")
+        .open(&mut windows_open.event_viewer)
+        .scroll2([true, true])
+        .show(ctx, |ui| {
+            Grid::new("Event filters").num_columns(2).show(ui, |ui| {
+                ui.label("From:");
+                ui.add(
+                    egui::TextEdit::singleline(&mut event_window_buffer.from)
+                        .hint_text("2022-11-20 08:00:00"),
+                );
+                ui.end_row();
+                ui.label("To:");
+                ui.add(
+                    egui::TextEdit::singleline(&mut event_window_buffer.to)
+                        .hint_text("2022-11-20 18:00:00"),
+                );
+                ui.end_row();
+                ui.label("Device:");
+                let selected_device = match event_window_buffer.device_id {
+                    Some(device_id) => match devices.iter().nth(device_id) {
+                        Some(device) => format!("{}", device),
+                        None => format!("D{}", device_id),
+                    },
+                    None => "All".to_owned(),
+                };
+                ComboBox::from_id_source("Event device")
+                    .selected_text(selected_device)
+                    .show_ui(ui, |ui| {
+                        ui.selectable_value(&mut event_window_buffer.device_id, None, "All");
+                        for device in devices {
+                            ui.selectable_value(
+                                &mut event_window_buffer.device_id,
+                                Some(device.id),
+                                format!("{}", device),
+                            );
+                        }
+                    });
+                ui.end_row();
+                ui.label("Minimum priority:");
+                let selected_priority = match event_window_buffer.min_priority {
+                    Some(priority) => format!("{}", priority),
+                    None => "Any".to_owned(),
+                };
+                ComboBox::from_id_source("Event priority")
+                    .selected_text(selected_priority)
+                    .show_ui(ui, |ui| {
+                        ui.selectable_value(&mut event_window_buffer.min_priority, None, "Any");
+                        for priority in [
+                            AlarmPriority::Low,
+                            AlarmPriority::Medium,
+                            AlarmPriority::High,
+                            AlarmPriority::Critical,
+                        ] {
+                            ui.selectable_value(
+                                &mut event_window_buffer.min_priority,
+                                Some(priority),
+                                format!("{}", priority),
+                            );
+                        }
+                    });
+                ui.end_row();
+            });
+
+            let filter = match event_filter(event_window_buffer) {
+                Ok(filter) => filter,
+                Err(e) => {
+                    ui.colored_label(Color32::RED, format!("{}", e));
+                    return;
+                }
+            };
+
+            ui.horizontal(|ui| {
+                ui.checkbox(
+                    &mut event_window_buffer.show_server_events,
+                    "Show server history",
+                );
+                if ui.button("Query server").clicked() {
+                    match query_server_events(&filter) {
+                        Ok(events) => {
+                            event_window_buffer.status =
+                                format!("{} events received from the server.", events.len());
+                            event_window_buffer.server_events = events;
+                            event_window_buffer.show_server_events = true;
+                        }
+                        Err(e) => event_window_buffer.status = format!("ERROR: {}", e),
+                    }
+                }
+                ui.label(&event_window_buffer.status);
+            });
+            ui.separator();
+
+            let events: Vec<&Event> = match event_window_buffer.show_server_events {
+                true => event_window_buffer.server_events.iter().collect(),
+                false => event_journal.iter().rev().collect(),
+            };
+
+            Grid::new("Event list")
+                .striped(true)
+                .num_columns(6)
+                .show(ui, |ui| {
+                    ui.label("Time");
+                    ui.label("Event");
+                    ui.label("Source");
+                    ui.label("Value");
+                    ui.label("Priority");
+                    ui.label("Message");
+                    ui.end_row();
+                    for event in events.into_iter().filter(|event| filter.matches(event)) {
+                        ui.label(format_timestamp(event.timestamp));
+                        match event.kind {
+                            EventKind::AlarmRaised | EventKind::DeviceDisconnected => {
+                                ui.colored_label(Color32::RED, format!("{}", event.kind))
+                            }
+                            _ => ui.label(format!("{}", event.kind)),
+                        };
+                        ui.label(event.source());
+                        match event.value {
+                            Some(value) => ui.label(format!("{:.2}", value)),
+                            None => ui.label(""),
+                        };
+                        match event.priority {
+                            Some(priority) => ui.label(format!("{}", priority)),
+                            None => ui.label(""),
+                        };
+                        ui.label(&event.message);
+                        ui.end_row();
+                    }
+                });
+        });
+}
+
+fn event_filter(event_window_buffer: &EventWindowBuffer) -> anyhow::Result<EventFilter> {
+    Ok(EventFilter {
+        from: parse_time(&event_window_buffer.from)?,
+        to: parse_time(&event_window_buffer.to)?,
+        device_id: event_window_buffer.device_id,
+        min_priority: event_window_buffer.min_priority,
+    })
+}
+
+// An empty field means the range is open on that side.
+fn parse_time(time: &str) -> anyhow::Result<Option<i64>> {
+    if time.trim().is_empty() {
+        return Ok(None);
+    }
+    let naive = NaiveDateTime::parse_from_str(time.trim(), TIME_FORMAT)
+        .map_err(|_| anyhow::anyhow!("Dates should look like 2022-11-20 08:00:00"))?;
+    match Local.from_local_datetime(&naive).single() {
+        Some(datetime) => Ok(Some(datetime.timestamp())),
+        None => anyhow::bail!("{} is not a valid local time", time),
+    }
+}
+
+fn format_timestamp(timestamp: i64) -> String {
+    match Local.timestamp_opt(timestamp, 0).single() {
+        Some(datetime) => datetime.format(TIME_FORMAT).to_string(),
+        None => timestamp.to_string(),
+    }
+}
+
+fn query_server_events(filter: &EventFilter) -> anyhow::Result<Vec<Event>> {
+    let mut request = ureq::get(EVENTS_URL).timeout(Duration::from_secs(2));
+    if let Some(from) = filter.from {
+        request = request.query("from", &from.to_string());
+    }
+    if let Some(to) = filter.to {
+        request = request.query("to", &to.to_string());
+    }
+    if let Some(device_id) = filter.device_id {
+        request = request.query("device_id", &device_id.to_string());
+    }
+    if let Some(min_priority) = filter.min_priority {
+        request = request.query("min_priority", &format!("{:?}", min_priority));
+    }
+    let events = request.call()?.into_json()?;
+    Ok(events)
+}
@@ -1,5 +1,6 @@
 use egui::{Color32, ComboBox, Grid, Slider, Window};
-use lib_logger::{parse_pattern, Logger, LoggerType};
+use lib_device::TagDatabase;
+use lib_logger::{parse_pattern_with_tags, Logger, LoggerType};
 use regex::Regex;
 use rfd::FileDialog;
 
@@ -11,6 +12,7 @@ pub fn logger_config_window(
     logger_window_buffer: &mut LoggerWindowBuffer,
     re: &mut (Regex, Regex),
     loggers: &mut Vec<Logger>,
+    tags: &TagDatabase,
 ) {
     Window::new("Configure Logger")
         .open(&mut windows_open.logger_configure)
@@ -51,10 +53,14 @@ pub fn logger_config_window(
                         egui::TextEdit::singleline(
                             &mut logger_window_buffer.channel_pattern.pattern,
                         )
-                        .hint_text("Ex: CH1-CH7, EVAL10-EVAL20"),
+                        .hint_text("Ex: CH1-CH7, EVAL10-EVAL20, Flow"),
                     );
                     ui.end_row();
-                    match parse_pattern(&logger_window_buffer.channel_pattern, (&re.0, &re.1)) {
+                    match parse_pattern_with_tags(
+                        &logger_window_buffer.channel_pattern,
+                        (&re.0, &re.1),
+                        tags,
+                    ) {
                         Ok(channels) => {
                             ui.colored_label(
                                 Color32::DARK_GREEN,
@@ -101,6 +107,7 @@ pub fn logger_config_window(
                                 logger_window_buffer.log_rate,
                                 false,
                                 (&re.0, &re.1),
+                                tags,
                             );
 
                             if let Ok(logger) = logger {
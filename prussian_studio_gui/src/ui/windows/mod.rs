@@ -1,2 +1,6 @@
 pub mod device_windows;
+pub mod diagnostics_windows;
+pub mod discovery_windows;
+pub mod event_windows;
 pub mod logger_windows;
+pub mod trend_windows;
@@ -0,0 +1,131 @@
+use std::time::Duration;
+
+use egui::{
+    plot::{Line, Plot, Value, Values},
+    ComboBox, DragValue, Grid, Window,
+};
+use lib_device::{now_millis, Device, History, HistoryFilter, Sample};
+
+use crate::{
+    app::HISTORY_URL,
+    window::{TrendWindowBuffer, WindowsOpen},
+};
+
+pub fn trend_window(
+    windows_open: &mut WindowsOpen,
+    ctx: &egui::Context,
+    trend_window_buffer: &mut TrendWindowBuffer,
+    history: &mut History,
+    devices: &[Device],
+) {
+    Window::new("Trends")
+        .open(&mut windows_open.trends)
+        .show(ctx, |ui| {
+            Grid::new("Trend settings").num_columns(2).show(ui, |ui| {
+                ui.label("Device:");
+                let selected_device = match devices.get(trend_window_buffer.device_id) {
+                    Some(device) => format!("{}", device),
+                    None => format!("D{}", trend_window_buffer.device_id),
+                };
+                ComboBox::from_id_source("Trend device")
+                    .selected_text(selected_device)
+                    .show_ui(ui, |ui| {
+                        for device in devices {
+                            ui.selectable_value(
+                                &mut trend_window_buffer.device_id,
+                                device.id,
+                                format!("{}", device),
+                            );
+                        }
+                    });
+                ui.end_row();
+                ui.label("Channel:");
+                let channels = match devices.get(trend_window_buffer.device_id) {
+                    Some(device) => device.channels.as_slice(),
+                    None => &[],
+                };
+                let selected_channel = match channels.get(trend_window_buffer.channel) {
+                    Some(channel) => format!("CH{} {}", channel.id, channel.tag),
+                    None => format!("CH{}", trend_window_buffer.channel),
+                };
+                ComboBox::from_id_source("Trend channel")
+                    .selected_text(selected_channel)
+                    .show_ui(ui, |ui| {
+                        for channel in channels.iter().filter(|channel| channel.enabled) {
+                            ui.selectable_value(
+                                &mut trend_window_buffer.channel,
+                                channel.id,
+                                format!("CH{} {}", channel.id, channel.tag),
+                            );
+                        }
+                    });
+                ui.end_row();
+                ui.label("Minutes:");
+                ui.add(DragValue::new(&mut trend_window_buffer.span).clamp_range(1..=1440));
+                ui.end_row();
+            });
+
+            let to = now_millis();
+            let from = to - trend_window_buffer.span as i64 * 60_000;
+
+            ui.horizontal(|ui| {
+                // The buffer starts empty after a restart, the server has kept what we missed.
+                if ui.button("Load from server").clicked() {
+                    let filter = HistoryFilter {
+                        device_id: trend_window_buffer.device_id,
+                        channel: trend_window_buffer.channel,
+                        from: Some(from),
+                        to: Some(to),
+                    };
+                    let depth = devices
+                        .get(filter.device_id)
+                        .and_then(|device| device.channels.get(filter.channel))
+                        .map(|channel| channel.history_depth)
+                        .unwrap_or_default();
+                    match query_server_history(&filter) {
+                        Ok(samples) => {
+                            trend_window_buffer.status =
+                                format!("{} samples received from the server.", samples.len());
+                            history.prepend(filter.device_id, filter.channel, depth, &samples);
+                        }
+                        Err(e) => trend_window_buffer.status = format!("ERROR: {}", e),
+                    }
+                }
+                ui.label(&trend_window_buffer.status);
+            });
+            ui.separator();
+
+            // Seconds relative to now, so the latest value is on the right edge.
+            let values: Vec<Value> = history
+                .query(
+                    trend_window_buffer.device_id,
+                    trend_window_buffer.channel,
+                    from,
+                    to,
+                )
+                .iter()
+                .map(|sample| {
+                    Value::new((sample.timestamp - to) as f64 / 1000.0, sample.value as f64)
+                })
+                .collect();
+            let line = Line::new(Values::from_values(values));
+            Plot::new("Trend plot")
+                .view_aspect(2.0)
+                .show(ui, |plot_ui| plot_ui.line(line));
+        });
+}
+
+fn query_server_history(filter: &HistoryFilter) -> anyhow::Result<Vec<Sample>> {
+    let mut request = ureq::get(HISTORY_URL)
+        .timeout(Duration::from_secs(2))
+        .query("device_id", &filter.device_id.to_string())
+        .query("channel", &filter.channel.to_string());
+    if let Some(from) = filter.from {
+        request = request.query("from", &from.to_string());
+    }
+    if let Some(to) = filter.to {
+        request = request.query("to", &to.to_string());
+    }
+    let samples = request.call()?.into_json()?;
+    Ok(samples)
+}
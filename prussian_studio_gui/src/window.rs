@@ -1,13 +1,16 @@
 use std::path::PathBuf;
 
-use lib_device::{Channel, Device, DeviceConfig};
+use lib_device::{
+    AlarmPriority, Channel, ChannelImport, ClockSyncConfig, Device, DeviceConfig, DiscoveredDevice,
+    Event, HeartbeatConfig, Probe, ProbedRegister, Scan, TypeGuess, WatchdogConfig,
+};
 use lib_logger::{ChannelPattern, LoggerType};
 use serde::{Deserialize, Serialize};
 
 #[derive(Default, Serialize, Deserialize)]
 pub struct WindowsOpen {
-    pub plc: bool,
-    pub modbus_device: bool,
+    pub devices: bool,
+    pub device_config: bool,
     pub compressor: bool,
     pub new_device: bool,
     pub device_channels: bool,
@@ -19,15 +22,28 @@ pub struct WindowsOpen {
     pub save_config: bool,
     pub load_config: bool,
     pub confirm_exit: bool,
+    pub event_viewer: bool,
+    pub calculations: bool,
+    pub channel_import: bool,
+    pub discovery: bool,
+    pub probe: bool,
+    pub trends: bool,
+    pub diagnostics: bool,
 }
-#[derive(Default, Serialize, Deserialize)]
+#[derive(Clone, Copy, Serialize, Deserialize, PartialEq)]
 pub enum DeviceType {
-    #[default]
     Tcp,
     Serial,
 }
+impl Default for DeviceType {
+    fn default() -> Self {
+        DeviceType::Tcp
+    }
+}
 #[derive(Default, Serialize, Deserialize)]
 pub struct DeviceWindowsBuffer {
+    // The index of the device being configured.
+    pub device_id: usize,
     pub device_type: DeviceType,
     pub name: String,
     pub address: String,
@@ -38,6 +54,55 @@ pub struct DeviceWindowsBuffer {
     pub config: DeviceConfig,
     pub status: String,
     pub scan_rate: u64,
+    pub integrity_rate: u64,
+    pub secondary_address: String,
+    pub secondary_port: String,
+    pub health_check_rate: u64,
+    // Milliseconds a serial slave gets to answer.
+    pub timeout: u64,
+    pub heartbeat: HeartbeatConfig,
+    pub watchdog: WatchdogConfig,
+    pub clock_sync: ClockSyncConfig,
+}
+impl DeviceWindowsBuffer {
+    // Fills the configuration window with the settings of a device.
+    pub fn load(&mut self, device: &Device) {
+        self.device_id = device.id;
+        self.name = device.name.clone();
+        self.scan_rate = device.scan_rate;
+        self.integrity_rate = device.integrity_rate;
+        self.heartbeat = device.heartbeat.clone();
+        self.watchdog = device.watchdog.clone();
+        self.clock_sync = device.clock_sync.clone();
+        self.config = device.config.clone();
+        self.status = String::new();
+        match &device.config {
+            DeviceConfig::Tcp(config) => {
+                self.device_type = DeviceType::Tcp;
+                self.address = config.address.clone();
+                self.port = config.port.to_string();
+                self.slave = config.unit.to_string();
+                self.health_check_rate = config.health_check_rate;
+                match &config.secondary {
+                    Some(secondary) => {
+                        self.secondary_address = secondary.address.clone();
+                        self.secondary_port = secondary.port.to_string();
+                    }
+                    None => {
+                        self.secondary_address = String::new();
+                        self.secondary_port = config.port.to_string();
+                    }
+                }
+            }
+            DeviceConfig::Serial(config) => {
+                self.device_type = DeviceType::Serial;
+                self.path = config.com_port.clone();
+                self.baudrate = config.baudrate.to_string();
+                self.slave = config.slave.to_string();
+                self.timeout = config.timeout;
+            }
+        }
+    }
 }
 #[derive(Default, Serialize, Deserialize)]
 pub struct ChannelWindowsBuffer {
@@ -45,7 +110,24 @@ pub struct ChannelWindowsBuffer {
     pub device_id: usize,
     pub selected_channel: Channel,
     pub edited_channel: Channel,
-    pub channel_write_value: Vec<String>,
+    pub channel_write_value: String,
+    // The result of the last write made from the write window.
+    #[serde(skip)]
+    pub write_status: String,
+    // The device and channel of a write made here that waits for confirmation.
+    #[serde(skip)]
+    pub awaiting_confirmation: Option<(usize, usize)>,
+    pub status: String,
+    // The result of the last CSV export or import.
+    pub table_status: String,
+    // A channel table read from a file, waiting to be applied.
+    #[serde(skip)]
+    pub import: Option<ChannelImport>,
+}
+#[derive(Default, Serialize, Deserialize)]
+pub struct CalculationWindowsBuffer {
+    // The index of the calculation whose alarms are being configured.
+    pub selected_calculation: Option<usize>,
 }
 #[derive(Default, Serialize, Deserialize)]
 pub struct LoggerWindowBuffer {
@@ -57,3 +139,104 @@ pub struct LoggerWindowBuffer {
     pub pattern_str: String,
     pub is_logging: bool,
 }
+#[derive(Default, Serialize, Deserialize)]
+pub struct EventWindowBuffer {
+    pub from: String,
+    pub to: String,
+    pub device_id: Option<usize>,
+    pub min_priority: Option<AlarmPriority>,
+    // Show the events queried from the server instead of this session's journal.
+    pub show_server_events: bool,
+    pub server_events: Vec<Event>,
+    pub status: String,
+}
+// The channel shown in the trend window and how far back it goes.
+pub struct TrendWindowBuffer {
+    pub device_id: usize,
+    pub channel: usize,
+    // Minutes of history on the plot.
+    pub span: u64,
+    pub status: String,
+}
+impl Default for TrendWindowBuffer {
+    fn default() -> Self {
+        Self {
+            device_id: 0,
+            channel: 0,
+            span: 10,
+            status: String::new(),
+        }
+    }
+}
+// The device whose traffic the diagnostics window shows.
+#[derive(Default)]
+pub struct DiagnosticsWindowBuffer {
+    pub device_id: usize,
+    pub status: String,
+}
+// The settings of the discovery window and what the last scan found.
+pub struct DiscoveryWindowBuffer {
+    pub serial: bool,
+    pub ips: String,
+    pub ports: String,
+    pub unit: String,
+    pub com_port: String,
+    pub baudrate: String,
+    pub units: String,
+    // Milliseconds a unit gets to answer.
+    pub timeout: u64,
+    pub scan: Option<Scan>,
+    pub progress: (usize, usize),
+    pub found: Vec<DiscoveredDevice>,
+    pub status: String,
+}
+impl Default for DiscoveryWindowBuffer {
+    fn default() -> Self {
+        Self {
+            serial: false,
+            ips: "192.168.0.0/24".to_owned(),
+            ports: "502".to_owned(),
+            unit: "255".to_owned(),
+            com_port: String::new(),
+            baudrate: "9600".to_owned(),
+            units: "1-247".to_owned(),
+            timeout: 200,
+            scan: None,
+            progress: (0, 0),
+            found: Vec::new(),
+            status: String::new(),
+        }
+    }
+}
+// The register ranges to probe on a device and what was found.
+pub struct ProbeWindowBuffer {
+    pub device_id: usize,
+    pub holding: bool,
+    pub input: bool,
+    pub first: u16,
+    pub last: u16,
+    // Milliseconds the device gets to answer.
+    pub timeout: u64,
+    pub probe: Option<Probe>,
+    pub progress: (usize, usize),
+    pub registers: Vec<ProbedRegister>,
+    pub guesses: Vec<TypeGuess>,
+    pub status: String,
+}
+impl Default for ProbeWindowBuffer {
+    fn default() -> Self {
+        Self {
+            device_id: 0,
+            holding: true,
+            input: true,
+            first: 0,
+            last: 99,
+            timeout: 500,
+            probe: None,
+            progress: (0, 0),
+            registers: Vec::new(),
+            guesses: Vec::new(),
+            status: String::new(),
+        }
+    }
+}
@@ -0,0 +1,623 @@
+use crate::{
+    crossbeam::{CrossBeamChannel, CrossBeamReportChannel, DeviceBeam, DeviceMsgBeam},
+    server::{WriteResultsSerialized, URL},
+};
+use crossbeam_channel::{unbounded, Receiver};
+use lib_device::{
+    alarm_events, channel_values_from_buffer, get_register_list, BusManager, ClockSync,
+    ConnectionPath, Device, DeviceConfig, DeviceMsg, Event, EventKind, ExceptionReporter,
+    Heartbeat, JsonAckAlarm, JsonConfirmWrite, JsonShelveAlarm, JsonWriteChannel, JsonWriteTag,
+    ModbusClient, TagAddress, TagDatabase, TrafficMonitor, WriteGuard, WriteOutcome, WriteResult,
+};
+use std::{
+    error::Error,
+    sync::{Arc, Mutex},
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+use tungstenite::{connect, Message};
+use url::Url;
+
+// How the workers reach their device: serial devices on the same port share
+// its bus, and every connection goes through the traffic capture.
+#[derive(Clone)]
+pub struct Connector {
+    buses: BusManager,
+    traffic: TrafficMonitor,
+}
+
+impl Connector {
+    fn connect(&self, device: &mut Device) -> Result<Box<dyn ModbusClient>, Box<dyn Error>> {
+        let ctx = device.connect(&self.buses)?;
+        Ok(self.traffic.wrap(ctx, device.id))
+    }
+
+    fn connect_path(
+        &self,
+        device: &mut Device,
+        path: ConnectionPath,
+    ) -> Result<Box<dyn ModbusClient>, Box<dyn Error>> {
+        let ctx = device.connect_path(path, &self.buses)?;
+        Ok(self.traffic.wrap(ctx, device.id))
+    }
+}
+
+// Starts one worker per device, their beams and handles are in the same order
+// as the devices. The workers read the other devices and the calculations
+// through the tags.
+pub fn spawn_device_threads(
+    devices: &[Device],
+    buses: &BusManager,
+    traffic: &TrafficMonitor,
+    tags: &Arc<Mutex<TagDatabase>>,
+) -> (Vec<DeviceBeam>, Vec<DeviceMsgBeam>, Vec<JoinHandle<()>>) {
+    let connector = Connector {
+        buses: buses.clone(),
+        traffic: traffic.clone(),
+    };
+    let mut device_beams = Vec::with_capacity(devices.len());
+    let mut device_msg_beams = Vec::with_capacity(devices.len());
+    let mut workers = Vec::with_capacity(devices.len());
+    for i in 0..devices.len() {
+        let (device_msg_s, device_msg_r) = unbounded();
+        let (read_s, read_r) = unbounded();
+        let (update_s, update_r) = unbounded();
+
+        let device_msg_channel = DeviceMsgBeam {
+            send: device_msg_s,
+            receive: device_msg_r,
+        };
+        let device_channel = DeviceBeam {
+            read: Some(CrossBeamReportChannel {
+                send: read_s,
+                receive: read_r,
+            }),
+            update: Some(CrossBeamChannel {
+                send: update_s,
+                receive: update_r,
+            }),
+        };
+
+        let worker = spawn_device_thread(
+            devices.to_vec(),
+            device_channel.clone(),
+            device_msg_channel.clone(),
+            connector.clone(),
+            Arc::clone(tags),
+            i,
+        );
+        device_beams.push(device_channel);
+        device_msg_beams.push(device_msg_channel);
+        workers.push(worker);
+    }
+    (device_beams, device_msg_beams, workers)
+}
+
+pub fn stop_device_threads(
+    device_beam: &mut Vec<DeviceBeam>,
+    device_msg_beam: &mut Vec<DeviceMsgBeam>,
+) {
+    for beam in device_msg_beam.iter() {
+        if beam.send.send(DeviceMsg::Stop).is_ok() {}
+    }
+    device_msg_beam.clear();
+    device_beam.clear();
+}
+
+pub fn spawn_device_thread(
+    mut devices_to_read: Vec<Device>,
+    device_beam: DeviceBeam,
+    device_msg_beam: DeviceMsgBeam,
+    connector: Connector,
+    tags: Arc<Mutex<TagDatabase>>,
+    i: usize,
+) -> JoinHandle<()> {
+    thread::spawn(move || {
+        // We reset the device status.
+        devices_to_read[i].status = "Initialized.".to_owned();
+        // We spin the loop that reads data from the device.
+        start_thread_loop(
+            device_beam,
+            device_msg_beam,
+            devices_to_read,
+            &connector,
+            &tags,
+            i,
+        )
+    })
+}
+
+pub fn start_thread_loop(
+    device_beam: DeviceBeam,
+    device_msg_beam: DeviceMsgBeam,
+    mut devices_to_read: Vec<Device>,
+    connector: &Connector,
+    tags: &Arc<Mutex<TagDatabase>>,
+    i: usize,
+) {
+    // The reporter outlives reconnections so we don't republish unchanged values,
+    // the guard so the write intervals and pending confirmations survive them
+    // and the heartbeat so its alarm is cleared once the device is back.
+    let mut reporter = ExceptionReporter::new();
+    let mut guard = WriteGuard::default();
+    let mut heartbeat = Heartbeat::default();
+    let mut clock_sync = ClockSync::default();
+    // Connecting through the secondary right away is a failover too.
+    let mut path = ConnectionPath::Primary;
+    loop {
+        // This allows us to update the device config from the main thread.
+        match connector.connect(&mut devices_to_read[i]) {
+            Ok(ctx) => {
+                devices_to_read[i].status = connected_status(&devices_to_read[i], true);
+                let mut events = vec![Event::new(
+                    EventKind::DeviceConnected,
+                    i,
+                    format!("{} connected", devices_to_read[i]),
+                )];
+                if devices_to_read[i].active_path != path {
+                    path = devices_to_read[i].active_path;
+                    events.push(path_switched(&devices_to_read[i]));
+                }
+                heartbeat.restart();
+                // This loop keeps on reading and updating device data.
+                // It only returns when the device stops answering or the worker is stopped.
+                let running = start_device_poll_loop(
+                    &device_beam,
+                    &mut devices_to_read,
+                    i,
+                    &device_msg_beam,
+                    connector,
+                    tags,
+                    &mut reporter,
+                    &mut guard,
+                    &mut heartbeat,
+                    &mut clock_sync,
+                    events,
+                    ctx,
+                );
+                if !running {
+                    return;
+                }
+                // The poll loop may have gone back to the primary.
+                path = devices_to_read[i].active_path;
+            }
+            Err(e) => {
+                // The poll loop already reported the disconnection, if any.
+                devices_to_read[i].status = format!("Error: {}", e);
+                send_report(
+                    &device_beam,
+                    &mut reporter,
+                    &devices_to_read[i],
+                    Vec::new(),
+                    Vec::new(),
+                );
+                thread::sleep(Duration::from_secs(devices_to_read[i].scan_rate.max(1)));
+                // Writes can't reach a disconnected device, they fail right away.
+                // Alarms are held here, so they can still be acknowledged and shelved.
+                let previous = devices_to_read[i].clone();
+                let mut writes = Vec::new();
+                for device_msg in device_msg_beam.receive.try_iter() {
+                    match device_msg {
+                        DeviceMsg::Reconnect(config) => devices_to_read[i].config = config,
+                        DeviceMsg::Stop => return,
+                        // Carried out once the device is back.
+                        DeviceMsg::SyncClock => clock_sync.request(),
+                        DeviceMsg::WriteChannel(request) => {
+                            let outcome = WriteOutcome::Failed("Device not connected".to_owned());
+                            writes.push(WriteResult::new(request, outcome));
+                        }
+                        DeviceMsg::ConfirmWrite(confirm) => {
+                            match guard.confirm(&devices_to_read[i], &confirm) {
+                                Some(Ok(request)) => {
+                                    let outcome =
+                                        WriteOutcome::Failed("Device not connected".to_owned());
+                                    writes.push(WriteResult::new(request, outcome));
+                                }
+                                Some(Err(result)) => writes.push(result),
+                                None => {}
+                            }
+                        }
+                        DeviceMsg::AckAlarm(ack) => {
+                            if let Some(channel) = devices_to_read[i].channels.get_mut(ack.channel)
+                            {
+                                channel.alarm.acknowledge(ack.alarm_type, &ack.user);
+                            }
+                        }
+                        DeviceMsg::ShelveAlarm(shelve) => {
+                            if let Some(channel) =
+                                devices_to_read[i].channels.get_mut(shelve.channel)
+                            {
+                                channel.alarm.shelve(
+                                    shelve.alarm_type,
+                                    shelve.duration,
+                                    &shelve.user,
+                                );
+                            }
+                        }
+                    }
+                }
+                let events = alarm_events(&previous, &devices_to_read[i]);
+                if !events.is_empty() || !writes.is_empty() {
+                    send_report(
+                        &device_beam,
+                        &mut reporter,
+                        &devices_to_read[i],
+                        events,
+                        writes,
+                    );
+                }
+            }
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn start_device_poll_loop(
+    device_beam: &DeviceBeam,
+    devices_to_read: &mut Vec<Device>,
+    i: usize,
+    device_msg_beam: &DeviceMsgBeam,
+    connector: &Connector,
+    tags: &Arc<Mutex<TagDatabase>>,
+    reporter: &mut ExceptionReporter,
+    guard: &mut WriteGuard,
+    heartbeat: &mut Heartbeat,
+    clock_sync: &mut ClockSync,
+    mut events: Vec<Event>,
+    mut ctx: Box<dyn ModbusClient>,
+) -> bool {
+    let mut writes = Vec::new();
+    let mut last_health_check = Instant::now();
+    loop {
+        // We check if there is any update from the main thread.
+        if let Some(crossbeam_channel) = device_beam.update.clone() {
+            if let Ok(received_devices) = crossbeam_channel.receive.try_recv() {
+                // The copy of the main thread may lag behind a failover.
+                let active_path = devices_to_read[i].active_path;
+                *devices_to_read = received_devices.clone();
+                devices_to_read[i].active_path = active_path;
+                devices_to_read[i].status = "Updated.".to_owned();
+            }
+        }
+        if let Some(health_check_rate) = health_check_rate(&devices_to_read[i]) {
+            if last_health_check.elapsed() >= health_check_rate {
+                last_health_check = Instant::now();
+                check_standby_path(&mut devices_to_read[i], connector, &mut ctx, &mut events);
+            }
+        }
+        // We keep a snapshot to find out which alarms changed during this cycle.
+        let previous = devices_to_read[i].clone();
+
+        // We check if there is any message to reconnect the device.
+        // We update the ctx with the config wrapped in the received message.
+        if let Ok(device_msg) = device_msg_beam.receive.try_recv() {
+            match device_msg {
+                DeviceMsg::Reconnect(config) => {
+                    let path = devices_to_read[i].active_path;
+                    devices_to_read[i].config = config;
+                    if let Ok(ctx_update) = connector.connect(&mut devices_to_read[i]) {
+                        ctx = ctx_update;
+                        if devices_to_read[i].active_path != path {
+                            events.push(path_switched(&devices_to_read[i]));
+                        }
+                    }
+                }
+                DeviceMsg::Stop => return false,
+                DeviceMsg::SyncClock => clock_sync.request(),
+                DeviceMsg::WriteChannel(request) => {
+                    let checked = guard.request(&devices_to_read[i], request);
+                    write_checked(
+                        &mut ctx,
+                        &devices_to_read[i],
+                        guard,
+                        checked,
+                        &mut events,
+                        &mut writes,
+                    );
+                }
+                DeviceMsg::ConfirmWrite(confirm) => {
+                    if let Some(checked) = guard.confirm(&devices_to_read[i], &confirm) {
+                        write_checked(
+                            &mut ctx,
+                            &devices_to_read[i],
+                            guard,
+                            checked,
+                            &mut events,
+                            &mut writes,
+                        );
+                    }
+                }
+                DeviceMsg::AckAlarm(ack) => {
+                    if let Some(channel) = devices_to_read[i].channels.get_mut(ack.channel) {
+                        channel.alarm.acknowledge(ack.alarm_type, &ack.user);
+                    }
+                }
+                DeviceMsg::ShelveAlarm(shelve) => {
+                    if let Some(channel) = devices_to_read[i].channels.get_mut(shelve.channel) {
+                        channel
+                            .alarm
+                            .shelve(shelve.alarm_type, shelve.duration, &shelve.user);
+                    }
+                }
+            }
+        }
+
+        let reg_list = get_register_list(&devices_to_read[i]);
+
+        // We poll data from the device and send it to the main GUI thread.
+
+        //let channels = devices_to_read[i].channels.clone();
+        //let mut channels_to_send = Vec::with_capacity(channels.len());
+
+        let mut read_buffer: Vec<u16> = Vec::new();
+
+        if let Some(start_register) = reg_list.iter().nth(0) {
+            match ctx.read_holding_registers(*start_register, reg_list.len() as u16) {
+                Ok(data) => read_buffer = data,
+                Err(e) => {
+                    // We hand over to the thread loop, which reconnects.
+                    devices_to_read[i].status = format!("Error: {}", e);
+                    events.append(&mut alarm_events(&previous, &devices_to_read[i]));
+                    events.push(Event::new(
+                        EventKind::DeviceDisconnected,
+                        i,
+                        format!("{} disconnected: {}", devices_to_read[i], e),
+                    ));
+                    send_report(device_beam, reporter, &devices_to_read[i], events, writes);
+                    return true;
+                }
+            }
+        }
+
+        devices_to_read[i] =
+            channel_values_from_buffer(devices_to_read[i].clone(), reg_list, read_buffer);
+        devices_to_read[i].read_coils(&mut ctx);
+        {
+            let tags = tags.lock().unwrap_or_else(|e| e.into_inner());
+            devices_to_read[i].process_deviation_alarms(&tags);
+            devices_to_read[i].process_suppression(&tags);
+        }
+        events.append(&mut alarm_events(&previous, &devices_to_read[i]));
+        events.append(&mut heartbeat.process(&mut devices_to_read[i], ctx.as_mut()));
+        events.extend(clock_sync.process(&devices_to_read[i], ctx.as_mut()));
+
+        // Send the read data to the main GUI thread, but only if something
+        // moved outside its deadband or an integrity refresh is due.
+        send_report(
+            device_beam,
+            reporter,
+            &devices_to_read[i],
+            std::mem::take(&mut events),
+            std::mem::take(&mut writes),
+        );
+
+        // The thread sleeps.
+        thread::sleep(Duration::from_secs(devices_to_read[i].scan_rate));
+    }
+}
+
+// The worker only checks the standby path of redundant devices.
+fn health_check_rate(device: &Device) -> Option<Duration> {
+    match &device.config {
+        DeviceConfig::Tcp(config) if config.secondary.is_some() => {
+            Some(Duration::from_secs(config.health_check_rate.max(1)))
+        }
+        _ => None,
+    }
+}
+
+// Goes back to the primary as soon as it answers, on the primary we only
+// find out whether the secondary would be there if we needed it.
+fn check_standby_path(
+    device: &mut Device,
+    connector: &Connector,
+    ctx: &mut Box<dyn ModbusClient>,
+    events: &mut Vec<Event>,
+) {
+    match device.active_path {
+        ConnectionPath::Secondary => {
+            if let Ok(primary) = connector.connect_path(device, ConnectionPath::Primary) {
+                *ctx = primary;
+                device.status = connected_status(device, true);
+                events.push(path_switched(device));
+            }
+        }
+        ConnectionPath::Primary => {
+            let available = device.check_path(ConnectionPath::Secondary);
+            device.status = connected_status(device, available);
+        }
+    }
+}
+
+fn connected_status(device: &Device, standby_available: bool) -> String {
+    match (device.config.is_redundant(), standby_available) {
+        (false, _) => "Connected.".to_owned(),
+        (true, true) => format!("Connected via {}.", device.active_path),
+        (true, false) => format!("Connected via {}, standby unavailable.", device.active_path),
+    }
+}
+
+fn path_switched(device: &Device) -> Event {
+    Event::new(
+        EventKind::PathSwitched,
+        device.id,
+        format!("{} switched to its {} path", device, device.active_path),
+    )
+}
+
+fn send_report(
+    device_beam: &DeviceBeam,
+    reporter: &mut ExceptionReporter,
+    device: &Device,
+    events: Vec<Event>,
+    writes: Vec<WriteResult>,
+) {
+    if let Some(report) = reporter.report(device, events, writes) {
+        if let Some(crossbeam_channel) = device_beam.read.clone() {
+            if let Ok(_) = crossbeam_channel.send.send(report) {}
+        }
+    }
+}
+
+// Writes what the guard let through, every write gets a result and an event
+// but the ones waiting for confirmation.
+fn write_checked(
+    ctx: &mut dyn ModbusClient,
+    device: &Device,
+    guard: &mut WriteGuard,
+    checked: Result<JsonWriteChannel, WriteResult>,
+    events: &mut Vec<Event>,
+    writes: &mut Vec<WriteResult>,
+) {
+    let result = match checked {
+        Ok(request) => {
+            let outcome = match device.channels.get(request.channel) {
+                Some(channel) => channel.write(ctx, request.value),
+                None => WriteOutcome::Failed("Channel no longer exists".to_owned()),
+            };
+            guard.written(request.channel);
+            WriteResult::new(request, outcome)
+        }
+        Err(result) => result,
+    };
+    let kind = match &result.outcome {
+        WriteOutcome::Success => Some(EventKind::ChannelWritten),
+        WriteOutcome::AwaitingConfirmation => None,
+        _ => Some(EventKind::WriteFailed),
+    };
+    if let Some(kind) = kind {
+        events.push(Event {
+            channel: Some(result.channel),
+            value: Some(result.value),
+            ..Event::new(kind, device.id, result.to_string())
+        });
+    }
+    writes.push(result);
+}
+
+// pub fn spawn_socket_recv(socket_channel: CrossBeamSocketChannel) {
+//     thread::spawn(move || {
+//         if let Ok((mut socket, _)) = connect(Url::parse(URL).unwrap()) {
+//             loop {
+//                 if let Ok(msg) = socket.read_message() {
+//                     if let Ok(json_write_channel) = serde_json::from_str(msg.to_text().unwrap()) {
+//                         if socket_channel.send.send(json_write_channel).is_ok() {
+//                             println!("Channel serialized!");
+//                         }
+//                     }
+//                 } else {
+//                     if let Ok((socket_reconn, _)) = connect(Url::parse(URL).unwrap()) {
+//                         socket = socket_reconn;
+//                     }
+//                 }
+//             }
+//         };
+//     });
+// }
+// The thread lives as long as the app, the workers it routes to are
+// replaced through `beams_update` whenever the device list changes.
+pub fn spawn_socket_write_msg(
+    mut device_msg_beams: Vec<DeviceMsgBeam>,
+    beams_update: Receiver<Vec<DeviceMsgBeam>>,
+    tags: Arc<Mutex<TagDatabase>>,
+) {
+    thread::spawn(move || {
+        if let Ok((mut socket, _)) = connect(Url::parse(URL).unwrap()) {
+            loop {
+                if let Ok(msg) = socket.read_message() {
+                    if let Some(beams) = beams_update.try_iter().last() {
+                        device_msg_beams = beams;
+                    }
+                    let text = msg.to_text().unwrap_or_default();
+                    if let Ok(json_write_channel) = serde_json::from_str(text) {
+                        let channel: JsonWriteChannel = json_write_channel;
+                        if let Some(device_msg_beam) = device_msg_beams.get(channel.device_id) {
+                            if device_msg_beam
+                                .send
+                                .send(DeviceMsg::WriteChannel(channel))
+                                .is_ok()
+                            {}
+                        }
+                    } else if let Ok(json_write_tag) = serde_json::from_str(text) {
+                        let write: JsonWriteTag = json_write_tag;
+                        let (channel, address) = match tags.lock() {
+                            Ok(tags) => {
+                                (tags.write(&write.tag, write.value), tags.lookup(&write.tag))
+                            }
+                            Err(_) => continue,
+                        };
+                        match channel {
+                            Ok(mut channel) => {
+                                channel.request_id = write.request_id;
+                                if let Some(device_msg_beam) =
+                                    device_msg_beams.get(channel.device_id)
+                                {
+                                    if device_msg_beam
+                                        .send
+                                        .send(DeviceMsg::WriteChannel(channel))
+                                        .is_ok()
+                                    {}
+                                }
+                            }
+                            // Rejected before reaching a worker, so the result is sent from
+                            // here. An unknown tag has no channel, the request id tells the
+                            // client which write it was.
+                            Err(e) => {
+                                let (device_id, channel) = match address {
+                                    Some(TagAddress::Channel { device_id, channel }) => {
+                                        (device_id, channel)
+                                    }
+                                    _ => (0, 0),
+                                };
+                                let request = JsonWriteChannel {
+                                    device_id,
+                                    channel,
+                                    value: write.value,
+                                    request_id: write.request_id,
+                                    ..Default::default()
+                                };
+                                let results = WriteResultsSerialized {
+                                    write_results: vec![WriteResult::new(
+                                        request,
+                                        WriteOutcome::Rejected(e.to_string()),
+                                    )],
+                                };
+                                if let Ok(json) = serde_json::to_string(&results) {
+                                    if socket.write_message(Message::Text(json)).is_ok() {}
+                                }
+                            }
+                        }
+                    } else if let Ok(json_shelve_alarm) = serde_json::from_str(text) {
+                        // Tried before acknowledgements, which have the same fields but the duration.
+                        let shelve: JsonShelveAlarm = json_shelve_alarm;
+                        if let Some(device_msg_beam) = device_msg_beams.get(shelve.device_id) {
+                            if device_msg_beam
+                                .send
+                                .send(DeviceMsg::ShelveAlarm(shelve))
+                                .is_ok()
+                            {}
+                        }
+                    } else if let Ok(json_ack_alarm) = serde_json::from_str(text) {
+                        let ack: JsonAckAlarm = json_ack_alarm;
+                        if let Some(device_msg_beam) = device_msg_beams.get(ack.device_id) {
+                            if device_msg_beam.send.send(DeviceMsg::AckAlarm(ack)).is_ok() {}
+                        }
+                    } else if let Ok(json_confirm_write) = serde_json::from_str(text) {
+                        let confirm: JsonConfirmWrite = json_confirm_write;
+                        if let Some(device_msg_beam) = device_msg_beams.get(confirm.device_id) {
+                            if device_msg_beam
+                                .send
+                                .send(DeviceMsg::ConfirmWrite(confirm))
+                                .is_ok()
+                            {}
+                        }
+                    }
+                } else {
+                    if let Ok((socket_reconn, _)) = connect(Url::parse(URL).unwrap()) {
+                        socket = socket_reconn;
+                    }
+                }
+            }
+        };
+    });
+}
@@ -0,0 +1,232 @@
+use lib_device::{
+    check_tags, renumber_devices, Calculation, DeviationSource, Device, TagAddress, ValueType,
+};
+use lib_logger::Logger;
+use ron::ser::{to_string_pretty, PrettyConfig};
+use serde::{Deserialize, Serialize};
+
+// The version of the project files written by this build. New fields only need
+// a serde default, a migration is added when older files have to be changed
+// to mean the same thing, and the version is bumped.
+pub const CONFIG_VERSION: u32 = 2;
+
+// MIGRATIONS[n] upgrades a project from version n to n + 1.
+const MIGRATIONS: [fn(&mut AppConfig); CONFIG_VERSION as usize] =
+    [migrate_v0_to_v1, migrate_v1_to_v2];
+
+#[derive(Serialize, Deserialize)]
+pub struct AppConfig {
+    // Files written before versioning have none, they're version 0.
+    #[serde(default)]
+    pub version: u32,
+    pub devices: Vec<Device>,
+    pub loggers: Vec<Logger>,
+    #[serde(default)]
+    pub calculations: Vec<Calculation>,
+    // What the migrations couldn't change for the user, shown once the
+    // project is loaded.
+    #[serde(skip)]
+    pub warnings: Vec<String>,
+}
+
+// Only the version is read first, so we can refuse files from newer builds
+// before trying to make sense of the rest.
+#[derive(Deserialize)]
+struct ConfigVersion {
+    #[serde(default)]
+    version: u32,
+}
+
+impl AppConfig {
+    pub fn new(devices: Vec<Device>, loggers: Vec<Logger>, calculations: Vec<Calculation>) -> Self {
+        Self {
+            version: CONFIG_VERSION,
+            devices,
+            loggers,
+            calculations,
+            warnings: Vec::new(),
+        }
+    }
+
+    // Reads a project file of any known version and migrates it to the current one.
+    pub fn from_ron(s: &str) -> anyhow::Result<Self> {
+        let version = ron::de::from_str::<ConfigVersion>(s)
+            .map_err(|e| anyhow::anyhow!("Not a project file: {}", e))?
+            .version;
+        if version > CONFIG_VERSION {
+            anyhow::bail!(
+                "The project is version {}, this build only reads up to version {}",
+                version,
+                CONFIG_VERSION
+            );
+        }
+        let mut config: AppConfig = ron::de::from_str(s)
+            .map_err(|e| anyhow::anyhow!("Invalid version {} project: {}", version, e))?;
+        for migration in &MIGRATIONS[version as usize..] {
+            migration(&mut config);
+        }
+        config.version = CONFIG_VERSION;
+        // A duplicate tag would silently resolve to its first channel.
+        check_tags(&config.devices, &config.calculations)?;
+        Ok(config)
+    }
+
+    pub fn to_ron(&self) -> anyhow::Result<String> {
+        let pretty = PrettyConfig::new()
+            .depth_limit(7)
+            .separate_tuple_members(true)
+            .enumerate_arrays(true);
+        Ok(to_string_pretty(self, pretty)?)
+    }
+}
+
+// Devices and channels are now addressed by their position, older files could
+// have ids that don't match it. Runtime state saved along with them is reset.
+fn migrate_v0_to_v1(config: &mut AppConfig) {
+    renumber_devices(&mut config.devices);
+    for device in config.devices.iter_mut() {
+        device.status = "Initialized".to_owned();
+        for channel in device.channels.iter_mut() {
+            channel.status = "Initialized".to_owned();
+        }
+    }
+    // Bool channels read inverted before versioning. Whether an alarm or a
+    // calculation relies on the old value can't be told from the file, so
+    // they're left alone and pointed out.
+    let bools: Vec<String> = config
+        .devices
+        .iter()
+        .flat_map(|device| device.channels.iter())
+        .filter(|channel| channel.value_type == ValueType::BoolType)
+        .map(|channel| {
+            TagAddress::Channel {
+                device_id: channel.device_id,
+                channel: channel.id,
+            }
+            .to_string()
+        })
+        .collect();
+    if !bools.is_empty() {
+        config.warnings.push(format!(
+            "Bool channels now read 1.0 when the coil is on, check the alarms and calculations using {}",
+            bools.join(", ")
+        ));
+    }
+}
+
+// Deviation alarms compared against a channel of the same device by its id,
+// they now take a reference to any channel or calculation, or a setpoint.
+fn migrate_v1_to_v2(config: &mut AppConfig) {
+    for device in config.devices.iter_mut() {
+        let tags: Vec<(usize, String)> = device
+            .channels
+            .iter()
+            .map(|channel| (channel.id, channel.tag.trim().to_owned()))
+            .collect();
+        for channel in device.channels.iter_mut() {
+            let id = match channel.alarm.deviation_reference.take() {
+                Some(id) => id,
+                None => continue,
+            };
+            // By tag when there's one, it survives renumbering.
+            let reference = match tags.iter().find(|(channel, _)| *channel == id) {
+                Some((_, tag)) if !tag.is_empty() => tag.clone(),
+                _ => format!("D{}:CH{}", device.id, id),
+            };
+            channel.alarm.deviation_source = DeviationSource::Reference(reference);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use lib_device::DeviationSource;
+
+    use super::{migrate_v1_to_v2, AppConfig, CONFIG_VERSION};
+
+    const SAMPLES: [&str; 3] = [
+        include_str!("../../prussian_studio_gui/config.ron"),
+        include_str!("../../prussian_studio_gui/config_file.ron"),
+        include_str!("../../prussian_studio_gui/src/config.ron"),
+    ];
+
+    #[test]
+    fn config_round_trip_test() {
+        for sample in SAMPLES {
+            let config = AppConfig::from_ron(sample).unwrap();
+            assert_eq!(config.version, CONFIG_VERSION);
+            for (i, device) in config.devices.iter().enumerate() {
+                assert_eq!(device.id, i);
+                assert!(device.channels.iter().all(|channel| channel.device_id == i));
+            }
+
+            let saved = config.to_ron().unwrap();
+            let reloaded = AppConfig::from_ron(&saved).unwrap();
+            assert_eq!(reloaded.devices, config.devices);
+            assert!(reloaded.loggers == config.loggers);
+            assert_eq!(reloaded.calculations, config.calculations);
+        }
+    }
+
+    #[test]
+    fn config_version_test() {
+        let newer = format!(
+            "(version: {}, devices: [], loggers: [])",
+            CONFIG_VERSION + 1
+        );
+        assert!(AppConfig::from_ron(&newer).is_err());
+        match AppConfig::from_ron("(devices: [(id: 0)], loggers: [])") {
+            Err(e) => assert!(e.to_string().starts_with("Invalid version 0 project")),
+            Ok(_) => panic!("An incomplete device was accepted"),
+        }
+        assert!(AppConfig::from_ron("devices").is_err());
+    }
+
+    #[test]
+    fn duplicate_tags_test() {
+        let mut config = AppConfig::from_ron(SAMPLES[0]).unwrap();
+        let channels = &mut config.devices[0].channels;
+        channels[0].tag = "Pump1".to_owned();
+        channels[1].tag = "Pump1".to_owned();
+        let saved = config.to_ron().unwrap();
+        match AppConfig::from_ron(&saved) {
+            Err(e) => assert!(e.to_string().contains("Pump1 (D0:CH0, D0:CH1)")),
+            Ok(_) => panic!("A duplicate tag was accepted"),
+        }
+    }
+
+    #[test]
+    fn deviation_reference_migration_test() {
+        let mut config = AppConfig::from_ron(SAMPLES[0]).unwrap();
+        let channels = &mut config.devices[0].channels;
+        channels[1].tag = "Flow".to_owned();
+        channels[0].alarm.deviation_reference = Some(1);
+        channels[1].alarm.deviation_reference = Some(2);
+        migrate_v1_to_v2(&mut config);
+
+        let channels = &config.devices[0].channels;
+        assert_eq!(
+            channels[0].alarm.deviation_source,
+            DeviationSource::Reference("Flow".to_owned())
+        );
+        assert_eq!(
+            channels[1].alarm.deviation_source,
+            DeviationSource::Reference("D0:CH2".to_owned())
+        );
+        assert!(channels
+            .iter()
+            .all(|c| c.alarm.deviation_reference.is_none()));
+    }
+
+    #[test]
+    fn bool_channels_migration_test() {
+        // The samples are version 0 projects with Bool channels.
+        let config = AppConfig::from_ron(SAMPLES[0]).unwrap();
+        assert_eq!(config.warnings.len(), 1);
+        assert!(config.warnings[0].contains("D0:CH"));
+
+        // Saved again, the project is current and loads without warnings.
+        let saved = config.to_ron().unwrap();
+        assert!(AppConfig::from_ron(&saved).unwrap().warnings.is_empty());
+    }
+}
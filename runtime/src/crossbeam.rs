@@ -1,13 +1,16 @@
 use lib_device::*;
 
-use crate::app::JsonWriteChannel;
-
 #[derive(Clone)]
 pub struct CrossBeamChannel {
     pub send: crossbeam_channel::Sender<Vec<Device>>,
     pub receive: crossbeam_channel::Receiver<Vec<Device>>,
 }
 #[derive(Clone)]
+pub struct CrossBeamReportChannel {
+    pub send: crossbeam_channel::Sender<DeviceReport>,
+    pub receive: crossbeam_channel::Receiver<DeviceReport>,
+}
+#[derive(Clone)]
 pub struct CrossBeamSocketChannel {
     pub send: crossbeam_channel::Sender<JsonWriteChannel>,
     pub receive: crossbeam_channel::Receiver<JsonWriteChannel>,
@@ -15,7 +18,7 @@ pub struct CrossBeamSocketChannel {
 
 #[derive(Clone)]
 pub struct DeviceBeam {
-    pub read: Option<CrossBeamChannel>,
+    pub read: Option<CrossBeamReportChannel>,
     pub update: Option<CrossBeamChannel>,
 }
 
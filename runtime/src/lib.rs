@@ -0,0 +1,11 @@
+#![warn(clippy::all, rust_2018_idioms)]
+
+// The acquisition shared by the GUI and the headless runtime: the device
+// workers, the project file and what is sent to the server.
+pub mod app_threads;
+pub mod config;
+pub mod crossbeam;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod runtime;
+pub mod server;
+pub mod status;
@@ -0,0 +1,8 @@
+#![warn(clippy::all, rust_2018_idioms)]
+
+use clap::Parser;
+use prussian_runtime::runtime::{run, RuntimeArgs};
+
+fn main() -> anyhow::Result<()> {
+    run(RuntimeArgs::parse())
+}
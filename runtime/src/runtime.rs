@@ -0,0 +1,327 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use clap::Parser;
+use crossbeam_channel::{unbounded, Sender};
+use lib_device::{
+    calculation_events, evaluate_calculations, BlockState, BlockType, BusManager, Calculation,
+    Device, TagDatabase, TrafficMonitor, CALCULATION_RATE,
+};
+use lib_logger::{Logger, LoggerType};
+use rhai::Engine;
+use signal_hook::consts::{SIGHUP, SIGINT, SIGTERM};
+use tungstenite::{connect, stream::MaybeTlsStream, WebSocket};
+use url::Url;
+
+use crate::{
+    app_threads::{spawn_device_threads, spawn_socket_write_msg, stop_device_threads},
+    config::AppConfig,
+    crossbeam::{DeviceBeam, DeviceMsgBeam},
+    server::{
+        send_calculations_to_server, send_events_to_server, send_to_server,
+        send_write_results_to_server, URL,
+    },
+    status::Status,
+};
+
+// How often the runtime checks for reports, signals and due loggers.
+const TICK: Duration = Duration::from_millis(100);
+// Seconds between two connection attempts to the websocket server.
+const RECONNECT_DELAY: u64 = 5;
+// Seconds between two saves of the calculation block states.
+const STATE_SAVE_RATE: u64 = 60;
+
+/// Runs the acquisition of a project file without the GUI.
+///
+/// SIGTERM and SIGINT stop it, SIGHUP reloads the project file.
+#[derive(Parser)]
+#[command(name = "prussian_runtime", version)]
+pub struct RuntimeArgs {
+    /// The project file, as saved from the GUI.
+    pub project: PathBuf,
+}
+
+// Everything the GUI would hold for a running project.
+struct Runtime {
+    devices: Vec<Device>,
+    calculations: Vec<Calculation>,
+    loggers: Vec<(Logger, Instant)>,
+    device_beam: Vec<DeviceBeam>,
+    device_msg_beam: Vec<DeviceMsgBeam>,
+    workers: Vec<JoinHandle<()>>,
+    buses: BusManager,
+    // Where the totals of the calculation blocks are kept across restarts.
+    state_path: PathBuf,
+    last_state_save: Instant,
+    last_evaluation: Instant,
+}
+
+// The block of a calculation, restored only into a block of the same type.
+type SavedBlocks = Vec<(usize, BlockType, BlockState)>;
+
+pub fn run(args: RuntimeArgs) -> anyhow::Result<()> {
+    let stop = Arc::new(AtomicBool::new(false));
+    let reload = Arc::new(AtomicBool::new(false));
+    signal_hook::flag::register(SIGTERM, Arc::clone(&stop))?;
+    signal_hook::flag::register(SIGINT, Arc::clone(&stop))?;
+    signal_hook::flag::register(SIGHUP, Arc::clone(&reload))?;
+
+    // Kept across reloads, so the serial ports stay with this process.
+    let buses = BusManager::default();
+    let state_path = args.project.with_extension("state.ron");
+    let tags = Arc::new(Mutex::new(TagDatabase::default()));
+    let mut runtime = Runtime::start(load_project(&args)?, buses.clone(), &state_path, &tags);
+    println!("Running {}", args.project.display());
+
+    let engine = Engine::new();
+    let mut status = Status::default();
+    let mut socket = None;
+    let mut last_connection_attempt: Option<Instant> = None;
+    // The HMI write thread lives as long as the runtime, it gets the beams
+    // of the new workers after a reload.
+    let (beams_s, beams_r): (Sender<Vec<DeviceMsgBeam>>, _) = unbounded();
+    spawn_socket_write_msg(runtime.device_msg_beam.to_vec(), beams_r, Arc::clone(&tags));
+
+    while !stop.load(Ordering::Relaxed) {
+        if reload.swap(false, Ordering::Relaxed) {
+            // A broken file keeps the project that's running.
+            match load_project(&args) {
+                Ok(config) => {
+                    runtime.stop();
+                    runtime = Runtime::start(config, buses.clone(), &state_path, &tags);
+                    if beams_s.send(runtime.device_msg_beam.to_vec()).is_ok() {}
+                    println!("Reloaded {}", args.project.display());
+                }
+                Err(e) => eprintln!("Reload failed, keeping the running project: {}", e),
+            }
+        }
+
+        let due = last_connection_attempt
+            .map_or(true, |last| last.elapsed().as_secs() >= RECONNECT_DELAY);
+        if socket.is_none() && due {
+            last_connection_attempt = Some(Instant::now());
+            socket = connect_to_server();
+        }
+
+        runtime.receive_reports(&engine, &mut status, &mut socket);
+        runtime.evaluate_if_due(&engine, &mut status, &mut socket);
+        if let Ok(mut tags) = tags.lock() {
+            tags.update(&runtime.devices, &runtime.calculations);
+        }
+        runtime.log();
+        if runtime.last_state_save.elapsed().as_secs() >= STATE_SAVE_RATE {
+            runtime.save_block_states();
+        }
+        thread::sleep(TICK);
+    }
+
+    runtime.stop();
+    println!("Stopped");
+    Ok(())
+}
+
+fn load_project(args: &RuntimeArgs) -> anyhow::Result<AppConfig> {
+    let config = fs::read_to_string(&args.project)
+        .map_err(|e| anyhow::anyhow!("Couldn't read {}: {}", args.project.display(), e))?;
+    let config = AppConfig::from_ron(&config)?;
+    for warning in &config.warnings {
+        eprintln!("{}", warning);
+    }
+    Ok(config)
+}
+
+// A missing file is a first start, there's nothing to restore.
+fn restore_block_states(calculations: &mut [Calculation], path: &Path) -> anyhow::Result<()> {
+    let state = match fs::read_to_string(path) {
+        Ok(state) => state,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e.into()),
+    };
+    let blocks: SavedBlocks = ron::de::from_str(&state)?;
+    for (id, block_type, state) in blocks {
+        if let Some(calculation) = calculations
+            .iter_mut()
+            .find(|calculation| calculation.id == id && calculation.block.block_type == block_type)
+        {
+            calculation.block.state = state;
+        }
+    }
+    Ok(())
+}
+
+fn connect_to_server() -> Option<WebSocket<MaybeTlsStream<std::net::TcpStream>>> {
+    match connect(Url::parse(URL).ok()?) {
+        Ok((socket, _)) => {
+            println!("Connected to {}", URL);
+            Some(socket)
+        }
+        Err(e) => {
+            eprintln!("Couldn't connect to {}: {}", URL, e);
+            None
+        }
+    }
+}
+
+impl Runtime {
+    fn start(
+        config: AppConfig,
+        buses: BusManager,
+        state_path: &Path,
+        tags: &Arc<Mutex<TagDatabase>>,
+    ) -> Self {
+        let (device_beam, device_msg_beam, workers) =
+            spawn_device_threads(&config.devices, &buses, &TrafficMonitor::default(), tags);
+        let mut calculations = config.calculations;
+        if let Err(e) = restore_block_states(&mut calculations, state_path) {
+            eprintln!("Couldn't restore the calculation states: {}", e);
+        }
+        Self {
+            devices: config.devices,
+            calculations,
+            loggers: config
+                .loggers
+                .into_iter()
+                .filter(|logger| match logger.logger_type {
+                    // Reported once here instead of on every interval.
+                    LoggerType::DataBase => {
+                        println!(
+                            "Logger {}: database loggers are written by the server, skipped",
+                            logger.name
+                        );
+                        false
+                    }
+                    LoggerType::TextFile => true,
+                })
+                .map(|logger| (logger, Instant::now()))
+                .collect(),
+            device_beam,
+            device_msg_beam,
+            workers,
+            buses,
+            state_path: state_path.to_owned(),
+            last_state_save: Instant::now(),
+            last_evaluation: Instant::now(),
+        }
+    }
+
+    // Waits for the workers, so a reload doesn't poll a device twice and the
+    // serial ports are free once this returns.
+    fn stop(&mut self) {
+        stop_device_threads(&mut self.device_beam, &mut self.device_msg_beam);
+        for worker in self.workers.drain(..) {
+            if worker.join().is_err() {
+                eprintln!("A device worker panicked");
+            }
+        }
+        self.save_block_states();
+        for bus in self.buses.stats() {
+            println!(
+                "{}: {} transactions, {} errors, {:.0}% utilisation",
+                bus.com_port,
+                bus.transactions,
+                bus.errors,
+                bus.utilisation * 100.0
+            );
+        }
+    }
+
+    // Same as the GUI does on every frame.
+    fn receive_reports(
+        &mut self,
+        engine: &Engine,
+        status: &mut Status,
+        socket: &mut Option<WebSocket<MaybeTlsStream<std::net::TcpStream>>>,
+    ) {
+        for i in 0..self.device_beam.len() {
+            let reports = match &self.device_beam[i].read {
+                Some(reports) => reports.receive.clone(),
+                None => continue,
+            };
+            for report in reports.try_iter() {
+                self.devices[i] = report.device.clone();
+                let previous = self.calculations.clone();
+                evaluate_calculations(engine, &mut self.calculations, &self.devices);
+                self.last_evaluation = Instant::now();
+                let mut events = report.events.clone();
+                events.append(&mut calculation_events(&previous, &self.calculations));
+                for event in &events {
+                    println!("{}", event.message);
+                }
+                send_to_server(&report, &self.calculations, status, socket);
+                for result in &report.writes {
+                    println!("{}", result);
+                }
+                if !report.writes.is_empty() {
+                    send_write_results_to_server(&report.writes, status, socket);
+                }
+                if !events.is_empty() {
+                    send_events_to_server(&events, status, socket);
+                }
+            }
+        }
+    }
+
+    // Without reports the calculations are evaluated on their own, so the
+    // time based blocks keep running.
+    fn evaluate_if_due(
+        &mut self,
+        engine: &Engine,
+        status: &mut Status,
+        socket: &mut Option<WebSocket<MaybeTlsStream<std::net::TcpStream>>>,
+    ) {
+        if self.calculations.is_empty() || self.last_evaluation.elapsed() < CALCULATION_RATE {
+            return;
+        }
+        let previous = self.calculations.clone();
+        evaluate_calculations(engine, &mut self.calculations, &self.devices);
+        self.last_evaluation = Instant::now();
+        send_calculations_to_server(&self.calculations, status, socket);
+        let events = calculation_events(&previous, &self.calculations);
+        for event in &events {
+            println!("{}", event.message);
+        }
+        if !events.is_empty() {
+            send_events_to_server(&events, status, socket);
+        }
+    }
+
+    // The GUI keeps them with its own state, the runtime next to the project.
+    fn save_block_states(&mut self) {
+        self.last_state_save = Instant::now();
+        let blocks: SavedBlocks = self
+            .calculations
+            .iter()
+            .filter(|calculation| calculation.block.block_type != BlockType::None)
+            .map(|calculation| {
+                let block = &calculation.block;
+                (calculation.id, block.block_type, block.state.clone())
+            })
+            .collect();
+        let result = ron::to_string(&blocks)
+            .map_err(anyhow::Error::from)
+            .and_then(|state| Ok(fs::write(&self.state_path, state)?));
+        if let Err(e) = result {
+            eprintln!("Couldn't save the calculation states: {}", e);
+        }
+    }
+
+    fn log(&mut self) {
+        for (logger, last) in self.loggers.iter_mut() {
+            if last.elapsed().as_secs() < logger.log_rate as u64 {
+                continue;
+            }
+            *last = Instant::now();
+            if let Err(e) = logger.log(&self.devices, &self.calculations) {
+                eprintln!("Logger {}: {}", logger.name, e);
+            }
+        }
+    }
+}
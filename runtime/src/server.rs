@@ -0,0 +1,123 @@
+use std::net::TcpStream;
+
+use lib_device::{Calculation, Device, DeviceReport, Event, WriteResult};
+use serde::Serialize;
+use tungstenite::{stream::MaybeTlsStream, Message, WebSocket};
+
+use crate::status::Status;
+
+pub const URL: &str = "wss://127.0.0.1:3000/websocket";
+
+#[derive(Serialize, Clone)]
+pub struct DataSerialized {
+    pub devices: Vec<Device>,
+    // False when the devices only carry the channels that changed.
+    pub integrity: bool,
+    pub calculations: Vec<Calculation>,
+}
+
+#[derive(Serialize, Clone)]
+pub struct EventsSerialized {
+    pub events: Vec<Event>,
+}
+
+#[derive(Serialize, Clone)]
+pub struct WriteResultsSerialized {
+    pub write_results: Vec<WriteResult>,
+}
+
+impl DataSerialized {
+    // Calculations are few and evaluated on every report, so they are always sent.
+    pub fn new(report: &DeviceReport, calculations: &[Calculation]) -> Self {
+        Self {
+            devices: vec![report.published_device()],
+            ..Self::calculations(calculations)
+        }
+    }
+
+    // Calculations evaluated between two reports come without any device.
+    pub fn calculations(calculations: &[Calculation]) -> Self {
+        Self {
+            devices: Vec::new(),
+            integrity: false,
+            calculations: calculations
+                .iter()
+                .filter(|calculation| calculation.enabled)
+                .cloned()
+                .collect(),
+        }
+    }
+}
+
+pub fn send_to_server(
+    report: &DeviceReport,
+    calculations: &[Calculation],
+    status: &mut Status,
+    socket: &mut Option<WebSocket<MaybeTlsStream<TcpStream>>>,
+) {
+    send_data_to_server(&DataSerialized::new(report, calculations), status, socket);
+}
+
+pub fn send_calculations_to_server(
+    calculations: &[Calculation],
+    status: &mut Status,
+    socket: &mut Option<WebSocket<MaybeTlsStream<TcpStream>>>,
+) {
+    send_data_to_server(&DataSerialized::calculations(calculations), status, socket);
+}
+
+fn send_data_to_server(
+    data_to_serialize: &DataSerialized,
+    status: &mut Status,
+    socket: &mut Option<WebSocket<MaybeTlsStream<TcpStream>>>,
+) {
+    // We send the data over the web socket to the HMI and update our status.
+    status.websocket = match send_over_socket(socket, data_to_serialize) {
+        Ok(_) => "Connected to WebSocket.".to_owned(),
+        Err(e) => {
+            *socket = None;
+            format!("ERROR: {}", e)
+        }
+    };
+}
+
+pub fn send_events_to_server(
+    events: &[Event],
+    status: &mut Status,
+    socket: &mut Option<WebSocket<MaybeTlsStream<TcpStream>>>,
+) {
+    let events_serialized = EventsSerialized {
+        events: events.to_vec(),
+    };
+    if let Err(e) = send_over_socket(socket, &events_serialized) {
+        *socket = None;
+        status.websocket = format!("ERROR: {}", e);
+    }
+}
+
+pub fn send_write_results_to_server(
+    write_results: &[WriteResult],
+    status: &mut Status,
+    socket: &mut Option<WebSocket<MaybeTlsStream<TcpStream>>>,
+) {
+    let write_results_serialized = WriteResultsSerialized {
+        write_results: write_results.to_vec(),
+    };
+    if let Err(e) = send_over_socket(socket, &write_results_serialized) {
+        *socket = None;
+        status.websocket = format!("ERROR: {}", e);
+    }
+}
+
+pub fn send_over_socket<T: Serialize>(
+    socket: &mut Option<WebSocket<MaybeTlsStream<TcpStream>>>,
+    data: &T,
+) -> anyhow::Result<()> {
+    let json = serde_json::to_string(data)?;
+    if let Some(socket) = socket {
+        socket.write_message(Message::Text(json))?;
+        Ok(())
+    } else {
+        anyhow::bail!("There is no socket connected!")
+    }
+}
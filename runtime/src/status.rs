@@ -0,0 +1,6 @@
+#[derive(Clone, Default)]
+pub struct Status {
+    pub websocket: String,
+    // The outcome of the last project load or save.
+    pub config: String,
+}
@@ -1,10 +1,10 @@
 use axum::{
     extract::ws::{Message, WebSocket, WebSocketUpgrade},
-    extract::State,
+    extract::{Query, State},
     http::StatusCode,
     response::{Html, IntoResponse},
     routing::{get, get_service},
-    Router,
+    Json, Router,
 };
 use axum_server::tls_rustls::RustlsConfig;
 use dashboard::{DashBoardData, DataPoint, DataRecords};
@@ -31,10 +31,26 @@ use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 const DB_URI: &str = "sqlite://data.db";
 // Duration in seconds between two separate logs.
 const LOG_RATE: u64 = 5;
+// The maximum number of events returned by a single query.
+const EVENT_QUERY_LIMIT: i64 = 1000;
 
-#[derive(Clone, Deserialize, Serialize)]
+#[derive(Clone, Default, Deserialize, Serialize)]
 struct DeviceData {
     devices: Vec<Device>,
+    // False when the devices only carry the channels that changed.
+    #[serde(default)]
+    integrity: bool,
+    #[serde(default)]
+    calculations: Vec<Calculation>,
+}
+#[derive(Clone, Deserialize, Serialize)]
+struct EventData {
+    events: Vec<Event>,
+}
+// Sent to a client as soon as it connects, so its trends don't start empty.
+#[derive(Clone, Serialize)]
+struct HistoryData {
+    history: Vec<ChannelSamples>,
 }
 #[derive(Clone, Deserialize)]
 struct ExportOptions;
@@ -52,6 +68,11 @@ struct AppState {
     tx: broadcast::Sender<Msg>,
     // An SQLITE connection pool that we use to execute queries on the database.
     db_pool: SqlitePool,
+    // The recent values of every channel received from the GUI.
+    history: Mutex<History>,
+    // The latest value of every channel, reports only carry the ones that
+    // changed so they're merged in here before anything is logged.
+    image: Mutex<DeviceData>,
 }
 #[tokio::main]
 async fn main() {
@@ -90,10 +111,34 @@ async fn main() {
         record_id INTEGER,
         FOREIGN KEY (record_id)
             REFERENCES Records(id)
-    );"#;
+    );
+    CREATE TABLE IF NOT EXISTS CalculationData (
+        data_id INTEGER PRIMARY KEY AUTOINCREMENT,
+        calculation_id int NOT NULL,
+        value FLOAT(14, 4) NOT NULL,
+        record_id INTEGER,
+        FOREIGN KEY (record_id)
+            REFERENCES Records(id)
+    );
+    CREATE TABLE IF NOT EXISTS Events (
+        event_id INTEGER PRIMARY KEY AUTOINCREMENT,
+        datetime INTEGER NOT NULL,
+        kind TEXT NOT NULL,
+        device_id int NOT NULL,
+        channel_id int,
+        value FLOAT(14, 4),
+        priority int,
+        message TEXT NOT NULL,
+        calculation_id int
+    );
+    CREATE INDEX IF NOT EXISTS events_datetime ON Events (datetime);"#;
 
     let result = sqlx::query(&query).execute(&db_pool).await.unwrap();
     println!("{:?}", result);
+    // Databases created before calculation events lack the column, it fails if it's already there.
+    let _ = sqlx::query("ALTER TABLE Events ADD COLUMN calculation_id int")
+        .execute(&db_pool)
+        .await;
 
     // We create the channel that we will use to transfer messages between clients.
     let (tx, _rx) = broadcast::channel(3);
@@ -103,6 +148,8 @@ async fn main() {
         client_set,
         tx,
         db_pool,
+        history: Mutex::new(History::default()),
+        image: Mutex::new(DeviceData::default()),
     });
 
     let hmi_dir = PathBuf::from(".").join("assets").join("HMI");
@@ -120,6 +167,8 @@ async fn main() {
         )
         .nest("/", get_service(hmi_service).handle_error(handle_error))
         .route("/test", get(test))
+        .route("/events", get(query_events))
+        .route("/history", get(query_history))
         .nest(
             "/logger/",
             get_service(logger_service).handle_error(handle_error),
@@ -174,6 +223,29 @@ async fn test() -> Html<&'static str> {
     Html("<h1>Hello, World!</h1>")
 }
 
+async fn query_events(
+    State(state): State<Arc<AppState>>,
+    Query(filter): Query<EventFilter>,
+) -> impl IntoResponse {
+    match fetch_events(&state.db_pool, &filter).await {
+        Ok(events) => Ok(Json(events)),
+        Err(e) => Err((StatusCode::INTERNAL_SERVER_ERROR, format!("{}", e))),
+    }
+}
+
+async fn query_history(
+    State(state): State<Arc<AppState>>,
+    Query(filter): Query<HistoryFilter>,
+) -> impl IntoResponse {
+    match state.history.lock() {
+        Ok(history) => Ok(Json(history.filter(&filter))),
+        Err(_) => Err((
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "The history is unavailable.".to_owned(),
+        )),
+    }
+}
+
 async fn ws_handler(ws: WebSocketUpgrade, State(state): State<Arc<AppState>>) -> impl IntoResponse {
     let id = Uuid::new_v4().to_string();
 
@@ -207,10 +279,24 @@ async fn websocket(stream: WebSocket, id: String, state: Arc<AppState>) {
 
     let mut rx = state.tx.subscribe();
 
+    // Late joiners get what we have in memory before the live values.
+    let backfill = match state.history.lock() {
+        Ok(history) => serde_json::to_string(&HistoryData {
+            history: history.backfill(i64::MIN, i64::MAX),
+        })
+        .ok(),
+        Err(_) => None,
+    };
+
     // We spawn a task that receives any broadcasted messages and send them to our client.
 
     let client_id = id.clone();
     let mut send_task = tokio::spawn(async move {
+        if let Some(backfill) = backfill {
+            if sender.send(Message::Text(backfill)).await.is_err() {
+                return;
+            }
+        }
         while let Ok(msg) = rx.recv().await {
             // We check if the msg is coming from this same client. We don't want to send the msg to ourselves.
             if msg.client_id != client_id {
@@ -225,10 +311,13 @@ async fn websocket(stream: WebSocket, id: String, state: Arc<AppState>) {
     let tx = state.tx.clone();
     let client_id = id.clone();
     let db_pool_cloned = state.db_pool.clone();
+    let state_cloned = Arc::clone(&state);
 
     let mut receive_task = tokio::spawn(async move {
         // We use a timer
         let mut time = Instant::now();
+        // Set once the client sends device data, only the GUI or the runtime does.
+        let mut publisher = false;
         while let Some(Ok(payload)) = receiver.next().await {
             match payload {
                 Message::Binary(payload) => {
@@ -245,10 +334,30 @@ async fn websocket(stream: WebSocket, id: String, state: Arc<AppState>) {
                         client_id: client_id.clone(),
                         payload: text,
                     };
+                    // Events are journaled as soon as they arrive, but only from the
+                    // publisher and for what it published.
+                    if let Ok(event_data) = serde_json::from_str(&msg.payload) {
+                        if !publisher {
+                            println!("{} => events rejected, it publishes no devices", &client_id);
+                            continue;
+                        }
+                        let events_to_log = known_events(&state_cloned, event_data);
+                        log_events(&db_pool_cloned, &events_to_log).await;
+                    }
                     // We log the data to the database.
                     if let Ok(devices_data) = serde_json::from_str(&msg.payload) {
-                        if time.elapsed().as_secs() >= LOG_RATE {
-                            let devices_to_log: DeviceData = devices_data;
+                        publisher = true;
+                        record_history(&state_cloned, &devices_data);
+                        let due = time.elapsed().as_secs() >= LOG_RATE;
+                        // Copied out as the lock can't be held across the queries.
+                        let image = match state_cloned.image.lock() {
+                            Ok(mut image) => {
+                                merge_image(&mut image, &devices_data);
+                                due.then(|| image.clone())
+                            }
+                            Err(_) => None,
+                        };
+                        if let Some(devices_to_log) = image {
                             log_data(&db_pool_cloned, &devices_to_log).await;
                             // And we reset the timer.
                             time = Instant::now();
@@ -297,6 +406,67 @@ async fn websocket(stream: WebSocket, id: String, state: Arc<AppState>) {
     }
 }
 
+fn record_history(state: &AppState, data: &DeviceData) {
+    if let Ok(mut history) = state.history.lock() {
+        let timestamp = now_millis();
+        for device in &data.devices {
+            history.record(device, timestamp);
+        }
+    }
+}
+
+// Integrity reports replace the device, the others only the channels they carry.
+fn merge_image(image: &mut DeviceData, data: &DeviceData) {
+    for device in &data.devices {
+        match image.devices.iter_mut().find(|known| known.id == device.id) {
+            Some(known) if !data.integrity => {
+                let mut channels = std::mem::take(&mut known.channels);
+                for channel in &device.channels {
+                    match channels.iter_mut().find(|known| known.id == channel.id) {
+                        Some(known) => *known = channel.clone(),
+                        None => channels.push(channel.clone()),
+                    }
+                }
+                channels.sort_by_key(|channel| channel.id);
+                *known = Device {
+                    channels,
+                    ..device.clone()
+                };
+            }
+            Some(known) => *known = device.clone(),
+            None => image.devices.push(device.clone()),
+        }
+    }
+    image.devices.sort_by_key(|device| device.id);
+    // The calculations are always sent whole.
+    image.calculations = data.calculations.clone();
+}
+
+// Drops the events of devices, channels and calculations missing from the image.
+fn known_events(state: &AppState, data: EventData) -> EventData {
+    let image = match state.image.lock() {
+        Ok(image) => image,
+        Err(_) => return EventData { events: Vec::new() },
+    };
+    let events = data
+        .events
+        .into_iter()
+        .filter(|event| match event.calculation {
+            Some(id) => image.calculations.iter().any(|known| known.id == id),
+            None => image
+                .devices
+                .iter()
+                .find(|device| device.id == event.device_id)
+                .map_or(false, |device| {
+                    event.channel.map_or(true, |channel| {
+                        device.channels.iter().any(|known| known.id == channel)
+                    })
+                }),
+        })
+        .collect();
+    EventData { events }
+}
+
 fn is_registered(state: &AppState, id: &str) -> bool {
     let mut client_ids = state.client_set.lock().unwrap();
 
@@ -334,6 +504,82 @@ async fn log_data(db_pool: &SqlitePool, data: &DeviceData) {
             //println!("{:?}", &result);
         }
     }
+    let calculation_query =
+        "INSERT INTO calculationdata (data_id, calculation_id, value, record_id)
+                                    VALUES(NULL, $1, $2, $3)";
+    for calculation in &data.calculations {
+        let result = sqlx::query(&calculation_query)
+            .bind(calculation.id as i32)
+            .bind(calculation.value)
+            .bind(row_id)
+            .execute(db_pool)
+            .await;
+        if let Err(e) = result {
+            println!("Couldn't log calculation: {}", e);
+        }
+    }
+}
+
+async fn log_events(db_pool: &SqlitePool, data: &EventData) {
+    let event_query = "INSERT INTO events (event_id, datetime, kind, device_id, channel_id, value, priority, message, calculation_id)
+                                    VALUES(NULL, $1, $2, $3, $4, $5, $6, $7, $8)";
+    for event in &data.events {
+        let result = sqlx::query(&event_query)
+            .bind(event.timestamp)
+            .bind(event.kind.to_string())
+            .bind(event.device_id as i32)
+            .bind(event.channel.map(|channel| channel as i32))
+            .bind(event.value)
+            .bind(event.priority.map(|priority| priority.level()))
+            .bind(&event.message)
+            .bind(event.calculation.map(|calculation| calculation as i32))
+            .execute(db_pool)
+            .await;
+        if let Err(e) = result {
+            println!("Couldn't log event: {}", e);
+        }
+    }
+}
+
+async fn fetch_events(db_pool: &SqlitePool, filter: &EventFilter) -> anyhow::Result<Vec<Event>> {
+    let event_query =
+        "SELECT datetime, kind, device_id, channel_id, value, priority, message, calculation_id FROM events
+                                    WHERE datetime >= $1 AND datetime <= $2
+                                    AND ($3 IS NULL OR (device_id = $3 AND calculation_id IS NULL))
+                                    AND ($4 IS NULL OR priority >= $4)
+                                    ORDER BY datetime DESC LIMIT $5";
+    let rows: Vec<(
+        i64,
+        String,
+        i64,
+        Option<i64>,
+        Option<f32>,
+        Option<i64>,
+        String,
+        Option<i64>,
+    )> = sqlx::query_as(&event_query)
+        .bind(filter.from.unwrap_or(i64::MIN))
+        .bind(filter.to.unwrap_or(i64::MAX))
+        .bind(filter.device_id.map(|device_id| device_id as i64))
+        .bind(filter.min_priority.map(|priority| priority.level()))
+        .bind(EVENT_QUERY_LIMIT)
+        .fetch_all(db_pool)
+        .await?;
+
+    let mut events = Vec::new();
+    for (timestamp, kind, device_id, channel, value, priority, message, calculation) in rows {
+        events.push(Event {
+            timestamp,
+            kind: kind.parse()?,
+            device_id: device_id as usize,
+            channel: channel.map(|channel| channel as usize),
+            calculation: calculation.map(|calculation| calculation as usize),
+            value,
+            priority: priority.and_then(AlarmPriority::from_level),
+            message,
+        });
+    }
+    Ok(events)
 }
 
 fn device_to_dashboard(data: &DeviceData) -> DashBoardData {